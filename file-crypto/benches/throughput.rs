@@ -0,0 +1,47 @@
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use file_crypto as lib;
+
+const DATA_SIZE: usize = 16 * 1024 * 1024;
+
+fn bench_throughput(c: &mut Criterion) {
+    let cipher = lib::new_aes128gcm_cipher("bench");
+    let mut rng = rand::thread_rng();
+
+    let mut raw_bytes = vec![0u8; DATA_SIZE];
+    for b in raw_bytes.iter_mut() {
+        *b = rand::random();
+    }
+    let mut encrypted = Vec::with_capacity(DATA_SIZE * 2);
+    lib::encrypt(
+        &mut Cursor::new(&raw_bytes),
+        &mut encrypted,
+        &cipher,
+        &mut rng,
+    )
+    .unwrap();
+
+    let mut group = c.benchmark_group("throughput");
+    group.throughput(Throughput::Bytes(DATA_SIZE as u64));
+    group.sample_size(20);
+    group.bench_function("encrypt", |b| {
+        let mut output = Vec::with_capacity(encrypted.len());
+        b.iter(|| {
+            output.clear();
+            lib::encrypt(&mut Cursor::new(&raw_bytes), &mut output, &cipher, &mut rng).unwrap();
+        })
+    });
+    group.bench_function("decrypt", |b| {
+        let mut output = Vec::with_capacity(DATA_SIZE);
+        b.iter(|| {
+            output.clear();
+            lib::decrypt(&mut Cursor::new(&encrypted), &mut output, &cipher).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_throughput);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+#ifndef FILE_CRYPTO_H
+#define FILE_CRYPTO_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define FC_OK 0
+
+#define FC_INVALID_ARGUMENT 1
+
+#define FC_IO_ERROR 2
+
+#define FC_CRYPTO_ERROR 3
+
+// A bug in the library; the details are in `fc_last_error`.
+#define FC_INTERNAL_ERROR 4
+
+typedef struct FcContext FcContext;
+
+// The message of the last error on the calling thread, or null. Valid until the next call on
+// the same thread.
+const char *fc_last_error(void);
+
+// Creates a context holding the cipher derived from `key`; AES-256-GCM if `use_aes256gcm` is
+// not 0, AES-128-GCM otherwise. Returns null on error.
+//
+// # Safety
+// `key` must be a valid C string.
+FcContext *fc_context_new(const char *key, int use_aes256gcm);
+
+// # Safety
+// `context` must be null or returned by `fc_context_new` and not freed yet.
+void fc_context_free(FcContext *context);
+
+// Encrypts the file at `in_path` into `out_path`, which is created or replaced once done; on
+// errors it's left as it was. Fails with `FC_INVALID_ARGUMENT` if both are the same file.
+//
+// # Safety
+// `context` must be returned by `fc_context_new`; the paths must be valid C strings.
+int fc_encrypt_file(const FcContext *context, const char *in_path, const char *out_path);
+
+// Decrypts the file at `in_path` into `out_path`, which is created or replaced once done; on
+// errors it's left as it was. Fails with `FC_INVALID_ARGUMENT` if both are the same file.
+//
+// # Safety
+// `context` must be returned by `fc_context_new`; the paths must be valid C strings.
+int fc_decrypt_file(const FcContext *context, const char *in_path, const char *out_path);
+
+// Encrypts `len` bytes at `data`. On success `*out` and `*out_len` receive the result, which
+// must be freed with `fc_buffer_free`.
+//
+// # Safety
+// `context` must be returned by `fc_context_new`; `data` must point to `len` readable bytes
+// (or be null if `len` is 0); `out` and `out_len` must be writable.
+int fc_encrypt_buffer(const FcContext *context,
+                      const uint8_t *data,
+                      size_t len,
+                      uint8_t **out,
+                      size_t *out_len);
+
+// Decrypts `len` bytes at `data`. On success `*out` and `*out_len` receive the result, which
+// must be freed with `fc_buffer_free`.
+//
+// # Safety
+// `context` must be returned by `fc_context_new`; `data` must point to `len` readable bytes
+// (or be null if `len` is 0); `out` and `out_len` must be writable.
+int fc_decrypt_buffer(const FcContext *context,
+                      const uint8_t *data,
+                      size_t len,
+                      uint8_t **out,
+                      size_t *out_len);
+
+// Wipes and frees a buffer returned by `fc_encrypt_buffer` or `fc_decrypt_buffer`.
+//
+// # Safety
+// `buf` must be null or returned along with `len` by one of the functions above, and not freed
+// yet.
+void fc_buffer_free(uint8_t *buf, size_t len);
+
+#endif  /* FILE_CRYPTO_H */
@@ -0,0 +1,708 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use aes_gcm::AeadInPlace;
+
+use crate::{decrypt, encrypt, NonceSource};
+
+// An archive is a plaintext stream of entries, encrypted as a whole like any other file. Every
+// entry begins with a header: the kind (u8), the length of the path (u16 LE), the path (UTF-8,
+// relative, separated by '/'), the permission bits (u32 LE), the modification time in seconds
+// since the Unix epoch (u64 LE) and the size of the content (u64 LE), which follows the header.
+// The archive ends with an end entry, which is only the kind, so a truncated archive is told.
+const MAGIC: &[u8] = b"FCAR\x01";
+const KIND_END: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_DIR: u8 = 2;
+const HEADER_FIXED_SIZE: usize = 1 + 2 + 4 + 8 + 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub is_dir: bool,
+    pub mode: u32,
+    pub mtime: u64,
+    pub size: u64,
+}
+impl ArchiveEntry {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_FIXED_SIZE + self.path.len());
+        bytes.push(if self.is_dir { KIND_DIR } else { KIND_FILE });
+        bytes.extend_from_slice(&(self.path.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(self.path.as_bytes());
+        bytes.extend_from_slice(&self.mode.to_le_bytes());
+        bytes.extend_from_slice(&self.mtime.to_le_bytes());
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+
+    // returns `None` if more bytes are needed, the entry and the length of its header otherwise
+    fn decode(bytes: &[u8]) -> io::Result<Option<(Self, usize)>> {
+        if bytes.len() < 3 {
+            return Ok(None);
+        }
+        let kind = bytes[0];
+        if kind != KIND_FILE && kind != KIND_DIR {
+            return Err(invalid_data(format!("unknown archive entry kind {kind}")));
+        }
+        let path_len = u16::from_le_bytes([bytes[1], bytes[2]]) as usize;
+        let len = HEADER_FIXED_SIZE + path_len;
+        if bytes.len() < len {
+            return Ok(None);
+        }
+        let path = match std::str::from_utf8(&bytes[3..3 + path_len]) {
+            Ok(p) => p.to_string(),
+            Err(_) => {
+                return Err(invalid_data("archive entry path is not UTF-8".to_string()));
+            }
+        };
+        let rest = &bytes[3 + path_len..len];
+        let entry = Self {
+            path,
+            is_dir: kind == KIND_DIR,
+            mode: u32::from_le_bytes(rest[..4].try_into().unwrap()),
+            mtime: u64::from_le_bytes(rest[4..12].try_into().unwrap()),
+            size: u64::from_le_bytes(rest[12..20].try_into().unwrap()),
+        };
+        if entry.is_dir && entry.size != 0 {
+            return Err(invalid_data(format!(
+                "directory '{}' has content",
+                entry.path
+            )));
+        }
+        Ok(Some((entry, len)))
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Packs files and directories (recursively) into a single encrypted archive. Files are stored
+/// under their file names and directories with their contents under the directory name, like
+/// `tar` does. Returns the entries.
+pub fn pack_archive<P, W, C, N>(
+    paths: &[P],
+    writer: &mut W,
+    cipher: &C,
+    nonce_source: &mut N,
+) -> Result<Vec<ArchiveEntry>, Vec<String>>
+where
+    P: AsRef<Path>,
+    W: Write,
+    C: AeadInPlace,
+    N: NonceSource,
+{
+    let mut queue = VecDeque::new();
+    for path in paths {
+        let path = path.as_ref();
+        let name = match path.file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => {
+                let msg = format!("cannot determine the file name of '{}'", path.display());
+                return Err(vec![msg]);
+            }
+        };
+        collect_entries(path, name, &mut queue).map_err(|msg| vec![msg])?;
+    }
+    let entries = queue.iter().map(|(_, entry)| entry.clone()).collect();
+    let mut packer = Packer {
+        queue,
+        current: None,
+        remaining: 0,
+        pending: MAGIC.to_vec(),
+        pos: 0,
+        done: false,
+    };
+    encrypt(&mut packer, writer, cipher, nonce_source)?;
+    Ok(entries)
+}
+
+fn collect_entries(
+    path: &Path,
+    name: String,
+    queue: &mut VecDeque<(PathBuf, ArchiveEntry)>,
+) -> Result<(), String> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) => {
+            return Err(format!("cannot read '{}'; {e}", path.display()));
+        }
+    };
+    if !metadata.is_file() && !metadata.is_dir() {
+        eprintln!(
+            "warning: '{}' is not a file or directory; skipped",
+            path.display()
+        );
+        return Ok(());
+    }
+    if name.len() > u16::MAX as usize {
+        return Err(format!("path '{name}' is too long"));
+    }
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let entry = ArchiveEntry {
+        path: name.clone(),
+        is_dir: metadata.is_dir(),
+        mode: mode_of(&metadata),
+        mtime,
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+    };
+    queue.push_back((path.to_path_buf(), entry));
+    if !metadata.is_dir() {
+        return Ok(());
+    }
+
+    let read_dir = match fs::read_dir(path) {
+        Ok(r) => r,
+        Err(e) => {
+            return Err(format!("cannot read directory '{}'; {e}", path.display()));
+        }
+    };
+    let mut children = vec![];
+    for child in read_dir {
+        match child {
+            Ok(c) => children.push(c.file_name()),
+            Err(e) => {
+                return Err(format!("cannot read directory '{}'; {e}", path.display()));
+            }
+        }
+    }
+    children.sort();
+    for child in children {
+        let child_name = format!("{name}/{}", child.to_string_lossy());
+        collect_entries(&path.join(child), child_name, queue)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+// Produces the plaintext of an archive, reading the files one after another.
+struct Packer {
+    queue: VecDeque<(PathBuf, ArchiveEntry)>,
+    current: Option<(File, PathBuf)>,
+    remaining: u64,
+    pending: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+impl Read for Packer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.pending.len() {
+                let len = buf.len().min(self.pending.len() - self.pos);
+                buf[..len].copy_from_slice(&self.pending[self.pos..self.pos + len]);
+                self.pos += len;
+                return Ok(len);
+            }
+            if let Some((file, path)) = self.current.as_mut() {
+                if self.remaining > 0 {
+                    let len = self.remaining.min(buf.len() as u64) as usize;
+                    let n = file.read(&mut buf[..len])?;
+                    if n == 0 {
+                        let msg = format!("'{}' shrank while being archived", path.display());
+                        return Err(io::Error::new(ErrorKind::UnexpectedEof, msg));
+                    }
+                    self.remaining -= n as u64;
+                    return Ok(n);
+                }
+                self.current = None;
+            }
+            if self.done {
+                return Ok(0);
+            }
+
+            self.pending.clear();
+            self.pos = 0;
+            match self.queue.pop_front() {
+                None => {
+                    self.pending.push(KIND_END);
+                    self.done = true;
+                }
+                Some((path, entry)) => {
+                    self.pending = entry.encode();
+                    if !entry.is_dir && entry.size > 0 {
+                        let file = match File::open(&path) {
+                            Ok(f) => f,
+                            Err(e) => {
+                                let msg = format!("cannot open '{}'; {e}", path.display());
+                                return Err(io::Error::new(e.kind(), msg));
+                            }
+                        };
+                        self.current = Some((file, path));
+                        self.remaining = entry.size;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lists the entries of an archive.
+pub fn list_archive<R, C>(reader: &mut R, cipher: &C) -> Result<Vec<ArchiveEntry>, Vec<String>>
+where
+    R: Read,
+    C: AeadInPlace,
+{
+    let mut unpacker = Unpacker::new(None, &[]);
+    decrypt(reader, &mut unpacker, cipher)?;
+    unpacker.finish().map_err(|msg| vec![msg])
+}
+
+/// Extracts an archive into `dest`. If `only` isn't empty, just the entries at (or under) the
+/// given paths are extracted. Existing files are only replaced if `overwrite` is set. Returns the
+/// extracted entries.
+pub fn extract_archive<R, C>(
+    reader: &mut R,
+    cipher: &C,
+    dest: &Path,
+    only: &[String],
+    overwrite: bool,
+) -> Result<Vec<ArchiveEntry>, Vec<String>>
+where
+    R: Read,
+    C: AeadInPlace,
+{
+    let only: Vec<String> = only
+        .iter()
+        .map(|p| p.trim_matches('/').replace('\\', "/"))
+        .collect();
+    let target = Target {
+        dest: dest.to_path_buf(),
+        overwrite,
+    };
+    let mut unpacker = Unpacker::new(Some(target), &only);
+    let result = decrypt(reader, &mut unpacker, cipher);
+    let entries = match (result, unpacker.finish()) {
+        (Ok(_), Ok(entries)) => entries,
+        (Err(msgs), _) => {
+            return Err(msgs);
+        }
+        (_, Err(msg)) => {
+            return Err(vec![msg]);
+        }
+    };
+    for path in &only {
+        if !entries
+            .iter()
+            .any(|e| is_selected(&e.path, std::slice::from_ref(path)))
+        {
+            return Err(vec![format!("'{path}' not found in the archive")]);
+        }
+    }
+    Ok(entries)
+}
+
+fn is_selected(path: &str, only: &[String]) -> bool {
+    only.is_empty()
+        || only.iter().any(|p| {
+            path == p
+                || (path.len() > p.len()
+                    && path.starts_with(p.as_str())
+                    && path.as_bytes()[p.len()] == b'/')
+        })
+}
+
+struct Target {
+    dest: PathBuf,
+    overwrite: bool,
+}
+
+enum State {
+    Magic,
+    Header,
+    Content(u64),
+    End,
+}
+
+// Takes the decrypted plaintext of an archive and extracts (or just lists) the entries on the way.
+struct Unpacker<'a> {
+    target: Option<Target>,
+    only: &'a [String],
+    state: State,
+    header: Vec<u8>,
+    entry: Option<ArchiveEntry>,
+    file: Option<File>,
+    entries: Vec<ArchiveEntry>,
+    dirs: Vec<(PathBuf, ArchiveEntry)>,
+}
+impl<'a> Unpacker<'a> {
+    fn new(target: Option<Target>, only: &'a [String]) -> Self {
+        Self {
+            target,
+            only,
+            state: State::Magic,
+            header: vec![],
+            entry: None,
+            file: None,
+            entries: vec![],
+            dirs: vec![],
+        }
+    }
+
+    fn begin_entry(&mut self, entry: ArchiveEntry) -> io::Result<()> {
+        let selected = is_selected(&entry.path, self.only);
+        if selected {
+            if let Some(target) = self.target.as_ref() {
+                let relative = checked_path(&entry.path)?;
+                let path = target.dest.join(&relative);
+                if entry.is_dir {
+                    create_dirs_under(&target.dest, &relative)?;
+                    self.dirs.push((path, entry.clone()));
+                } else {
+                    if let Some(parent) = relative.parent() {
+                        create_dirs_under(&target.dest, parent)?;
+                    }
+                    if let Ok(metadata) = fs::symlink_metadata(&path) {
+                        if !target.overwrite {
+                            let msg = format!("'{}' exists", path.display());
+                            return Err(io::Error::new(ErrorKind::AlreadyExists, msg));
+                        }
+                        if metadata.is_symlink() {
+                            fs::remove_file(&path)?; // don't write through it
+                        }
+                    }
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .truncate(true)
+                        .open(&path)?;
+                    self.file = Some(file);
+                }
+            }
+        }
+        self.state = State::Content(entry.size);
+        self.entry = selected.then_some(entry);
+        if matches!(self.state, State::Content(0)) {
+            self.end_entry()?;
+        }
+        Ok(())
+    }
+
+    fn end_entry(&mut self) -> io::Result<()> {
+        self.state = State::Header;
+        let entry = match self.entry.take() {
+            Some(e) => e,
+            None => return Ok(()),
+        };
+        if let Some(file) = self.file.take() {
+            let mtime = UNIX_EPOCH + Duration::from_secs(entry.mtime);
+            file.set_modified(mtime)?;
+            set_mode(&file, entry.mode)?;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<ArchiveEntry>, String> {
+        if !matches!(self.state, State::End) {
+            return Err("the archive is truncated".to_string());
+        }
+        // directories last, as their modes may keep their contents from being written
+        for (path, entry) in self.dirs.iter().rev() {
+            if let Ok(dir) = File::open(path) {
+                let _ = set_mode(&dir, entry.mode);
+            }
+        }
+        self.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(self.entries)
+    }
+}
+impl Write for Unpacker<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            match self.state {
+                State::Magic => {
+                    let len = rest.len().min(MAGIC.len() - self.header.len());
+                    self.header.extend_from_slice(&rest[..len]);
+                    rest = &rest[len..];
+                    if self.header.len() == MAGIC.len() {
+                        if self.header != MAGIC {
+                            return Err(invalid_data("not an archive".to_string()));
+                        }
+                        self.header.clear();
+                        self.state = State::Header;
+                    }
+                }
+                State::Header => {
+                    if self.header.is_empty() && rest[0] == KIND_END {
+                        rest = &rest[1..];
+                        self.state = State::End;
+                        continue;
+                    }
+                    // take what may belong to the header; the rest is handed back below
+                    let old_len = self.header.len();
+                    let len = rest.len().min(HEADER_FIXED_SIZE + u16::MAX as usize);
+                    self.header.extend_from_slice(&rest[..len]);
+                    match ArchiveEntry::decode(&self.header)? {
+                        None => {
+                            rest = &rest[len..];
+                        }
+                        Some((entry, header_len)) => {
+                            rest = &rest[header_len - old_len..];
+                            self.header.clear();
+                            self.begin_entry(entry)?;
+                        }
+                    }
+                }
+                State::Content(remaining) => {
+                    let len = remaining.min(rest.len() as u64) as usize;
+                    if let Some(file) = self.file.as_mut() {
+                        file.write_all(&rest[..len])?;
+                    }
+                    rest = &rest[len..];
+                    self.state = State::Content(remaining - len as u64);
+                    if remaining == len as u64 {
+                        self.end_entry()?;
+                    }
+                }
+                State::End => {
+                    return Err(invalid_data(
+                        "unexpected data after the end of the archive".to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Entry paths must stay within the destination.
+fn checked_path(path: &str) -> io::Result<PathBuf> {
+    let mut checked = PathBuf::new();
+    for part in path.split('/') {
+        let component = Path::new(part).components().next();
+        match component {
+            Some(Component::Normal(c)) if Path::new(part).components().count() == 1 => {
+                checked.push(c);
+            }
+            _ => {
+                let msg = format!("illegal path '{path}' in the archive");
+                return Err(invalid_data(msg));
+            }
+        }
+    }
+    Ok(checked)
+}
+
+// one directory after another, refusing to go through a symlink already under `dest`: with
+// `sub` linking to /etc, `sub/x` would be written to /etc/x
+fn create_dirs_under(dest: &Path, relative: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    let mut path = dest.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_symlink() => {
+                let msg = format!("'{}' is a symbolic link", path.display());
+                return Err(invalid_data(msg));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => fs::create_dir(&path)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // no setuid, setgid or sticky bits from an archive
+    file.set_permissions(fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(file: &File, mode: u32) -> io::Result<()> {
+    let mut permissions = file.metadata()?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    file.set_permissions(permissions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use crate::{encrypt, extract_archive, list_archive, new_aes128gcm_cipher, pack_archive};
+
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file-crypto-test-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_archive() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let scratch = scratch_dir();
+        let src = scratch.join("src");
+        fs::create_dir_all(src.join("sub/empty")).unwrap();
+        let big: Vec<u8> = (0..100_000).map(|_| rand::random()).collect();
+        fs::write(src.join("big.bin"), &big).unwrap();
+        fs::write(src.join("sub/note.txt"), b"hello").unwrap();
+        fs::write(src.join("sub/zero"), b"").unwrap();
+        fs::write(scratch.join("single.txt"), b"single").unwrap();
+
+        let mut archive = vec![];
+        let paths = [src.clone(), scratch.join("single.txt")];
+        let packed = pack_archive(&paths, &mut archive, &cipher, &mut rng).unwrap();
+        assert_eq!(packed.len(), 7);
+
+        let mut listed = list_archive(&mut Cursor::new(&archive), &cipher).unwrap();
+        listed.sort_by(|a, b| a.path.cmp(&b.path));
+        let names: Vec<_> = listed.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "single.txt",
+                "src",
+                "src/big.bin",
+                "src/sub",
+                "src/sub/empty",
+                "src/sub/note.txt",
+                "src/sub/zero"
+            ]
+        );
+        assert_eq!(listed[2].size, 100_000);
+
+        let out = scratch.join("out");
+        extract_archive(&mut Cursor::new(&archive), &cipher, &out, &[], false).unwrap();
+        assert_eq!(fs::read(out.join("src/big.bin")).unwrap(), big);
+        assert_eq!(fs::read(out.join("src/sub/note.txt")).unwrap(), b"hello");
+        assert!(fs::read(out.join("src/sub/zero")).unwrap().is_empty());
+        assert!(out.join("src/sub/empty").is_dir());
+        assert_eq!(fs::read(out.join("single.txt")).unwrap(), b"single");
+
+        // existing files are kept unless overwriting
+        let mut cursor = Cursor::new(&archive);
+        assert!(extract_archive(&mut cursor, &cipher, &out, &[], false).is_err());
+        let mut cursor = Cursor::new(&archive);
+        extract_archive(&mut cursor, &cipher, &out, &[], true).unwrap();
+
+        let only = scratch.join("only");
+        let selection = ["src/sub/".to_string()];
+        let mut cursor = Cursor::new(&archive);
+        let extracted = extract_archive(&mut cursor, &cipher, &only, &selection, false).unwrap();
+        assert_eq!(extracted.len(), 4);
+        assert!(only.join("src/sub/note.txt").is_file());
+        assert!(!only.join("src/big.bin").exists());
+        let missing = ["src/su".to_string()];
+        let mut cursor = Cursor::new(&archive);
+        assert!(extract_archive(&mut cursor, &cipher, &only, &missing, true).is_err());
+
+        // truncated at a chunk boundary, which the records alone don't tell
+        let truncated = &archive[..4128 * 10];
+        assert!(list_archive(&mut Cursor::new(truncated), &cipher).is_err());
+
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn test_archive_path_traversal() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let scratch = scratch_dir();
+
+        let path = b"../escaped";
+        let mut plaintext = b"FCAR\x01\x01".to_vec();
+        plaintext.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        plaintext.extend_from_slice(path);
+        plaintext.extend_from_slice(&0o644u32.to_le_bytes());
+        plaintext.extend_from_slice(&0u64.to_le_bytes());
+        plaintext.extend_from_slice(&1u64.to_le_bytes());
+        plaintext.extend_from_slice(b"x\x00");
+        let mut archive = vec![];
+        encrypt(&mut Cursor::new(plaintext), &mut archive, &cipher, &mut rng).unwrap();
+
+        let out = scratch.join("out");
+        let result = extract_archive(&mut Cursor::new(&archive), &cipher, &out, &[], false);
+        assert!(result.is_err());
+        assert!(!scratch.join("escaped").exists());
+
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_archive_special_mode_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let scratch = scratch_dir();
+
+        let path = b"suid";
+        let mut plaintext = b"FCAR\x01\x01".to_vec();
+        plaintext.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        plaintext.extend_from_slice(path);
+        plaintext.extend_from_slice(&0o6755u32.to_le_bytes());
+        plaintext.extend_from_slice(&0u64.to_le_bytes());
+        plaintext.extend_from_slice(&1u64.to_le_bytes());
+        plaintext.extend_from_slice(b"x\x00");
+        let mut archive = vec![];
+        encrypt(&mut Cursor::new(plaintext), &mut archive, &cipher, &mut rng).unwrap();
+
+        let out = scratch.join("out");
+        extract_archive(&mut Cursor::new(&archive), &cipher, &out, &[], false).unwrap();
+        let mode = fs::metadata(out.join("suid")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o755);
+
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_archive_symlinked_dir() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let scratch = scratch_dir();
+        let src = scratch.join("src");
+        fs::create_dir_all(src.join("sub/deeper")).unwrap();
+        fs::write(src.join("sub/x"), b"x").unwrap();
+        let mut archive = vec![];
+        pack_archive(&[src], &mut archive, &cipher, &mut rng).unwrap();
+
+        // a directory of the archive is a symlink to elsewhere in the destination
+        let elsewhere = scratch.join("elsewhere");
+        fs::create_dir(&elsewhere).unwrap();
+        let out = scratch.join("out");
+        fs::create_dir_all(out.join("src")).unwrap();
+        std::os::unix::fs::symlink(&elsewhere, out.join("src/sub")).unwrap();
+        for overwrite in [false, true] {
+            let mut cursor = Cursor::new(&archive);
+            let result = extract_archive(&mut cursor, &cipher, &out, &[], overwrite);
+            assert!(result.is_err());
+            assert!(fs::read_dir(&elsewhere).unwrap().next().is_none());
+        }
+
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+use std::fs::{File, OpenOptions};
+use std::io::stdin;
+use std::ops::Sub;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Instant;
+
+use aes_gcm::AeadInPlace;
+use clap::{Args, Parser, Subcommand};
+
+use file_crypto as lib;
+
+#[derive(Parser)]
+struct Arg {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Pack files and directories into an encrypted archive")]
+    Create {
+        #[command(flatten)]
+        key: KeyArg,
+
+        #[arg(
+            short,
+            long = "force",
+            help = "Overwrite existed archive without confirmation"
+        )]
+        overwrite: bool,
+
+        #[arg(help = "The archive to create")]
+        archive: String,
+
+        #[arg(required = true, help = "File(s) and directories to pack")]
+        paths: Vec<String>,
+    },
+
+    #[command(about = "List the entries of an encrypted archive")]
+    List {
+        #[command(flatten)]
+        key: KeyArg,
+
+        #[arg(help = "The archive to list")]
+        archive: String,
+    },
+
+    #[command(about = "Extract an encrypted archive")]
+    Extract {
+        #[command(flatten)]
+        key: KeyArg,
+
+        #[arg(
+            short,
+            long = "force",
+            help = "Overwrite existed files without confirmation"
+        )]
+        overwrite: bool,
+
+        #[arg(
+            short = 'C',
+            long,
+            default_value = ".",
+            help = "The directory to extract into"
+        )]
+        directory: String,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Only extract the entry at PATH (or under it, for directories); repeatable"
+        )]
+        only: Vec<String>,
+
+        #[arg(help = "The archive to extract")]
+        archive: String,
+    },
+}
+
+#[derive(Args)]
+struct KeyArg {
+    #[arg(short, long, help = "The secret key", value_parser = parse_key)]
+    key: lib::SecretString,
+
+    #[arg(long = "256", help = "Use AES-256-GCM instead of AES-128-GCM")]
+    use_aes256gcm: bool,
+}
+
+fn main() {
+    let arg = Arg::parse();
+    let key = match &arg.command {
+        Command::Create { key, .. } | Command::List { key, .. } | Command::Extract { key, .. } => {
+            key
+        }
+    };
+    if key.key.is_empty() {
+        eprintln!("error: empty secret key");
+        exit(1);
+    }
+
+    let ok = if key.use_aes256gcm {
+        let cipher = lib::SecretBox::new(lib::new_aes256gcm_cipher(&key.key));
+        run(&arg.command, &*cipher)
+    } else {
+        let cipher = lib::SecretBox::new(lib::new_aes128gcm_cipher(&key.key));
+        run(&arg.command, &*cipher)
+    };
+    if !ok {
+        exit(1);
+    }
+}
+
+fn parse_key(key: &str) -> Result<lib::SecretString, String> {
+    Ok(lib::SecretString::new(key.to_string()))
+}
+
+fn run<C: AeadInPlace>(command: &Command, cipher: &C) -> bool {
+    match command {
+        Command::Create {
+            overwrite,
+            archive,
+            paths,
+            ..
+        } => create(cipher, *overwrite, archive, paths),
+        Command::List { archive, .. } => list(cipher, archive),
+        Command::Extract {
+            overwrite,
+            directory,
+            only,
+            archive,
+            ..
+        } => extract(cipher, *overwrite, directory, only, archive),
+    }
+}
+
+fn create<C: AeadInPlace>(cipher: &C, overwrite: bool, archive: &str, paths: &[String]) -> bool {
+    for path in paths {
+        if !Path::new(path).exists() {
+            eprintln!("error: '{path}' not exists");
+            return false;
+        }
+    }
+    let out_path = PathBuf::from(archive);
+    if out_path.exists() {
+        if !out_path.is_file() {
+            eprintln!("error: output file '{archive}' exists and is not a file");
+            return false;
+        }
+        if !overwrite {
+            print!("question: overwrite file '{archive}'? [y/n] ");
+            let mut input = String::new();
+            match stdin().read_line(&mut input) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("error: failed to read from stdin; {e}");
+                    return false;
+                }
+            }
+            let input = input.trim();
+            if input != "y" && input != "Y" {
+                return false;
+            }
+        }
+    }
+    let mut out_file = match OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&out_path)
+    {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("error: cannot open output file '{archive}': {e}");
+            return false;
+        }
+    };
+
+    let t0 = Instant::now();
+    let mut rng = rand::thread_rng();
+    match lib::pack_archive(paths, &mut out_file, cipher, &mut rng) {
+        Ok(entries) => {
+            let duration = Instant::now().sub(t0).as_secs_f32();
+            let count = entries.len();
+            println!("info: '{archive}' created with {count} entries; duration={duration:.3}s");
+            true
+        }
+        Err(msgs) => {
+            eprintln!("error: failed to create '{archive}'; {msgs:?}");
+            false
+        }
+    }
+}
+
+fn list<C: AeadInPlace>(cipher: &C, archive: &str) -> bool {
+    let mut in_file = match File::open(archive) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("error: cannot open input file '{archive}'; {e}");
+            return false;
+        }
+    };
+    match lib::list_archive(&mut in_file, cipher) {
+        Ok(entries) => {
+            for entry in entries {
+                let kind = if entry.is_dir { 'd' } else { '-' };
+                let path = &entry.path;
+                println!("{kind} {:04o} {:>12} {path}", entry.mode, entry.size);
+            }
+            true
+        }
+        Err(msgs) => {
+            eprintln!("error: failed to list '{archive}'; {msgs:?}");
+            false
+        }
+    }
+}
+
+fn extract<C: AeadInPlace>(
+    cipher: &C,
+    overwrite: bool,
+    directory: &str,
+    only: &[String],
+    archive: &str,
+) -> bool {
+    let mut in_file = match File::open(archive) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("error: cannot open input file '{archive}'; {e}");
+            return false;
+        }
+    };
+    let t0 = Instant::now();
+    let dest = Path::new(directory);
+    match lib::extract_archive(&mut in_file, cipher, dest, only, overwrite) {
+        Ok(entries) => {
+            let duration = Instant::now().sub(t0).as_secs_f32();
+            let count = entries.len();
+            println!("info: {count} entries extracted from '{archive}'; duration={duration:.3}s");
+            true
+        }
+        Err(msgs) => {
+            eprintln!("error: failed to extract '{archive}'; {msgs:?}");
+            false
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::io::{self, stdin, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+/// What to do with an output file which exists already.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Policy {
+    /// Ask for every file
+    Ask,
+    /// Overwrite it
+    Always,
+    /// Skip the input
+    Never,
+    /// Write beside it, as 'name (1).ext' etc.
+    Rename,
+}
+
+type ReadLine = Box<dyn FnMut(&mut String) -> io::Result<usize>>;
+
+pub struct Overwrite {
+    // answering "a" turns asking into always
+    policy: Cell<Policy>,
+    // reads an answer; from stdin but in tests
+    read_line: RefCell<ReadLine>,
+}
+impl Overwrite {
+    /// Without a policy, asks if stdin is a terminal, so scripted runs don't hang on a prompt.
+    pub fn new(policy: Option<Policy>, force: bool) -> Self {
+        let read_line = Box::new(|input: &mut String| stdin().read_line(input));
+        Self::with_input(policy, force, stdin().is_terminal(), read_line)
+    }
+
+    fn with_input(
+        policy: Option<Policy>,
+        force: bool,
+        terminal: bool,
+        read_line: ReadLine,
+    ) -> Self {
+        let policy = match policy {
+            _ if force => Policy::Always,
+            Some(p) => p,
+            None if terminal => Policy::Ask,
+            None => Policy::Never,
+        };
+        Self {
+            policy: Cell::new(policy),
+            read_line: RefCell::new(read_line),
+        }
+    }
+
+    /// Returns the path to write to, or `None` if the file is to be skipped. `probe` maps an
+    /// output path to the file which tells whether it exists, e.g. the first part of a split
+    /// archive.
+    pub fn check<F: Fn(&Path) -> PathBuf>(&self, path: &Path, probe: F) -> Option<PathBuf> {
+        let existing = probe(path);
+        if !existing.exists() {
+            return Some(path.to_path_buf());
+        }
+        let name = existing.to_string_lossy();
+        if !existing.is_file() {
+            eprintln!("error: output file '{name}' exists and is not a file");
+            return None;
+        }
+        match self.policy.get() {
+            Policy::Always => Some(path.to_path_buf()),
+            Policy::Never => {
+                eprintln!("warning: output file '{name}' exists; skipped");
+                None
+            }
+            Policy::Rename => renamed(path, probe),
+            Policy::Ask => self.ask(path, &name, probe),
+        }
+    }
+
+    fn ask<F: Fn(&Path) -> PathBuf>(&self, path: &Path, name: &str, probe: F) -> Option<PathBuf> {
+        loop {
+            // stderr isn't buffered, and keeps stdout to the results
+            eprint!("question: overwrite file '{name}'? [y]es, [n]o, [a]ll, [r]ename: ");
+            let mut input = String::new();
+            let result = (self.read_line.borrow_mut())(&mut input);
+            match result {
+                Ok(0) => {
+                    eprintln!();
+                    self.policy.set(Policy::Never); // nobody to answer anymore
+                    return None;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("error: failed to read from stdin; {e}");
+                    return None;
+                }
+            }
+            match input.trim() {
+                "y" | "Y" => return Some(path.to_path_buf()),
+                "n" | "N" => return None,
+                "a" | "A" => {
+                    self.policy.set(Policy::Always);
+                    return Some(path.to_path_buf());
+                }
+                "r" | "R" => return renamed(path, probe),
+                _ => {}
+            }
+        }
+    }
+}
+
+// 'backup.tar.enc' becomes 'backup.tar (1).enc', 'backup.tar (2).enc', etc.
+fn renamed<F: Fn(&Path) -> PathBuf>(path: &Path, probe: F) -> Option<PathBuf> {
+    let stem = path.file_stem()?;
+    for i in 1.. {
+        let mut name = OsString::from(stem);
+        name.push(format!(" ({i})"));
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let candidate = path.with_file_name(name);
+        if !probe(&candidate).exists() {
+            return Some(candidate);
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{Overwrite, Policy};
+
+    fn answering(policy: Option<Policy>, answers: &'static str) -> Overwrite {
+        let mut lines = answers.split_inclusive('\n');
+        let read_line = Box::new(move |input: &mut String| {
+            let line = lines.next().unwrap_or("");
+            input.push_str(line);
+            Ok(line.len())
+        });
+        Overwrite::with_input(policy, false, true, read_line)
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let no_input = || Box::new(|_: &mut String| Ok(0));
+        let resolved = |policy, force, terminal| {
+            let overwrite = Overwrite::with_input(policy, force, terminal, no_input());
+            overwrite.policy.get()
+        };
+        assert_eq!(resolved(None, false, true), Policy::Ask);
+        assert_eq!(resolved(None, false, false), Policy::Never);
+        assert_eq!(resolved(None, true, false), Policy::Always);
+        assert_eq!(resolved(Some(Policy::Never), true, true), Policy::Always);
+        assert_eq!(resolved(Some(Policy::Rename), false, false), Policy::Rename);
+
+        let dir = std::env::temp_dir().join(format!("overwrite-test-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("backup.tar.enc");
+        let same = |p: &Path| p.to_path_buf();
+
+        // nothing to ask about
+        let overwrite = answering(None, "");
+        assert_eq!(overwrite.check(&path, same), Some(path.clone()));
+
+        fs::write(&path, b"").unwrap();
+        fs::write(dir.join("backup.tar (1).enc"), b"").unwrap();
+        let renamed = dir.join("backup.tar (2).enc");
+        let not_terminal = Overwrite::with_input(None, false, false, no_input());
+        assert_eq!(not_terminal.check(&path, same), None);
+        let rename = answering(Some(Policy::Rename), "");
+        assert_eq!(rename.check(&path, same), Some(renamed.clone()));
+
+        // a split archive is told by its first part
+        let probe = |p: &Path| {
+            let mut name = p.as_os_str().to_owned();
+            name.push(".001");
+            PathBuf::from(name)
+        };
+        assert_eq!(rename.check(&path, probe), Some(path.clone()));
+
+        // other answers are asked again; "a" answers the questions to come
+        let ask = answering(None, "x\nn\nr\na\n");
+        assert_eq!(ask.check(&path, same), None);
+        assert_eq!(ask.check(&path, same), Some(renamed));
+        assert_eq!(ask.check(&path, same), Some(path.clone()));
+        assert_eq!(ask.policy.get(), Policy::Always);
+        assert_eq!(ask.check(&path, same), Some(path.clone()));
+
+        // nobody to answer
+        let closed = answering(None, "");
+        assert_eq!(closed.check(&path, same), None);
+        assert_eq!(closed.policy.get(), Policy::Never);
+
+        fs::create_dir(dir.join("dir")).unwrap();
+        let always = answering(Some(Policy::Always), "");
+        assert_eq!(always.check(&dir.join("dir"), same), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
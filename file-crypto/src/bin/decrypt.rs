@@ -1,19 +1,25 @@
 use std::fs::{File, OpenOptions};
-use std::io::{sink, stdin};
+use std::io::{sink, Cursor, ErrorKind, Read, Write};
 use std::ops::Sub;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::time::Instant;
 
 use aes_gcm::AeadInPlace;
 use clap::Parser;
+use sha2::{Digest, Sha256};
 
 use file_crypto as lib;
 
+#[path = "common/overwrite.rs"]
+mod overwrite;
+
+use overwrite::{Overwrite, Policy};
+
 #[derive(Parser)]
 struct Arg {
-    #[arg(short, long, help = "The secret key")]
-    key: String,
+    #[arg(short, long, help = "The secret key", value_parser = parse_key)]
+    key: lib::SecretString,
 
     #[arg(short, long, help = "The extension name of encrypted file")]
     #[arg(default_value = "enc")]
@@ -24,14 +30,44 @@ struct Arg {
 
     #[arg(
         short,
-        long = "force",
-        help = "Overwrite existed file without confirmation"
+        long,
+        visible_short_alias = 'o',
+        help = "Overwrite existed file without confirmation, the same as --overwrite=always"
+    )]
+    force: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        conflicts_with = "force",
+        help = "What to do with existing output files; the default is ask if stdin is a terminal, never otherwise"
     )]
-    overwrite: bool,
+    overwrite: Option<Policy>,
 
     #[arg(long = "256", help = "Use AES-256-GCM instead of AES-128-GCM")]
     use_aes256gcm: bool,
 
+    #[arg(
+        long,
+        help = "Zero-fill the chunks which are damaged beyond repair instead of failing"
+    )]
+    best_effort: bool,
+
+    #[arg(
+        long,
+        value_name = "PUBKEY",
+        value_parser = parse_verifying_key,
+        help = "Fail unless signed by PUBKEY, the base64 verifying key or a file holding it (see keygen)"
+    )]
+    verify_signer: Option<lib::VerifyingKey>,
+
+    #[arg(
+        long,
+        help = "Print the SHA-256 of the decrypted files like sha256sum does (other messages go to stderr)"
+    )]
+    sha256: bool,
+
     #[arg(required = true, help = "File(s) to decrypt")]
     files: Vec<String>,
 }
@@ -45,19 +81,34 @@ fn main() {
         }
     };
 
+    let overwrite = Overwrite::new(arg.overwrite, arg.force);
     if arg.use_aes256gcm {
-        let cipher = lib::new_aes256gcm_cipher(&arg.key);
+        let cipher = lib::SecretBox::new(lib::new_aes256gcm_cipher(&arg.key));
         for file in &arg.files {
-            decrypt_file(&arg, &cipher, file);
+            decrypt_file(&arg, &*cipher, &overwrite, file);
         }
     } else {
-        let cipher = lib::new_aes128gcm_cipher(&arg.key);
+        let cipher = lib::SecretBox::new(lib::new_aes128gcm_cipher(&arg.key));
         for file in &arg.files {
-            decrypt_file(&arg, &cipher, file);
+            decrypt_file(&arg, &*cipher, &overwrite, file);
         }
     }
 }
 
+fn parse_key(key: &str) -> Result<lib::SecretString, String> {
+    Ok(lib::SecretString::new(key.to_string()))
+}
+
+fn parse_verifying_key(key: &str) -> Result<lib::VerifyingKey, String> {
+    if !Path::new(key).is_file() {
+        return lib::parse_verifying_key(key);
+    }
+    match std::fs::read_to_string(key) {
+        Ok(text) => lib::parse_verifying_key(&text),
+        Err(e) => Err(format!("cannot read verifying key file '{key}'; {e}")),
+    }
+}
+
 fn parse_and_check_arg() -> Result<Arg, String> {
     let arg = Arg::parse();
 
@@ -70,7 +121,8 @@ fn parse_and_check_arg() -> Result<Arg, String> {
     }
 
     for file in &arg.files {
-        if !file.ends_with(&arg.ext_name) {
+        let name = split_archive_base(file).unwrap_or(file);
+        if !name.ends_with(&arg.ext_name) {
             return Err(format!("'{file}' doesn't end with {}", arg.ext_name));
         }
         let path = PathBuf::from(file);
@@ -85,63 +137,49 @@ fn parse_and_check_arg() -> Result<Arg, String> {
     Ok(arg)
 }
 
-fn decrypt_file<C: AeadInPlace>(arg: &Arg, cipher: &C, file: &str) {
-    let in_path = PathBuf::from(file);
-    let mut in_file = match File::open(&in_path) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("error: cannot open input file '{file}'; {e}");
+// 'backup.enc.001' is the first part of the split archive 'backup.enc'
+fn split_archive_base(file: &str) -> Option<&str> {
+    file.strip_suffix(".001")
+}
+
+fn decrypt_file<C: AeadInPlace>(arg: &Arg, cipher: &C, overwrite: &Overwrite, file: &str) {
+    let split_base = split_archive_base(file);
+    let in_path = PathBuf::from(split_base.unwrap_or(file));
+    let mut in_file = match split_base {
+        Some(_) => None, // the parts are opened one after another
+        None => match File::open(&in_path) {
+            Ok(f) => Some(f),
+            Err(e) => {
+                eprintln!("error: cannot open input file '{file}'; {e}");
+                return;
+            }
+        },
+    };
+    let out_path = match in_path.file_name() {
+        None => {
+            eprintln!("error: cannot determine the file name of '{file}'");
             return;
         }
+        Some(in_name) => {
+            let in_name = in_name.to_string_lossy();
+            let out_name = in_name.strip_suffix(&arg.ext_name).unwrap();
+            PathBuf::from(out_name)
+        }
     };
-    let mut out_file = if arg.dry_run {
-        None
+    let (out_path, mut out_file) = if arg.dry_run {
+        (out_path, None)
     } else {
-        let out_path = match in_path.file_name() {
-            None => {
-                eprintln!("error: cannot determine the file name of '{file}'");
-                return;
-            }
-            Some(in_name) => {
-                let in_name = in_name.to_string_lossy();
-                let out_name = in_name.strip_suffix(&arg.ext_name).unwrap();
-                PathBuf::from(out_name)
-            }
+        let out_path = match overwrite.check(&out_path, Path::to_path_buf) {
+            Some(p) => p,
+            None => return,
         };
-        if out_path.exists() {
-            if !out_path.is_file() {
-                eprintln!(
-                    "error: output file '{}' exists and is not a file",
-                    out_path.to_string_lossy()
-                );
-                return;
-            }
-            if !arg.overwrite {
-                print!(
-                    "question: overwrite file '{}'? [y/n] ",
-                    out_path.to_string_lossy()
-                );
-                let mut input = String::new();
-                match stdin().read_line(&mut input) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        eprintln!("error: failed to read from stdin; {e}");
-                        return;
-                    }
-                }
-                let input = input.trim();
-                if input != "y" && input != "Y" {
-                    return;
-                }
-            }
-        }
-        match OpenOptions::new()
+        let out_file = match OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
             .open(&out_path)
         {
-            Ok(f) => Some(f),
+            Ok(f) => f,
             Err(e) => {
                 eprintln!(
                     "error: cannot open output file '{}': {e}",
@@ -149,24 +187,117 @@ fn decrypt_file<C: AeadInPlace>(arg: &Arg, cipher: &C, file: &str) {
                 );
                 return;
             }
-        }
+        };
+        (out_path, Some(out_file))
     };
 
+    let options = lib::DecryptOptions {
+        best_effort: arg.best_effort,
+        verify_signer: arg.verify_signer,
+    };
     let t0 = Instant::now();
     let result = match out_file.as_mut() {
         None => {
             let mut sink = sink();
-            lib::decrypt(&mut in_file, &mut sink, cipher)
+            decrypt_input(&in_path, in_file.as_mut(), &mut sink, cipher, &options)
         }
-        Some(out_file) => lib::decrypt(&mut in_file, out_file, cipher),
+        Some(out_file) => decrypt_input(&in_path, in_file.as_mut(), out_file, cipher, &options),
     };
     match result {
-        Ok(_) => {
+        Ok(report) => {
+            if report.repaired_chunks > 0 {
+                let count = report.repaired_chunks;
+                info(arg, &format!("'{file}': repaired {count} damaged chunk(s)"));
+            }
+            for range in &report.damaged {
+                eprintln!(
+                    "warning: '{file}' is damaged at plaintext bytes {}..{}; zero-filled",
+                    range.start, range.end
+                );
+            }
+            if let Some(signer) = report.signer {
+                let signer = lib::encode_verifying_key(&signer);
+                info(arg, &format!("'{file}' is signed by {signer}"));
+            }
+            if report.sha256_recorded {
+                info(
+                    arg,
+                    &format!("'{file}' matches the SHA-256 recorded when encrypting"),
+                );
+            }
             let duration = Instant::now().sub(t0).as_secs_f32();
-            println!("info: '{file}' decrypted; duration={duration:.3}s");
+            info(arg, &format!("'{file}' decrypted; duration={duration:.3}s"));
+            if arg.sha256 {
+                let sha256: String = report.sha256.iter().map(|b| format!("{b:02x}")).collect();
+                println!("{sha256}  {}", out_path.to_string_lossy());
+            }
         }
         Err(msgs) => {
             eprintln!("error: failed to decrypt '{file}'; {msgs:?}");
         }
     }
 }
+
+// with --sha256, stdout is kept for the hashes
+fn info(arg: &Arg, msg: &str) {
+    if arg.sha256 {
+        eprintln!("info: {msg}");
+    } else {
+        println!("info: {msg}");
+    }
+}
+
+fn decrypt_input<W: Write, C: AeadInPlace>(
+    in_path: &Path,
+    in_file: Option<&mut File>,
+    writer: &mut W,
+    cipher: &C,
+    options: &lib::DecryptOptions,
+) -> Result<lib::DecryptReport, Vec<String>> {
+    let in_file = match in_file {
+        Some(f) => f,
+        None => {
+            return lib::decrypt_parts(|i| open_part(in_path, i), writer, cipher, options);
+        }
+    };
+
+    // armored files are told by their begin marker
+    let mut head = vec![];
+    if let Err(e) = in_file.take(64).read_to_end(&mut head) {
+        return Err(vec![format!("read error: {e}")]);
+    }
+    if !lib::is_armored(&head) {
+        let mut reader = Cursor::new(head).chain(in_file);
+        return lib::decrypt_with_options(&mut reader, writer, cipher, options);
+    }
+    let mut text = String::from_utf8_lossy(&head).into_owned();
+    if let Err(e) = in_file.read_to_string(&mut text) {
+        return Err(vec![format!("read error: {e}")]);
+    }
+    if options.verify_signer.is_some() {
+        return Err(vec!["armored files aren't signed".to_string()]);
+    }
+    if options.best_effort {
+        return Err(vec![
+            "armored files can't be decrypted in best-effort mode".to_string()
+        ]);
+    }
+    let encrypted = lib::dearmor(&text).map_err(|msg| vec![msg])?;
+    let decrypted = lib::decrypt_from_slice(&encrypted, cipher).map_err(|msg| vec![msg])?;
+    let report = lib::DecryptReport {
+        sha256: Sha256::digest(&decrypted).into(),
+        ..Default::default()
+    };
+    match writer.write_all(&decrypted) {
+        Ok(_) => Ok(report),
+        Err(e) => Err(vec![format!("write error: {e}")]),
+    }
+}
+
+fn open_part(in_path: &Path, index: usize) -> std::io::Result<Option<File>> {
+    match File::open(lib::part_path(in_path, index)) {
+        Ok(f) => Ok(Some(f)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,455 @@
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{sink, Read, Write};
+use std::ops::Sub;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Instant;
+
+use aes_gcm::AeadInPlace;
+use clap::Parser;
+
+use file_crypto as lib;
+use lib::NonceSource;
+
+#[path = "../common/overwrite.rs"]
+mod overwrite;
+mod watch;
+
+use overwrite::{Overwrite, Policy};
+
+#[derive(Parser)]
+struct Arg {
+    #[arg(short, long, help = "The secret key", value_parser = parse_key)]
+    key: lib::SecretString,
+
+    #[arg(short, long, help = "The extension name of encrypted file")]
+    #[arg(default_value = "enc")]
+    ext_name: String,
+
+    #[arg(short = 'n', long, help = "Dry run without writing the result")]
+    dry_run: bool,
+
+    #[arg(
+        short,
+        long,
+        visible_short_alias = 'o',
+        help = "Overwrite existed file without confirmation, the same as --overwrite=always"
+    )]
+    force: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        conflicts_with = "force",
+        help = "What to do with existing output files; the default is ask if stdin is a terminal, never otherwise"
+    )]
+    overwrite: Option<Policy>,
+
+    #[arg(long = "256", help = "Use AES-256-GCM instead of AES-128-GCM")]
+    use_aes256gcm: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Derive nonces from the content, so identical files encrypt identically"
+    )]
+    deterministic: bool,
+
+    #[arg(
+        short,
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Split the output into parts of at most SIZE bytes (K, M and G suffixes accepted)"
+    )]
+    split: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Add N parity chunks to every group of chunks, so up to N damaged chunks of a group can be repaired"
+    )]
+    parity: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "K",
+        default_value_t = 16,
+        help = "The number of chunks per parity group"
+    )]
+    parity_group: usize,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["split", "parity", "sign", "sha256"],
+        help = "Write ASCII-armored text (base64 between begin and end markers), for small files"
+    )]
+    armor: bool,
+
+    #[arg(
+        long,
+        value_name = "KEYFILE",
+        value_parser = parse_signing_key_file,
+        help = "Sign the output with the Ed25519 signing key in KEYFILE (see keygen)"
+    )]
+    sign: Option<lib::SigningKey>,
+
+    #[arg(
+        long,
+        help = "Record the SHA-256 of the plaintext, which decrypting recomputes and compares"
+    )]
+    sha256: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["split", "armor", "dry_run", "watch"],
+        help = "Continue an interrupted encryption from where its output ends, instead of starting over"
+    )]
+    resume: bool,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Write the encrypted files into DIR instead of the current directory"
+    )]
+    out_dir: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        requires = "out_dir",
+        help = "Keep watching the given directories and encrypt the files created or modified in them; existing output is overwritten"
+    )]
+    watch: bool,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 1000,
+        help = "With --watch, wait until a file is unchanged for MS milliseconds before encrypting it"
+    )]
+    debounce: u64,
+
+    #[arg(
+        long,
+        requires = "watch",
+        help = "With --watch, delete the original files once they are encrypted"
+    )]
+    delete: bool,
+
+    #[arg(
+        required = true,
+        help = "File(s) to encrypt, or the directories to watch"
+    )]
+    files: Vec<String>,
+}
+
+fn main() {
+    let arg = match parse_and_check_arg() {
+        Ok(a) => a,
+        Err(msg) => {
+            eprintln!("error: {msg}");
+            exit(1);
+        }
+    };
+
+    let options = lib::EncryptOptions {
+        parity: arg
+            .parity
+            .map(|n| lib::Parity::new(arg.parity_group, n).unwrap()),
+        signing_key: arg.sign.clone(),
+        record_sha256: arg.sha256,
+    };
+    let overwrite = Overwrite::new(arg.overwrite, arg.force);
+    let ok = match (arg.use_aes256gcm, arg.watch) {
+        (true, false) => {
+            let cipher = lib::SecretBox::new(lib::new_aes256gcm_cipher(&arg.key));
+            encrypt_files(&arg, &*cipher, &options, &overwrite);
+            true
+        }
+        (false, false) => {
+            let cipher = lib::SecretBox::new(lib::new_aes128gcm_cipher(&arg.key));
+            encrypt_files(&arg, &*cipher, &options, &overwrite);
+            true
+        }
+        (true, true) => {
+            let cipher = lib::SecretBox::new(lib::new_aes256gcm_cipher(&arg.key));
+            watch::watch(&arg, &*cipher, &options)
+        }
+        (false, true) => {
+            let cipher = lib::SecretBox::new(lib::new_aes128gcm_cipher(&arg.key));
+            watch::watch(&arg, &*cipher, &options)
+        }
+    };
+    if !ok {
+        exit(1);
+    }
+}
+
+fn encrypt_files<C: AeadInPlace>(
+    arg: &Arg,
+    cipher: &C,
+    options: &lib::EncryptOptions,
+    overwrite: &Overwrite,
+) {
+    let mut rng = rand::thread_rng();
+    for file in &arg.files {
+        if arg.deterministic {
+            let mut nonce_source = lib::DeterministicNonce::new(&arg.key);
+            encrypt_file(arg, cipher, options, &mut nonce_source, overwrite, file);
+        } else {
+            encrypt_file(arg, cipher, options, &mut rng, overwrite, file);
+        }
+    }
+}
+
+fn parse_key(key: &str) -> Result<lib::SecretString, String> {
+    Ok(lib::SecretString::new(key.to_string()))
+}
+
+fn parse_signing_key_file(path: &str) -> Result<lib::SigningKey, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => lib::parse_signing_key(&lib::SecretString::new(text)),
+        Err(e) => Err(format!("cannot read signing key file '{path}'; {e}")),
+    }
+}
+
+fn parse_and_check_arg() -> Result<Arg, String> {
+    let arg = Arg::parse();
+
+    if arg.key.is_empty() {
+        return Err("empty secret key".to_string());
+    }
+
+    if arg.ext_name.is_empty() {
+        return Err("empty extension name".to_string());
+    }
+
+    if let Some(n) = arg.parity {
+        lib::Parity::new(arg.parity_group, n)?;
+    }
+
+    if let Some(out_dir) = &arg.out_dir {
+        if !Path::new(out_dir).is_dir() {
+            return Err(format!("output directory '{out_dir}' is not a directory"));
+        }
+    }
+
+    for file in &arg.files {
+        let path = PathBuf::from(file);
+        if !path.exists() {
+            return Err(format!("'{file}' not exists"));
+        }
+        if arg.watch {
+            if !path.is_dir() {
+                return Err(format!("'{file}' is not a directory"));
+            }
+            // the output would be encrypted again and again
+            if watch::same_dir(&path, Path::new(arg.out_dir.as_ref().unwrap())) {
+                return Err(format!("'{file}' is the output directory"));
+            }
+        } else if !path.is_file() {
+            return Err(format!("'{file}' is not a file"));
+        }
+    }
+
+    Ok(arg)
+}
+
+// returns whether the file is encrypted
+fn encrypt_file<C: AeadInPlace, N: NonceSource>(
+    arg: &Arg,
+    cipher: &C,
+    options: &lib::EncryptOptions,
+    nonce_source: &mut N,
+    overwrite: &Overwrite,
+    file: &str,
+) -> bool {
+    let in_path = PathBuf::from(file);
+    let mut in_file = match File::open(&in_path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("error: cannot open input file '{file}'; {e}");
+            return false;
+        }
+    };
+    let out_path = if arg.dry_run {
+        None
+    } else {
+        let out_path = match in_path.file_name() {
+            None => {
+                eprintln!("error: cannot determine the file name of '{file}'");
+                return false;
+            }
+            Some(in_name) => out_path(arg, &in_name.to_string_lossy()),
+        };
+        // a split archive is told by its first part
+        let probe = |path: &Path| match arg.split {
+            None => path.to_path_buf(),
+            Some(_) => lib::part_path(path, 0),
+        };
+        if arg.resume && out_path.is_file() {
+            Some(out_path) // continued, not overwritten
+        } else {
+            match overwrite.check(&out_path, probe) {
+                Some(p) => Some(p),
+                None => return false,
+            }
+        }
+    };
+
+    let t0 = Instant::now();
+    let result = match (out_path, arg.split) {
+        (out_path, None) if arg.armor => {
+            encrypt_armored(&mut in_file, out_path.as_deref(), cipher, nonce_source)
+        }
+        (Some(out_path), None) if arg.resume && out_path.exists() => {
+            resume_file(&mut in_file, &out_path, cipher, nonce_source, options).map(|offset| {
+                println!("info: '{file}' resumed at byte {offset}");
+            })
+        }
+        (None, None) => {
+            let mut sink = sink();
+            lib::encrypt_with_options(&mut in_file, &mut sink, cipher, nonce_source, options)
+        }
+        (None, Some(part_size)) => lib::encrypt_parts(
+            &mut in_file,
+            |_| Ok(sink()),
+            part_size,
+            cipher,
+            nonce_source,
+            options,
+        )
+        .map(|_| ()),
+        (Some(out_path), None) => {
+            let mut out_file = match open_output(&out_path) {
+                Ok(f) => f,
+                Err(e) => {
+                    eprintln!(
+                        "error: cannot open output file '{}': {e}",
+                        out_path.to_string_lossy()
+                    );
+                    return false;
+                }
+            };
+            lib::encrypt_with_options(&mut in_file, &mut out_file, cipher, nonce_source, options)
+        }
+        (Some(out_path), Some(part_size)) => lib::encrypt_parts(
+            &mut in_file,
+            |i| open_output(&lib::part_path(&out_path, i)),
+            part_size,
+            cipher,
+            nonce_source,
+            options,
+        )
+        .map(|count| remove_stale_parts(&out_path, count)),
+    };
+    match result {
+        Ok(_) => {
+            let duration = Instant::now().sub(t0).as_secs_f32();
+            println!("info: '{file}' encrypted; duration={duration:.3}s");
+            true
+        }
+        Err(msgs) => {
+            eprintln!("error: failed to encrypt '{file}'; {msgs:?}");
+            false
+        }
+    }
+}
+
+fn out_path(arg: &Arg, in_name: &str) -> PathBuf {
+    let ext_name = &arg.ext_name;
+    let out_name = format!("{in_name}.{ext_name}");
+    match &arg.out_dir {
+        None => PathBuf::from(out_name),
+        Some(out_dir) => Path::new(out_dir).join(out_name),
+    }
+}
+
+fn encrypt_armored<C: AeadInPlace, N: NonceSource>(
+    in_file: &mut File,
+    out_path: Option<&Path>,
+    cipher: &C,
+    nonce_source: &mut N,
+) -> Result<(), Vec<String>> {
+    let mut data = lib::SecretBuffer::default();
+    if let Err(e) = in_file.read_to_end(&mut data) {
+        return Err(vec![format!("read error: {e}")]);
+    }
+    let encrypted = lib::encrypt_to_vec(&data, cipher, nonce_source).map_err(|msg| vec![msg])?;
+    let text = lib::armor(&encrypted);
+    if let Some(out_path) = out_path {
+        if let Err(e) = open_output(out_path).and_then(|mut f| f.write_all(text.as_bytes())) {
+            return Err(vec![format!("write error: {e}")]);
+        }
+    }
+    Ok(())
+}
+
+fn resume_file<C: AeadInPlace, N: NonceSource>(
+    in_file: &mut File,
+    out_path: &Path,
+    cipher: &C,
+    nonce_source: &mut N,
+    options: &lib::EncryptOptions,
+) -> Result<u64, Vec<String>> {
+    let mut out_file = match OpenOptions::new().read(true).write(true).open(out_path) {
+        Ok(f) => f,
+        Err(e) => {
+            let msg = format!(
+                "cannot open output file '{}': {e}",
+                out_path.to_string_lossy()
+            );
+            return Err(vec![msg]);
+        }
+    };
+    lib::resume_encryption(in_file, &mut out_file, cipher, nonce_source, options)
+}
+
+fn open_output(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+}
+
+// parts left over from a previous, longer archive of the same name would break decryption
+fn remove_stale_parts(out_path: &Path, count: usize) {
+    for i in count.. {
+        let path = lib::part_path(out_path, i);
+        if !path.is_file() {
+            break;
+        }
+        if let Err(e) = remove_file(&path) {
+            eprintln!(
+                "warning: cannot remove stale part '{}'; {e}",
+                path.to_string_lossy()
+            );
+        }
+    }
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        None => (s, ""),
+        Some(i) => s.split_at(i),
+    };
+    let unit = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => {
+            return Err(format!("unknown size unit '{unit}'"));
+        }
+    };
+    match num.parse::<u64>() {
+        Ok(n) => n
+            .checked_mul(unit)
+            .ok_or_else(|| format!("size '{s}' is too large")),
+        Err(e) => Err(format!("invalid size '{s}'; {e}")),
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fs::{metadata, read_dir, remove_file, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+
+use aes_gcm::AeadInPlace;
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::overwrite::{Overwrite, Policy};
+use crate::{encrypt_file, out_path, Arg};
+use file_crypto as lib;
+
+// how often a file which fails to encrypt (e.g. still locked by the writer) is tried again
+const MAX_RETRIES: u32 = 5;
+
+struct Pending {
+    due: Instant,
+    retries: u32,
+}
+
+// Encrypts the files of the watched directories (not their subdirectories) once they stop
+// changing, and again whenever they change. Dot files are skipped, as many tools write partial
+// files under such names and rename them when done. Runs until interrupted; returns false if the
+// directories can't be watched.
+pub fn watch<C: AeadInPlace>(arg: &Arg, cipher: &C, options: &lib::EncryptOptions) -> bool {
+    let (tx, rx) = channel();
+    let mut watcher = match notify::recommended_watcher(tx) {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("error: cannot watch files; {e}");
+            return false;
+        }
+    };
+    for dir in &arg.files {
+        if let Err(e) = watcher.watch(Path::new(dir), RecursiveMode::NonRecursive) {
+            eprintln!("error: cannot watch '{dir}'; {e}");
+            return false;
+        }
+    }
+
+    let debounce = Duration::from_millis(arg.debounce);
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    // files which were dropped while not watching
+    for dir in &arg.files {
+        let entries = match read_dir(dir) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("error: cannot read directory '{dir}'; {e}");
+                return false;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if is_candidate(&path) && is_outdated(arg, &path) {
+                schedule(&mut pending, path, Instant::now());
+            }
+        }
+    }
+    println!("info: watching {:?}; press Ctrl-C to stop", arg.files);
+
+    // the output of a changed file is outdated
+    let overwrite = Overwrite::new(Some(Policy::Always), false);
+    let mut rng = rand::thread_rng();
+    loop {
+        let now = Instant::now();
+        let timeout = match pending.values().map(|p| p.due).min() {
+            Some(due) => due.saturating_duration_since(now),
+            None => Duration::from_secs(3600),
+        };
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                let changed = matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Modify(_)
+                        | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                );
+                for path in event.paths {
+                    if changed && is_candidate(&path) {
+                        schedule(&mut pending, path, Instant::now() + debounce);
+                    }
+                }
+            }
+            Ok(Err(e)) => {
+                eprintln!("warning: failed to watch; {e}");
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                eprintln!("error: the watcher stopped");
+                return false;
+            }
+        }
+
+        let now = Instant::now();
+        let due: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, p)| p.due <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in due {
+            let retries = pending.remove(&path).unwrap().retries;
+            let before = match metadata(&path) {
+                Ok(m) if m.is_file() => m,
+                _ => continue, // removed or renamed meanwhile
+            };
+            let file = path.to_string_lossy();
+            let ok = if arg.deterministic {
+                let mut nonce_source = lib::DeterministicNonce::new(&arg.key);
+                encrypt_file(arg, cipher, options, &mut nonce_source, &overwrite, &file)
+            } else {
+                encrypt_file(arg, cipher, options, &mut rng, &overwrite, &file)
+            };
+            let unchanged = metadata(&path).is_ok_and(|after| same_version(&before, &after));
+            if !unchanged {
+                // still being written; whatever was encrypted is partial
+                println!("info: '{file}' changed while encrypting; encrypting it again");
+                schedule(&mut pending, path, Instant::now() + debounce);
+            } else if !ok {
+                if retries < MAX_RETRIES {
+                    let delay = debounce.max(Duration::from_millis(100)) * 2u32.pow(retries);
+                    let seconds = delay.as_secs_f32();
+                    eprintln!("warning: retrying '{file}' in {seconds:.1}s");
+                    let retry = Pending {
+                        due: Instant::now() + delay,
+                        retries: retries + 1,
+                    };
+                    pending.insert(path, retry);
+                } else {
+                    eprintln!("error: giving up on '{file}' after {MAX_RETRIES} retries");
+                }
+            } else if arg.delete && !arg.dry_run {
+                match remove_file(&path) {
+                    Ok(_) => println!("info: '{file}' deleted"),
+                    Err(e) => eprintln!("warning: cannot delete '{file}'; {e}"),
+                }
+            }
+        }
+    }
+}
+
+// a change resets the delay and the retries
+fn schedule(pending: &mut HashMap<PathBuf, Pending>, path: PathBuf, due: Instant) {
+    pending.insert(path, Pending { due, retries: 0 });
+}
+
+fn is_candidate(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_none_or(|name| name.to_string_lossy().starts_with('.'));
+    !hidden && path.is_file()
+}
+
+fn is_outdated(arg: &Arg, path: &Path) -> bool {
+    let name = path.file_name().unwrap().to_string_lossy();
+    let modified = |p: &Path| metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(path), modified(&out_path(arg, &name))) {
+        (Some(input), Some(output)) => input > output,
+        _ => true,
+    }
+}
+
+fn same_version(before: &Metadata, after: &Metadata) -> bool {
+    let modified = |m: &Metadata| m.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    before.len() == after.len() && modified(before) == modified(after)
+}
+
+pub fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
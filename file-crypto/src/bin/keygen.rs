@@ -0,0 +1,102 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::Parser;
+
+use file_crypto as lib;
+
+#[path = "common/overwrite.rs"]
+mod overwrite;
+
+use overwrite::{Overwrite, Policy};
+
+#[derive(Parser)]
+#[command(about = "Generate an Ed25519 signing key for `encrypt --sign`")]
+struct Arg {
+    #[arg(
+        short,
+        long,
+        visible_short_alias = 'o',
+        help = "Overwrite existed key files without confirmation, the same as --overwrite=always"
+    )]
+    force: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        conflicts_with = "force",
+        help = "What to do with existing key files; the default is ask if stdin is a terminal, never otherwise"
+    )]
+    overwrite: Option<Policy>,
+
+    #[arg(help = "The file to write the signing key into; the verifying key goes into FILE.pub")]
+    file: String,
+}
+
+fn main() {
+    let arg = Arg::parse();
+    let overwrite = Overwrite::new(arg.overwrite, arg.force);
+    // the key and its .pub are replaced or renamed together, so either existing counts
+    let probe = |path: &Path| match path.exists() {
+        false if pub_path(path).exists() => pub_path(path),
+        _ => path.to_path_buf(),
+    };
+    let key_path = match overwrite.check(Path::new(&arg.file), probe) {
+        Some(p) => p,
+        None => exit(1),
+    };
+    let pub_path = pub_path(&key_path);
+
+    let key = lib::generate_signing_key();
+    let encoded = lib::encode_signing_key(&key);
+    if let Err(e) = write_private(&key_path, encoded.as_str()) {
+        eprintln!("error: cannot write '{}'; {e}", key_path.to_string_lossy());
+        exit(1);
+    }
+    let verifying_key = lib::encode_verifying_key(&key.verifying_key());
+    if let Err(e) = fs::write(&pub_path, format!("{verifying_key}\n")) {
+        eprintln!("error: cannot write '{}'; {e}", pub_path.to_string_lossy());
+        exit(1);
+    }
+    println!(
+        "info: signing key written to '{}'",
+        key_path.to_string_lossy()
+    );
+    println!("info: verifying key: {verifying_key}");
+}
+
+fn pub_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(".pub");
+    PathBuf::from(name)
+}
+
+// A file created with mode 0o600 and renamed over `path`, as opening an existing file keeps its
+// permissions, and other users may have it open already.
+fn write_private(path: &Path, text: &str) -> std::io::Result<()> {
+    let (tmp_path, mut file) = loop {
+        let mut name = OsString::from(path);
+        name.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+        let tmp_path = PathBuf::from(name);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&tmp_path) {
+            Ok(f) => break (tmp_path, f),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    };
+    let result = writeln!(file, "{text}")
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
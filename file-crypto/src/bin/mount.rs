@@ -0,0 +1,126 @@
+use std::mem;
+use std::path::Path;
+use std::process::exit;
+use std::ptr;
+use std::thread::spawn;
+
+use aes_gcm::AeadInPlace;
+use clap::Parser;
+
+use file_crypto as lib;
+
+#[derive(Parser)]
+#[command(about = "Show a directory of encrypted files as a read-only filesystem")]
+struct Arg {
+    #[arg(short, long, help = "The secret key", value_parser = parse_key)]
+    key: lib::SecretString,
+
+    #[arg(short, long, help = "The extension name of encrypted file")]
+    #[arg(default_value = "enc")]
+    ext_name: String,
+
+    #[arg(long = "256", help = "Use AES-256-GCM instead of AES-128-GCM")]
+    use_aes256gcm: bool,
+
+    #[arg(help = "The directory of the encrypted files")]
+    source: String,
+
+    #[arg(help = "The directory to mount the decrypted files on")]
+    mountpoint: String,
+}
+
+fn main() {
+    let arg = match parse_and_check_arg() {
+        Ok(a) => a,
+        Err(msg) => {
+            eprintln!("error: {msg}");
+            exit(1);
+        }
+    };
+
+    let ok = if arg.use_aes256gcm {
+        let cipher = lib::SecretBox::new(lib::new_aes256gcm_cipher(&arg.key));
+        mount(&arg, &*cipher)
+    } else {
+        let cipher = lib::SecretBox::new(lib::new_aes128gcm_cipher(&arg.key));
+        mount(&arg, &*cipher)
+    };
+    if !ok {
+        exit(1);
+    }
+}
+
+fn parse_key(key: &str) -> Result<lib::SecretString, String> {
+    Ok(lib::SecretString::new(key.to_string()))
+}
+
+fn parse_and_check_arg() -> Result<Arg, String> {
+    let arg = Arg::parse();
+
+    if arg.key.is_empty() {
+        return Err("empty secret key".to_string());
+    }
+
+    if arg.ext_name.is_empty() {
+        return Err("empty extension name".to_string());
+    }
+
+    if !Path::new(&arg.source).is_dir() {
+        let msg = format!("'{}' is not a directory", arg.source);
+        return Err(msg);
+    }
+
+    if !Path::new(&arg.mountpoint).is_dir() {
+        let msg = format!("mount point '{}' is not a directory", arg.mountpoint);
+        return Err(msg);
+    }
+
+    Ok(arg)
+}
+
+fn mount<C: AeadInPlace>(arg: &Arg, cipher: &C) -> bool {
+    let mountpoint = Path::new(&arg.mountpoint);
+    let mount = match lib::Mount::new(mountpoint) {
+        Ok(m) => m,
+        Err(msg) => {
+            eprintln!("error: {msg}");
+            return false;
+        }
+    };
+
+    // unmount on Ctrl-C and the like, which makes `serve` return; the signals are blocked here
+    // (and in the threads spawned from here on) so only the waiting thread takes them
+    unsafe {
+        let mut signals: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::sigaddset(&mut signals, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+        let signals = signals;
+        let target = mountpoint.to_path_buf();
+        spawn(move || {
+            let mut signal = 0;
+            libc::sigwait(&signals, &mut signal);
+            if let Err(msg) = lib::unmount(&target) {
+                eprintln!("error: {msg}");
+            }
+        });
+    }
+
+    println!(
+        "info: '{}' mounted on '{}'; unmount it or press Ctrl-C to stop",
+        arg.source, arg.mountpoint
+    );
+    match mount.serve(Path::new(&arg.source), &arg.ext_name, cipher) {
+        Ok(_) => {
+            println!("info: '{}' unmounted", arg.mountpoint);
+            true
+        }
+        Err(msg) => {
+            eprintln!("error: {msg}");
+            let _ = lib::unmount(mountpoint);
+            false
+        }
+    }
+}
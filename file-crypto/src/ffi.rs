@@ -0,0 +1,445 @@
+// The C API, exported with the `cdylib` feature; see include/file_crypto.h.
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Cursor, ErrorKind};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use zeroize::Zeroize;
+
+use crate::{
+    decrypt, encrypt, new_aes128gcm_cipher, new_aes256gcm_cipher, SecretBox, SecretBuffer,
+};
+
+pub const FC_OK: c_int = 0;
+pub const FC_INVALID_ARGUMENT: c_int = 1;
+pub const FC_IO_ERROR: c_int = 2;
+pub const FC_CRYPTO_ERROR: c_int = 3;
+/// A bug in the library; the details are in `fc_last_error`.
+pub const FC_INTERNAL_ERROR: c_int = 4;
+
+#[allow(clippy::large_enum_variant)] // boxed as a whole by SecretBox
+enum Cipher {
+    Aes128Gcm(Aes128Gcm),
+    Aes256Gcm(Aes256Gcm),
+}
+
+pub struct FcContext {
+    cipher: SecretBox<Cipher>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+fn fail(code: c_int, msg: String) -> c_int {
+    set_last_error(msg);
+    code
+}
+
+// a panic must not unwind into the caller, which is undefined behaviour across `extern "C"`
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let msg = match payload.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => match payload.downcast_ref::<String>() {
+                    Some(s) => s.clone(),
+                    None => "unknown panic".to_string(),
+                },
+            };
+            set_last_error(format!("internal error: {msg}"));
+            on_panic
+        }
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, c_int> {
+    if s.is_null() {
+        return Err(fail(FC_INVALID_ARGUMENT, format!("{name} is null")));
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(s),
+        Err(e) => Err(fail(
+            FC_INVALID_ARGUMENT,
+            format!("{name} is not UTF-8; {e}"),
+        )),
+    }
+}
+
+/// The message of the last error on the calling thread, or null. Valid until the next call on
+/// the same thread.
+#[no_mangle]
+pub extern "C" fn fc_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|e| match e.borrow().as_ref() {
+            Some(msg) => msg.as_ptr(),
+            None => ptr::null(),
+        })
+    })
+}
+
+/// Creates a context holding the cipher derived from `key`; AES-256-GCM if `use_aes256gcm` is
+/// not 0, AES-128-GCM otherwise. Returns null on error.
+///
+/// # Safety
+/// `key` must be a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn fc_context_new(
+    key: *const c_char,
+    use_aes256gcm: c_int,
+) -> *mut FcContext {
+    guard(ptr::null_mut(), || {
+        let key = match str_arg(key, "key") {
+            Ok(k) => k,
+            Err(_) => return ptr::null_mut(),
+        };
+        if key.is_empty() {
+            set_last_error("empty secret key".to_string());
+            return ptr::null_mut();
+        }
+        let cipher = if use_aes256gcm != 0 {
+            Cipher::Aes256Gcm(new_aes256gcm_cipher(key))
+        } else {
+            Cipher::Aes128Gcm(new_aes128gcm_cipher(key))
+        };
+        let context = FcContext {
+            cipher: SecretBox::new(cipher),
+        };
+        Box::into_raw(Box::new(context))
+    })
+}
+
+/// # Safety
+/// `context` must be null or returned by `fc_context_new` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn fc_context_free(context: *mut FcContext) {
+    guard((), || {
+        if !context.is_null() {
+            drop(Box::from_raw(context));
+        }
+    })
+}
+
+/// Encrypts the file at `in_path` into `out_path`, which is created or replaced once done; on
+/// errors it's left as it was. Fails with `FC_INVALID_ARGUMENT` if both are the same file.
+///
+/// # Safety
+/// `context` must be returned by `fc_context_new`; the paths must be valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn fc_encrypt_file(
+    context: *const FcContext,
+    in_path: *const c_char,
+    out_path: *const c_char,
+) -> c_int {
+    guard(FC_INTERNAL_ERROR, || {
+        transform_file(context, in_path, out_path, true)
+    })
+}
+
+/// Decrypts the file at `in_path` into `out_path`, which is created or replaced once done; on
+/// errors it's left as it was. Fails with `FC_INVALID_ARGUMENT` if both are the same file.
+///
+/// # Safety
+/// `context` must be returned by `fc_context_new`; the paths must be valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn fc_decrypt_file(
+    context: *const FcContext,
+    in_path: *const c_char,
+    out_path: *const c_char,
+) -> c_int {
+    guard(FC_INTERNAL_ERROR, || {
+        transform_file(context, in_path, out_path, false)
+    })
+}
+
+unsafe fn transform_file(
+    context: *const FcContext,
+    in_path: *const c_char,
+    out_path: *const c_char,
+    encrypting: bool,
+) -> c_int {
+    let context = match context.as_ref() {
+        Some(c) => c,
+        None => return fail(FC_INVALID_ARGUMENT, "context is null".to_string()),
+    };
+    let (in_path, out_path) = match (str_arg(in_path, "in_path"), str_arg(out_path, "out_path")) {
+        (Ok(i), Ok(o)) => (i, o),
+        (Err(code), _) | (_, Err(code)) => return code,
+    };
+    let mut in_file = match File::open(in_path) {
+        Ok(f) => f,
+        Err(e) => {
+            return fail(
+                FC_IO_ERROR,
+                format!("cannot open input file '{in_path}'; {e}"),
+            )
+        }
+    };
+    // truncating the output would destroy the input
+    if let (Ok(i), Ok(o)) = (fs::canonicalize(in_path), fs::canonicalize(out_path)) {
+        if i == o {
+            return fail(
+                FC_INVALID_ARGUMENT,
+                format!("input and output are the same file '{in_path}'"),
+            );
+        }
+    }
+    // written next to the output and renamed over it on success, so a failure leaves no partial
+    // output behind; the name is new so no file of the user is truncated
+    let (tmp_path, mut out_file) = loop {
+        let tmp_path = format!("{out_path}.{:016x}.tmp", rand::random::<u64>());
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+        {
+            Ok(f) => break (tmp_path, f),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return fail(
+                    FC_IO_ERROR,
+                    format!("cannot open output file '{tmp_path}'; {e}"),
+                )
+            }
+        }
+    };
+    let result = match (&*context.cipher, encrypting) {
+        (Cipher::Aes128Gcm(c), true) => {
+            encrypt(&mut in_file, &mut out_file, c, &mut rand::thread_rng())
+        }
+        (Cipher::Aes256Gcm(c), true) => {
+            encrypt(&mut in_file, &mut out_file, c, &mut rand::thread_rng())
+        }
+        (Cipher::Aes128Gcm(c), false) => decrypt(&mut in_file, &mut out_file, c),
+        (Cipher::Aes256Gcm(c), false) => decrypt(&mut in_file, &mut out_file, c),
+    };
+    drop(out_file);
+    if let Err(msgs) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return fail(error_code(&msgs), msgs.join("; "));
+    }
+    match fs::rename(&tmp_path, out_path) {
+        Ok(_) => FC_OK,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            fail(
+                FC_IO_ERROR,
+                format!("cannot write output file '{out_path}'; {e}"),
+            )
+        }
+    }
+}
+
+/// Encrypts `len` bytes at `data`. On success `*out` and `*out_len` receive the result, which
+/// must be freed with `fc_buffer_free`.
+///
+/// # Safety
+/// `context` must be returned by `fc_context_new`; `data` must point to `len` readable bytes
+/// (or be null if `len` is 0); `out` and `out_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn fc_encrypt_buffer(
+    context: *const FcContext,
+    data: *const u8,
+    len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    guard(FC_INTERNAL_ERROR, || {
+        transform_buffer(context, data, len, out, out_len, true)
+    })
+}
+
+/// Decrypts `len` bytes at `data`. On success `*out` and `*out_len` receive the result, which
+/// must be freed with `fc_buffer_free`.
+///
+/// # Safety
+/// `context` must be returned by `fc_context_new`; `data` must point to `len` readable bytes
+/// (or be null if `len` is 0); `out` and `out_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn fc_decrypt_buffer(
+    context: *const FcContext,
+    data: *const u8,
+    len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    guard(FC_INTERNAL_ERROR, || {
+        transform_buffer(context, data, len, out, out_len, false)
+    })
+}
+
+unsafe fn transform_buffer(
+    context: *const FcContext,
+    data: *const u8,
+    len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+    encrypting: bool,
+) -> c_int {
+    let context = match context.as_ref() {
+        Some(c) => c,
+        None => return fail(FC_INVALID_ARGUMENT, "context is null".to_string()),
+    };
+    if (data.is_null() && len > 0) || out.is_null() || out_len.is_null() {
+        return fail(FC_INVALID_ARGUMENT, "null buffer".to_string());
+    }
+    let data = if len == 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(data, len)
+    };
+    let mut reader = Cursor::new(data);
+    // the plaintext is never longer than the encrypted data, so it's not reallocated (leaving
+    // copies behind) while written
+    let mut result_buf = match encrypting {
+        true => SecretBuffer::default(),
+        false => SecretBuffer::new(Vec::with_capacity(len)),
+    };
+    let result = match (&*context.cipher, encrypting) {
+        (Cipher::Aes128Gcm(c), true) => {
+            encrypt(&mut reader, &mut *result_buf, c, &mut rand::thread_rng())
+        }
+        (Cipher::Aes256Gcm(c), true) => {
+            encrypt(&mut reader, &mut *result_buf, c, &mut rand::thread_rng())
+        }
+        (Cipher::Aes128Gcm(c), false) => decrypt(&mut reader, &mut *result_buf, c),
+        (Cipher::Aes256Gcm(c), false) => decrypt(&mut reader, &mut *result_buf, c),
+    };
+    if let Err(msgs) = result {
+        return fail(error_code(&msgs), msgs.join("; "));
+    }
+    // copied into an allocation of the exact length fc_buffer_free takes; result_buf is wiped
+    let result_buf = Box::<[u8]>::from(&result_buf[..]);
+    *out_len = result_buf.len();
+    *out = Box::into_raw(result_buf) as *mut u8;
+    FC_OK
+}
+
+/// Wipes and frees a buffer returned by `fc_encrypt_buffer` or `fc_decrypt_buffer`.
+///
+/// # Safety
+/// `buf` must be null or returned along with `len` by one of the functions above, and not freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn fc_buffer_free(buf: *mut u8, len: usize) {
+    guard((), || {
+        if !buf.is_null() {
+            let mut buf = Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len));
+            buf.zeroize();
+        }
+    })
+}
+
+fn error_code(msgs: &[String]) -> c_int {
+    if msgs
+        .iter()
+        .any(|m| m.starts_with("read error") || m.starts_with("write error"))
+    {
+        FC_IO_ERROR
+    } else {
+        FC_CRYPTO_ERROR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        let key = CString::new("test").unwrap();
+        let raw_bytes: Vec<u8> = (0..10000).map(|_| rand::random()).collect();
+        unsafe {
+            let context = fc_context_new(key.as_ptr(), 1);
+            assert!(!context.is_null());
+
+            let (mut encrypted, mut encrypted_len) = (ptr::null_mut(), 0);
+            let code = fc_encrypt_buffer(
+                context,
+                raw_bytes.as_ptr(),
+                raw_bytes.len(),
+                &mut encrypted,
+                &mut encrypted_len,
+            );
+            assert_eq!(code, FC_OK);
+
+            let (mut decrypted, mut decrypted_len) = (ptr::null_mut(), 0);
+            let code = fc_decrypt_buffer(
+                context,
+                encrypted,
+                encrypted_len,
+                &mut decrypted,
+                &mut decrypted_len,
+            );
+            assert_eq!(code, FC_OK);
+            assert_eq!(
+                std::slice::from_raw_parts(decrypted, decrypted_len),
+                raw_bytes
+            );
+            fc_buffer_free(decrypted, decrypted_len);
+
+            *encrypted.add(100) ^= 1;
+            let code = fc_decrypt_buffer(
+                context,
+                encrypted,
+                encrypted_len,
+                &mut decrypted,
+                &mut decrypted_len,
+            );
+            assert_eq!(code, FC_CRYPTO_ERROR);
+            assert!(!CStr::from_ptr(fc_last_error()).to_bytes().is_empty());
+            fc_buffer_free(encrypted, encrypted_len);
+
+            let missing = CString::new("/nonexistent/file").unwrap();
+            let code = fc_encrypt_file(context, missing.as_ptr(), missing.as_ptr());
+            assert_eq!(code, FC_IO_ERROR);
+
+            let dir = std::env::temp_dir().join(format!("fc_ffi_{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = |name: &str| CString::new(dir.join(name).to_str().unwrap()).unwrap();
+            let (plain, encrypted, decrypted) = (path("plain"), path("encrypted"), path("out"));
+            std::fs::write(dir.join("plain"), &raw_bytes).unwrap();
+            let aliased = path("./plain");
+            let code = fc_encrypt_file(context, plain.as_ptr(), aliased.as_ptr());
+            assert_eq!(code, FC_INVALID_ARGUMENT);
+            assert_eq!(std::fs::read(dir.join("plain")).unwrap(), raw_bytes);
+            let code = fc_encrypt_file(context, plain.as_ptr(), encrypted.as_ptr());
+            assert_eq!(code, FC_OK);
+            // a failed decryption leaves no output, and no file of the user is overwritten
+            std::fs::write(dir.join("out.tmp"), b"mine").unwrap();
+            let code = fc_decrypt_file(context, plain.as_ptr(), decrypted.as_ptr());
+            assert_ne!(code, FC_OK);
+            assert!(!dir.join("out").exists());
+            assert_eq!(std::fs::read(dir.join("out.tmp")).unwrap(), b"mine");
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+            let code = fc_decrypt_file(context, encrypted.as_ptr(), decrypted.as_ptr());
+            assert_eq!(code, FC_OK);
+            assert_eq!(std::fs::read(dir.join("out")).unwrap(), raw_bytes);
+            std::fs::remove_dir_all(&dir).unwrap();
+
+            fc_context_free(context);
+            assert!(fc_context_new(ptr::null(), 0).is_null());
+        }
+    }
+
+    #[test]
+    fn test_guard() {
+        let code = guard(FC_INTERNAL_ERROR, || -> c_int { panic!("boom") });
+        assert_eq!(code, FC_INTERNAL_ERROR);
+        let msg = unsafe { CStr::from_ptr(fc_last_error()) };
+        assert_eq!(msg.to_str().unwrap(), "internal error: boom");
+        assert_eq!(guard(FC_INTERNAL_ERROR, || FC_OK), FC_OK);
+    }
+}
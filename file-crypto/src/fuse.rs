@@ -0,0 +1,635 @@
+// A read-only FUSE filesystem showing the decrypted files of a directory, served directly over
+// /dev/fuse (protocol 7.x, see linux/fuse.h). The encrypted files of the directory and its
+// subdirectories appear without the extension name; other files are hidden. Requests are served
+// one at a time, and reads only decrypt the chunks they cover.
+use std::collections::HashMap;
+use std::ffi::{c_int, c_void, CString, OsStr, OsString};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+
+use aes_gcm::AeadInPlace;
+
+use crate::RandomAccess;
+
+const FUSERMOUNT: [&str; 2] = ["fusermount3", "fusermount"];
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+const KERNEL_VERSION: u32 = 7;
+const KERNEL_MINOR_VERSION: u32 = 31;
+const IN_HEADER_SIZE: usize = 40;
+const OUT_HEADER_SIZE: usize = 16;
+const MAX_WRITE: usize = 128 * 1024;
+// how long the kernel may cache names and attributes, in seconds
+const TTL: u64 = 1;
+
+/// A mounted, empty FUSE filesystem, until it's served with `serve`.
+pub struct Mount {
+    device: File,
+}
+
+impl Mount {
+    /// Mounts a read-only FUSE filesystem on `mountpoint`. As root the kernel is asked directly,
+    /// otherwise through the setuid `fusermount3` (or `fusermount`) helper.
+    pub fn new(mountpoint: &Path) -> Result<Self, String> {
+        let target = c_path(mountpoint)?;
+        let device = match OpenOptions::new().read(true).write(true).open("/dev/fuse") {
+            Ok(f) => f,
+            Err(e) => {
+                let msg = format!("cannot open /dev/fuse; {e}");
+                return Err(msg);
+            }
+        };
+        let options = format!(
+            "fd={},rootmode=40000,user_id={},group_id={}",
+            device.as_raw_fd(),
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() }
+        );
+        let options = CString::new(options).unwrap();
+        let flags = libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV;
+        let result = unsafe {
+            libc::mount(
+                c"file-crypto".as_ptr(),
+                target.as_ptr(),
+                c"fuse.file-crypto".as_ptr(),
+                flags,
+                options.as_ptr() as *const c_void,
+            )
+        };
+        if result == 0 {
+            return Ok(Self { device });
+        }
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EPERM) {
+            let msg = format!("cannot mount '{}'; {e}", mountpoint.display());
+            return Err(msg);
+        }
+        let device = mount_with_fusermount(mountpoint)?;
+        Ok(Self { device })
+    }
+
+    /// Serves the decrypted view of the files named `*.{ext_name}` in the `source` directory
+    /// until the filesystem is unmounted.
+    pub fn serve<C: AeadInPlace>(
+        mut self,
+        source: &Path,
+        ext_name: &str,
+        cipher: &C,
+    ) -> Result<(), String> {
+        let mut fs = Filesystem {
+            source: source.to_path_buf(),
+            suffix: format!(".{ext_name}"),
+            cipher,
+            paths: vec![PathBuf::new()],
+            inodes: HashMap::from([(PathBuf::new(), 1)]),
+            sizes: HashMap::new(),
+            handles: HashMap::new(),
+            next_handle: 1,
+        };
+        let mut buf = vec![0u8; MAX_WRITE + 4096];
+        loop {
+            let len = match self.device.read(&mut buf) {
+                Ok(l) => l,
+                Err(e) => match e.raw_os_error() {
+                    Some(libc::ENODEV) => {
+                        return Ok(()); // unmounted
+                    }
+                    // ENOENT: the request was interrupted before it was read
+                    Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::ENOENT) => continue,
+                    _ => {
+                        let msg = format!("read error: {e}");
+                        return Err(msg);
+                    }
+                },
+            };
+            if len < IN_HEADER_SIZE {
+                let msg = format!("read error, illegal request length {len}");
+                return Err(msg);
+            }
+            let opcode = u32_at(&buf, 4);
+            let unique = u64_at(&buf, 8);
+            let node = u64_at(&buf, 16);
+            let reply = match fs.handle(opcode, node, &buf[IN_HEADER_SIZE..len]) {
+                Some(r) => r,
+                None => continue,
+            };
+            let (error, data) = match reply {
+                Ok(data) => (0, data),
+                Err(errno) => (-errno, vec![]),
+            };
+            let mut out = Vec::with_capacity(OUT_HEADER_SIZE + data.len());
+            out.extend_from_slice(&((OUT_HEADER_SIZE + data.len()) as u32).to_ne_bytes());
+            out.extend_from_slice(&error.to_ne_bytes());
+            out.extend_from_slice(&unique.to_ne_bytes());
+            out.extend_from_slice(&data);
+            match self.device.write(&out) {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {} // interrupted meanwhile
+                Err(e) if e.raw_os_error() == Some(libc::ENODEV) => {
+                    return Ok(());
+                }
+                Err(e) => {
+                    let msg = format!("write error: {e}");
+                    return Err(msg);
+                }
+            }
+        }
+    }
+}
+
+/// Unmounts the filesystem on `mountpoint`, which makes `Mount::serve` return. Files still open
+/// are detached rather than blocking the unmount.
+pub fn unmount(mountpoint: &Path) -> Result<(), String> {
+    let target = c_path(mountpoint)?;
+    if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EPERM) {
+        for program in FUSERMOUNT {
+            let status = Command::new(program)
+                .args(["-u", "-z", "--"])
+                .arg(mountpoint)
+                .status();
+            match status {
+                Ok(s) if s.success() => {
+                    return Ok(());
+                }
+                Ok(s) => {
+                    let msg = format!(
+                        "{program} failed to unmount '{}'; {s}",
+                        mountpoint.display()
+                    );
+                    return Err(msg);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    let msg = format!("cannot unmount '{}'; {e}", mountpoint.display());
+    Err(msg)
+}
+
+fn c_path(path: &Path) -> Result<CString, String> {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(p) => Ok(p),
+        Err(_) => Err(format!("illegal path '{}'", path.display())),
+    }
+}
+
+// fusermount mounts on behalf of an unprivileged user and passes the opened device back over the
+// socket named by _FUSE_COMMFD.
+fn mount_with_fusermount(mountpoint: &Path) -> Result<File, String> {
+    let mut fds = [0 as c_int; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) } != 0 {
+        let msg = format!("cannot create socket; {}", io::Error::last_os_error());
+        return Err(msg);
+    }
+    let (ours, theirs) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    for program in FUSERMOUNT {
+        let status = Command::new(program)
+            .args([
+                "-o",
+                "ro,nosuid,nodev,fsname=file-crypto,subtype=file-crypto",
+                "--",
+            ])
+            .arg(mountpoint)
+            .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+            .status();
+        match status {
+            Ok(s) if s.success() => {
+                return receive_fd(&ours);
+            }
+            Ok(s) => {
+                let msg = format!("{program} failed to mount '{}'; {s}", mountpoint.display());
+                return Err(msg);
+            }
+            Err(_) => {}
+        }
+    }
+    let msg = format!(
+        "cannot mount '{}'; not permitted and fusermount is not installed",
+        mountpoint.display()
+    );
+    Err(msg)
+}
+
+fn receive_fd(socket: &OwnedFd) -> Result<File, String> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut c_void,
+        iov_len: 1,
+    };
+    let mut control = [0u64; 8]; // room for one SCM_RIGHTS message, aligned for the header
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    if unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) } <= 0 {
+        return Err("fusermount didn't pass the FUSE device".to_string());
+    }
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    if cmsg.is_null() || unsafe { (*cmsg).cmsg_type } != libc::SCM_RIGHTS {
+        return Err("fusermount didn't pass the FUSE device".to_string());
+    }
+    let fd = unsafe { ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int) };
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_ne_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+struct Filesystem<'a, C> {
+    source: PathBuf,
+    suffix: String,
+    cipher: &'a C,
+    // the paths (relative to the source, with the suffix) of the inodes handed out, indexed by
+    // inode - 1; inodes are never forgotten
+    paths: Vec<PathBuf>,
+    inodes: HashMap<PathBuf, u64>,
+    // the plaintext sizes of the files, along with the size and mtime of the encrypted files
+    sizes: HashMap<u64, (u64, i64, i64, u64)>,
+    handles: HashMap<u64, RandomAccess<'a, File, C>>,
+    next_handle: u64,
+}
+
+type Reply = Result<Vec<u8>, i32>;
+
+impl<C: AeadInPlace> Filesystem<'_, C> {
+    // returns `None` for the requests which take no reply
+    fn handle(&mut self, opcode: u32, node: u64, body: &[u8]) -> Option<Reply> {
+        let reply = match opcode {
+            FUSE_INIT => self.init(body),
+            FUSE_LOOKUP => self.lookup(node, body),
+            FUSE_GETATTR => self.getattr(node),
+            FUSE_OPEN => self.open(node, body),
+            FUSE_READ => self.read(body),
+            FUSE_RELEASE => self.release(body),
+            FUSE_OPENDIR => self.opendir(node),
+            FUSE_READDIR => self.readdir(node, body),
+            FUSE_STATFS => Ok(statfs()),
+            FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_DESTROY => Ok(vec![]),
+            // requests are served in order, so there is nothing to interrupt
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            _ => Err(libc::ENOSYS),
+        };
+        Some(reply)
+    }
+
+    fn init(&mut self, body: &[u8]) -> Reply {
+        if body.len() < 16 {
+            return Err(libc::EINVAL);
+        }
+        let major = u32_at(body, 0);
+        let max_readahead = u32_at(body, 8);
+        if major < KERNEL_VERSION {
+            eprintln!("error: FUSE protocol {major} is not supported");
+            return Err(libc::EPROTO);
+        }
+        let mut out = Vec::with_capacity(64);
+        out.extend_from_slice(&KERNEL_VERSION.to_ne_bytes());
+        out.extend_from_slice(&KERNEL_MINOR_VERSION.to_ne_bytes());
+        out.extend_from_slice(&max_readahead.to_ne_bytes());
+        out.extend_from_slice(&0u32.to_ne_bytes()); // flags
+        out.extend_from_slice(&0u16.to_ne_bytes()); // max_background
+        out.extend_from_slice(&0u16.to_ne_bytes()); // congestion_threshold
+        out.extend_from_slice(&(MAX_WRITE as u32).to_ne_bytes());
+        out.extend_from_slice(&1u32.to_ne_bytes()); // time_gran
+        out.resize(64, 0);
+        Ok(out)
+    }
+
+    fn lookup(&mut self, parent: u64, body: &[u8]) -> Reply {
+        let name = OsStr::from_bytes(body.strip_suffix(b"\0").unwrap_or(body));
+        let dir = self.path(parent)?.to_path_buf();
+        let mut path = dir.join(name);
+        if !self.source.join(&path).is_dir() {
+            let mut file_name = name.to_os_string();
+            file_name.push(&self.suffix);
+            path = dir.join(file_name);
+            if !self.source.join(&path).is_file() {
+                return Err(libc::ENOENT);
+            }
+        }
+        let node = self.inode(path);
+        let mut out = Vec::with_capacity(128);
+        out.extend_from_slice(&node.to_ne_bytes());
+        out.extend_from_slice(&0u64.to_ne_bytes()); // generation
+        out.extend_from_slice(&TTL.to_ne_bytes()); // entry_valid
+        out.extend_from_slice(&TTL.to_ne_bytes()); // attr_valid
+        out.extend_from_slice(&[0u8; 8]); // entry_valid_nsec, attr_valid_nsec
+        self.encode_attr(node, &mut out)?;
+        Ok(out)
+    }
+
+    fn getattr(&mut self, node: u64) -> Reply {
+        let mut out = Vec::with_capacity(104);
+        out.extend_from_slice(&TTL.to_ne_bytes()); // attr_valid
+        out.extend_from_slice(&[0u8; 8]); // attr_valid_nsec, dummy
+        self.encode_attr(node, &mut out)?;
+        Ok(out)
+    }
+
+    fn open(&mut self, node: u64, body: &[u8]) -> Reply {
+        if body.len() < 8 {
+            return Err(libc::EINVAL);
+        }
+        if u32_at(body, 0) as c_int & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS);
+        }
+        let path = self.source.join(self.path(node)?);
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(e.raw_os_error().unwrap_or(libc::EIO));
+            }
+        };
+        let file = match RandomAccess::new(file, self.cipher) {
+            Ok(f) => f,
+            Err(msg) => {
+                eprintln!("warning: cannot open '{}'; {msg}", path.display());
+                return Err(libc::EIO);
+            }
+        };
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, file);
+        let mut out = Vec::with_capacity(16);
+        out.extend_from_slice(&handle.to_ne_bytes());
+        out.extend_from_slice(&[0u8; 8]); // open_flags, padding
+        Ok(out)
+    }
+
+    fn read(&mut self, body: &[u8]) -> Reply {
+        if body.len() < 24 {
+            return Err(libc::EINVAL);
+        }
+        let handle = u64_at(body, 0);
+        let offset = u64_at(body, 8);
+        let size = (u32_at(body, 16) as usize).min(MAX_WRITE);
+        let file = match self.handles.get_mut(&handle) {
+            Some(f) => f,
+            None => {
+                return Err(libc::EBADF);
+            }
+        };
+        let mut out = vec![0u8; size];
+        match file.read_at(offset, &mut out) {
+            Ok(len) => {
+                out.truncate(len);
+                Ok(out)
+            }
+            Err(msg) => {
+                eprintln!("warning: failed to read at offset {offset}; {msg}");
+                Err(libc::EIO)
+            }
+        }
+    }
+
+    fn release(&mut self, body: &[u8]) -> Reply {
+        if body.len() < 8 {
+            return Err(libc::EINVAL);
+        }
+        self.handles.remove(&u64_at(body, 0));
+        Ok(vec![])
+    }
+
+    fn opendir(&mut self, node: u64) -> Reply {
+        if !self.source.join(self.path(node)?).is_dir() {
+            return Err(libc::ENOTDIR);
+        }
+        Ok(vec![0u8; 16]) // fh, open_flags, padding
+    }
+
+    fn readdir(&mut self, node: u64, body: &[u8]) -> Reply {
+        if body.len() < 24 {
+            return Err(libc::EINVAL);
+        }
+        let offset = u64_at(body, 8) as usize;
+        let size = u32_at(body, 16) as usize;
+        let dir = self.path(node)?.to_path_buf();
+        let read_dir = match fs::read_dir(self.source.join(&dir)) {
+            Ok(r) => r,
+            Err(e) => {
+                return Err(e.raw_os_error().unwrap_or(libc::EIO));
+            }
+        };
+        let mut entries = vec![
+            (OsString::from("."), dir.clone(), true),
+            (
+                OsString::from(".."),
+                dir.parent().unwrap_or(&dir).to_path_buf(),
+                true,
+            ),
+        ];
+        let mut names = vec![];
+        for entry in read_dir.flatten() {
+            let name = entry.file_name();
+            let path = self.source.join(&dir).join(&name);
+            if path.is_dir() {
+                names.push((name.clone(), dir.join(&name), true));
+            } else if let Some(stem) = name.as_bytes().strip_suffix(self.suffix.as_bytes()) {
+                let stem = OsStr::from_bytes(stem);
+                // a directory of the same name hides the file, as with `lookup`
+                if !stem.is_empty() && path.is_file() && !self.source.join(&dir).join(stem).is_dir()
+                {
+                    names.push((stem.to_os_string(), dir.join(&name), false));
+                }
+            }
+        }
+        names.sort();
+        entries.extend(names);
+
+        let mut out = Vec::with_capacity(size);
+        for (index, (name, path, is_dir)) in entries.into_iter().enumerate().skip(offset) {
+            let name = name.as_bytes();
+            let entry_size = (24 + name.len()).next_multiple_of(8);
+            if out.len() + entry_size > size {
+                break;
+            }
+            let node = self.inode(path);
+            out.extend_from_slice(&node.to_ne_bytes());
+            out.extend_from_slice(&(index as u64 + 1).to_ne_bytes()); // offset of the next
+            out.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+            let kind = if is_dir { libc::DT_DIR } else { libc::DT_REG };
+            out.extend_from_slice(&(kind as u32).to_ne_bytes());
+            out.extend_from_slice(name);
+            out.resize(out.len().next_multiple_of(8), 0);
+        }
+        Ok(out)
+    }
+
+    fn path(&self, node: u64) -> Result<&Path, i32> {
+        match self.paths.get((node as usize).wrapping_sub(1)) {
+            Some(p) => Ok(p),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    fn inode(&mut self, path: PathBuf) -> u64 {
+        if let Some(node) = self.inodes.get(&path) {
+            return *node;
+        }
+        self.paths.push(path.clone());
+        let node = self.paths.len() as u64;
+        self.inodes.insert(path, node);
+        node
+    }
+
+    fn encode_attr(&mut self, node: u64, out: &mut Vec<u8>) -> Result<(), i32> {
+        let path = self.source.join(self.path(node)?);
+        let metadata = match fs::metadata(&path) {
+            Ok(m) => m,
+            Err(e) => {
+                return Err(e.raw_os_error().unwrap_or(libc::EIO));
+            }
+        };
+        let (size, kind, nlink) = if metadata.is_dir() {
+            (metadata.len(), libc::S_IFDIR, 2u32)
+        } else {
+            (
+                self.plaintext_size(node, &path, &metadata)?,
+                libc::S_IFREG,
+                1u32,
+            )
+        };
+        out.extend_from_slice(&node.to_ne_bytes());
+        out.extend_from_slice(&size.to_ne_bytes());
+        out.extend_from_slice(&size.div_ceil(512).to_ne_bytes()); // blocks
+        out.extend_from_slice(&metadata.atime().to_ne_bytes());
+        out.extend_from_slice(&metadata.mtime().to_ne_bytes());
+        out.extend_from_slice(&metadata.ctime().to_ne_bytes());
+        out.extend_from_slice(&(metadata.atime_nsec() as u32).to_ne_bytes());
+        out.extend_from_slice(&(metadata.mtime_nsec() as u32).to_ne_bytes());
+        out.extend_from_slice(&(metadata.ctime_nsec() as u32).to_ne_bytes());
+        out.extend_from_slice(&(kind | (metadata.mode() & 0o555)).to_ne_bytes());
+        out.extend_from_slice(&nlink.to_ne_bytes());
+        out.extend_from_slice(&metadata.uid().to_ne_bytes());
+        out.extend_from_slice(&metadata.gid().to_ne_bytes());
+        out.extend_from_slice(&0u32.to_ne_bytes()); // rdev
+        out.extend_from_slice(&4096u32.to_ne_bytes()); // blksize
+        out.extend_from_slice(&0u32.to_ne_bytes()); // flags
+        Ok(())
+    }
+
+    // the plaintext size takes scanning the record headers, so it's kept until the file changes
+    fn plaintext_size(&mut self, node: u64, path: &Path, metadata: &Metadata) -> Result<u64, i32> {
+        let stamp = (metadata.len(), metadata.mtime(), metadata.mtime_nsec());
+        if let Some((len, mtime, mtime_nsec, size)) = self.sizes.get(&node) {
+            if (*len, *mtime, *mtime_nsec) == stamp {
+                return Ok(*size);
+            }
+        }
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(e.raw_os_error().unwrap_or(libc::EIO));
+            }
+        };
+        let size = match RandomAccess::new(file, self.cipher) {
+            Ok(f) => f.len(),
+            Err(msg) => {
+                eprintln!("warning: cannot read '{}'; {msg}", path.display());
+                return Err(libc::EIO);
+            }
+        };
+        self.sizes.insert(node, (stamp.0, stamp.1, stamp.2, size));
+        Ok(size)
+    }
+}
+
+fn statfs() -> Vec<u8> {
+    let mut out = vec![0u8; 40]; // blocks, bfree, bavail, files, ffree
+    out.extend_from_slice(&4096u32.to_ne_bytes()); // bsize
+    out.extend_from_slice(&255u32.to_ne_bytes()); // namelen
+    out.extend_from_slice(&4096u32.to_ne_bytes()); // frsize
+    out.resize(80, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use std::thread::spawn;
+
+    use crate::{encrypt, new_aes128gcm_cipher, unmount, Mount};
+
+    // needs /dev/fuse and the permission to mount; skipped otherwise
+    #[test]
+    fn test_fuse() {
+        let scratch =
+            std::env::temp_dir().join(format!("file-crypto-test-{}", rand::random::<u64>()));
+        let source = scratch.join("source");
+        let mountpoint = scratch.join("mnt");
+        fs::create_dir_all(source.join("sub")).unwrap();
+        fs::create_dir(&mountpoint).unwrap();
+        let cipher = new_aes128gcm_cipher("test");
+        let raw_bytes: Vec<u8> = (0..100_000).map(|_| rand::random()).collect();
+        for name in ["a.log.enc", "sub/b.enc"] {
+            let mut out_file = File::create(source.join(name)).unwrap();
+            let mut rng = rand::thread_rng();
+            encrypt(&mut &raw_bytes[..], &mut out_file, &cipher, &mut rng).unwrap();
+        }
+        fs::write(source.join("plain.txt"), b"hidden").unwrap();
+
+        let mount = match Mount::new(&mountpoint) {
+            Ok(m) => m,
+            Err(msg) => {
+                eprintln!("skipped: {msg}");
+                fs::remove_dir_all(&scratch).unwrap();
+                return;
+            }
+        };
+        let served = source.clone();
+        let server = spawn(move || {
+            let cipher = new_aes128gcm_cipher("test");
+            mount.serve(&served, "enc", &cipher)
+        });
+
+        let mut names: Vec<_> = fs::read_dir(&mountpoint)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.log", "sub"]);
+        assert_eq!(fs::read(mountpoint.join("a.log")).unwrap(), raw_bytes);
+        assert_eq!(fs::read(mountpoint.join("sub/b")).unwrap(), raw_bytes);
+        let metadata = fs::metadata(mountpoint.join("sub/b")).unwrap();
+        assert_eq!(metadata.len(), raw_bytes.len() as u64);
+        assert!(fs::write(mountpoint.join("a.log"), b"x").is_err());
+        assert!(!PathBuf::from(&mountpoint).join("plain.txt").exists());
+
+        unmount(&mountpoint).unwrap();
+        server.join().unwrap().unwrap();
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+}
@@ -1,57 +1,178 @@
 use std::io::{Read, Write};
+use std::ops::Range;
 use std::thread::spawn;
 
 use aes_gcm::aes::cipher::Unsigned;
-use aes_gcm::{AeadInPlace, Aes128Gcm, Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::{AeadInPlace, Aes128Gcm, Aes256Gcm, KeyInit, Nonce, Tag};
 use crossbeam_channel::{Receiver, Sender};
-use rand::{CryptoRng, RngCore};
+use sha2::digest::generic_array::GenericArray;
 use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+pub use archive::{extract_archive, list_archive, pack_archive, ArchiveEntry};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+pub use fuse::{unmount, Mount};
+pub use memory::{armor, dearmor, decrypt_from_slice, encrypt_to_vec, is_armored};
+pub use nonce::{DeterministicNonce, NonceSource};
+pub use parity::{Parity, MAX_DATA_CHUNKS, MAX_PARITY_CHUNKS};
+pub use parts::{decrypt_parts, encrypt_parts, min_part_size, part_path};
+pub use random_access::RandomAccess;
+pub use resume::resume_encryption;
+pub use secret::{SecretBox, SecretBuffer, SecretString};
+pub use signature::{
+    encode_signing_key, encode_verifying_key, generate_signing_key, parse_signing_key,
+    parse_verifying_key,
+};
+pub use vectors::{format_test_vectors, parse_test_vectors, Algorithm, TestVector};
+
+use parity::{decode_info, encode_info, GroupReader, ParityEncoder, INFO_SIZE};
+use pool::{buffer_pool, BufferPool, BufferRecycler};
+use record::{
+    associated_data, decode_header, encode_header, read_header, PartTrailer, RecordWriter, Rewind,
+    HEADER_SIZE, KIND_DATA, KIND_PARITY_INFO, KIND_PART, KIND_SHA256, KIND_SIGNATURE, TRAILER_SIZE,
+};
+use resume::Resumed;
+use signature::SignatureChain;
+
+mod archive;
+#[cfg(feature = "cdylib")]
+mod ffi;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+mod fuse;
+mod memory;
+mod nonce;
+mod parity;
+mod parts;
+mod pool;
+mod random_access;
+mod record;
+mod resume;
+mod secret;
+mod signature;
+mod vectors;
 
 const CHUNK_SIZE: usize = 4096;
 
-struct CipherText {
-    nonce: Vec<u8>,
-    vec: Vec<u8>,
+#[derive(Clone, Debug, Default)]
+pub struct EncryptOptions {
+    /// Add parity records, so damaged chunks can be repaired when decrypting.
+    pub parity: Option<Parity>,
+    /// Sign the encrypted file, so the readers can tell who produced it, not only that it was
+    /// someone with the key.
+    pub signing_key: Option<SigningKey>,
+    /// Record the SHA-256 of the plaintext, which decrypting recomputes and compares.
+    pub record_sha256: bool,
 }
-impl CipherText {
-    fn len(&self) -> usize {
-        self.nonce.len() + self.vec.len()
-    }
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecryptOptions {
+    /// Write zeros in place of the chunks which are damaged beyond repair instead of failing.
+    pub best_effort: bool,
+    /// Fail unless the file is signed with this key. Signatures are verified either way.
+    pub verify_signer: Option<VerifyingKey>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DecryptReport {
+    /// The number of damaged chunks which were repaired with the parity records.
+    pub repaired_chunks: u64,
+    /// The ranges of the plaintext which were zero-filled in best-effort mode.
+    pub damaged: Vec<Range<u64>>,
+    /// The key which signed the file, if signed.
+    pub signer: Option<VerifyingKey>,
+    /// The SHA-256 of the plaintext written.
+    pub sha256: [u8; 32],
+    /// Whether the file records the SHA-256 of its plaintext (which `sha256` matches).
+    pub sha256_recorded: bool,
 }
 
-pub fn encrypt<R, W, C, RNG>(
+pub fn encrypt<R, W, C, N>(
     reader: &mut R,
     writer: &mut W,
     cipher: &C,
-    rng: &mut RNG,
+    nonce_source: &mut N,
 ) -> Result<(), Vec<String>>
 where
     R: Read,
     W: Write,
     C: AeadInPlace,
-    RNG: CryptoRng + RngCore,
+    N: NonceSource,
+{
+    encrypt_with_options(
+        reader,
+        writer,
+        cipher,
+        nonce_source,
+        &EncryptOptions::default(),
+    )
+}
+
+pub fn encrypt_with_options<R, W, C, N>(
+    reader: &mut R,
+    writer: &mut W,
+    cipher: &C,
+    nonce_source: &mut N,
+    options: &EncryptOptions,
+) -> Result<(), Vec<String>>
+where
+    R: Read,
+    W: Write,
+    C: AeadInPlace,
+    N: NonceSource,
+{
+    encrypt_records(reader, writer, cipher, nonce_source, options, None, None)
+}
+
+fn encrypt_records<R, O, C, N>(
+    reader: &mut R,
+    writer: &mut O,
+    cipher: &C,
+    nonce_source: &mut N,
+    options: &EncryptOptions,
+    part_size: Option<u64>,
+    resumed: Option<Resumed>,
+) -> Result<(), Vec<String>>
+where
+    R: Read,
+    O: RecordWriter,
+    C: AeadInPlace,
+    N: NonceSource,
 {
     let (plaintext_tx, plaintext_rx) = crossbeam_channel::unbounded();
     let (ciphertext_tx, ciphertext_rx) = crossbeam_channel::unbounded();
 
+    let nonce_size = C::NonceSize::to_usize();
+    let tag_size = C::TagSize::to_usize();
+    let (pool, recycler) = buffer_pool(HEADER_SIZE + nonce_size + CHUNK_SIZE + tag_size);
+
     let ptr = reader as *mut R as usize;
     let h1 = spawn(move || {
         let reader = unsafe { (ptr as *mut R).as_mut() }.unwrap();
-        produce_plaintext(reader, plaintext_tx)
+        produce_plaintext(reader, pool, plaintext_tx, HEADER_SIZE + nonce_size)
     });
 
     let ptr1 = cipher as *const C as usize;
-    let ptr2 = rng as *mut RNG as usize;
+    let ptr2 = nonce_source as *mut N as usize;
+    let options = options.clone();
     let h2 = spawn(move || {
         let cipher = unsafe { (ptr1 as *const C).as_ref() }.unwrap();
-        let rng = unsafe { (ptr2 as *mut RNG).as_mut() }.unwrap();
-        do_encrypt(cipher, rng, plaintext_rx, ciphertext_tx)
+        let nonce_source = unsafe { (ptr2 as *mut N).as_mut() }.unwrap();
+        let mut sender = RecordSender::new(ciphertext_tx, part_size, trailer_size::<C>() as u64);
+        do_encrypt(
+            cipher,
+            nonce_source,
+            plaintext_rx,
+            &mut sender,
+            &options,
+            resumed,
+        )
     });
 
-    let ptr = writer as *mut W as usize;
+    let ptr = writer as *mut O as usize;
     let h3 = spawn(move || {
-        let writer = unsafe { (ptr as *mut W).as_mut() }.unwrap();
-        consume_ciphertext(writer, ciphertext_rx)
+        let writer = unsafe { (ptr as *mut O).as_mut() }.unwrap();
+        consume_ciphertext(writer, ciphertext_rx, recycler)
     });
 
     let r1 = h1.join().unwrap();
@@ -84,18 +205,49 @@ where
     }
 }
 
-fn produce_plaintext<R: Read>(reader: &mut R, plaintext_tx: Sender<Vec<u8>>) -> Result<(), String> {
+// The size of the record holding a part trailer.
+fn trailer_size<C: AeadInPlace>() -> usize {
+    HEADER_SIZE + C::NonceSize::to_usize() + TRAILER_SIZE + C::TagSize::to_usize()
+}
+
+// The buffers sent to the encryptor are laid out as the records to be written: a placeholder for
+// the header and the nonce followed by the plaintext; the encryptor fills in the placeholder and
+// appends the tag. Chunks are filled up, so only the last one may be short.
+fn produce_plaintext<R: Read>(
+    reader: &mut R,
+    mut pool: BufferPool,
+    plaintext_tx: Sender<SecretBuffer>,
+    offset: usize,
+) -> Result<(), String> {
     loop {
-        let mut buf = vec![0u8; CHUNK_SIZE];
-        match reader.read(&mut buf) {
-            Ok(len) => {
-                buf.truncate(len);
+        let mut buf = match pool.take() {
+            Some(b) => b,
+            None => {
+                return Ok(()); // consumer stopped; error occurred
             }
-            Err(e) => {
-                let msg = format!("read error: {e}");
-                return Err(msg);
+        };
+        buf.resize(offset + CHUNK_SIZE, 0);
+        let mut len = 0;
+        while len < CHUNK_SIZE {
+            match reader.read(&mut buf[offset + len..]) {
+                Ok(0) => {
+                    break;
+                }
+                Ok(n) => {
+                    len += n;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let msg = format!("read error: {e}");
+                    return Err(msg);
+                }
             }
         }
+        if len == 0 {
+            buf.clear();
+        } else {
+            buf.truncate(offset + len);
+        }
         let eof = buf.is_empty();
         match plaintext_tx.send(buf) {
             Ok(_) => {
@@ -110,12 +262,122 @@ fn produce_plaintext<R: Read>(reader: &mut R, plaintext_tx: Sender<Vec<u8>>) ->
     }
 }
 
-fn do_encrypt<C: AeadInPlace, RNG: CryptoRng + RngCore>(
+// Sends the records to the writer thread, ending the parts of split archives with trailers on
+// the way. The methods return `false` if the writer thread has stopped.
+struct RecordSender {
+    ciphertext_tx: Sender<Option<SecretBuffer>>,
+    tag_chain: Sha256,
+    signature_chain: SignatureChain,
+    part_size: Option<u64>,
+    part_index: u32,
+    part_len: u64,
+    trailer_len: u64,
+}
+impl RecordSender {
+    fn new(
+        ciphertext_tx: Sender<Option<SecretBuffer>>,
+        part_size: Option<u64>,
+        trailer_len: u64,
+    ) -> Self {
+        Self {
+            ciphertext_tx,
+            tag_chain: Sha256::new(),
+            signature_chain: SignatureChain::new(),
+            part_size,
+            part_index: 0,
+            part_len: 0,
+            trailer_len,
+        }
+    }
+
+    // `tag` is the tag of an authenticated record, which goes into the chains of the trailers and
+    // of the signature
+    fn send<C: AeadInPlace, N: NonceSource>(
+        &mut self,
+        cipher: &C,
+        nonce_source: &mut N,
+        buf: SecretBuffer,
+        tag: Option<&[u8]>,
+    ) -> Result<bool, String> {
+        if let Some(part_size) = self.part_size {
+            let record_len = buf.len() as u64;
+            let full = self.part_len + record_len + self.trailer_len > part_size;
+            if self.part_len > 0 && full && !self.send_trailer(cipher, nonce_source, false)? {
+                return Ok(false);
+            }
+            self.part_len += record_len;
+        }
+        if let Some(tag) = tag {
+            self.tag_chain.update(tag);
+            let (header, body) = buf.split_at(HEADER_SIZE);
+            self.signature_chain.update(header, body);
+        }
+        Ok(self.ciphertext_tx.send(Some(buf)).is_ok())
+    }
+
+    fn send_trailer<C: AeadInPlace, N: NonceSource>(
+        &mut self,
+        cipher: &C,
+        nonce_source: &mut N,
+        last: bool,
+    ) -> Result<bool, String> {
+        let trailer = PartTrailer {
+            index: self.part_index,
+            last,
+            chain: self.tag_chain.clone().finalize().into(),
+        };
+        let mut trailer_buf = SecretBuffer::default();
+        let trailer_tag = seal_record(
+            cipher,
+            nonce_source,
+            KIND_PART,
+            &trailer.encode(),
+            &mut trailer_buf,
+        )?;
+        self.tag_chain.update(&trailer_tag);
+        let (header, body) = trailer_buf.split_at(HEADER_SIZE);
+        self.signature_chain.update(header, body);
+        self.part_index += 1;
+        self.part_len = 0;
+        Ok(self.ciphertext_tx.send(Some(trailer_buf)).is_ok())
+    }
+
+    fn send_eof(&mut self) {
+        let _ = self.ciphertext_tx.send(None); // indicates consumer about eof
+    }
+}
+
+fn do_encrypt<C: AeadInPlace, N: NonceSource>(
     cipher: &C,
-    rng: &mut RNG,
-    plaintext_rx: Receiver<Vec<u8>>,
-    ciphertext_tx: Sender<Option<CipherText>>,
+    nonce_source: &mut N,
+    plaintext_rx: Receiver<SecretBuffer>,
+    sender: &mut RecordSender,
+    options: &EncryptOptions,
+    resumed: Option<Resumed>,
 ) -> Result<(), String> {
+    let offset = HEADER_SIZE + C::NonceSize::to_usize();
+    let shard_size = C::NonceSize::to_usize() + CHUNK_SIZE + C::TagSize::to_usize();
+    let mut parity_encoder = options.parity.map(|p| ParityEncoder::new(p, shard_size));
+    let mut sha256 = options.record_sha256.then(Sha256::new);
+    if let Some(resumed) = resumed {
+        // the parity info record is written already
+        if let Some(encoder) = parity_encoder.as_mut() {
+            for body in &resumed.group {
+                encoder.push(body);
+            }
+        }
+        if let Some(sha256) = sha256.as_mut() {
+            *sha256 = resumed.sha256;
+        }
+        sender.signature_chain = resumed.signature_chain;
+    } else if let Some(parity) = options.parity {
+        let mut buf = SecretBuffer::default();
+        buf.extend_from_slice(&encode_header(KIND_PARITY_INFO, INFO_SIZE));
+        buf.extend_from_slice(&encode_info(&parity));
+        if !sender.send(cipher, nonce_source, buf, None)? {
+            return Ok(()); // consumer stopped; error occurred
+        }
+    }
     loop {
         let mut buf = match plaintext_rx.recv() {
             Ok(b) => b,
@@ -125,34 +387,100 @@ fn do_encrypt<C: AeadInPlace, RNG: CryptoRng + RngCore>(
         };
 
         if buf.is_empty() {
-            let _ = ciphertext_tx.send(None); // indicates consumer about eof
+            if let Some(encoder) = parity_encoder.as_mut() {
+                for parity_buf in encoder.finish_group() {
+                    if !sender.send(cipher, nonce_source, parity_buf, None)? {
+                        return Ok(()); // consumer stopped; error occurred
+                    }
+                }
+            }
+            if let Some(sha256) = sha256.take() {
+                let mut buf = SecretBuffer::default();
+                let digest = sha256.finalize();
+                let tag = seal_record(cipher, nonce_source, KIND_SHA256, &digest, &mut buf)?;
+                if !sender.send(cipher, nonce_source, buf, Some(&tag))? {
+                    return Ok(()); // consumer stopped; error occurred
+                }
+            }
+            if let Some(key) = &options.signing_key {
+                let signature = sender.signature_chain.sign(key);
+                let mut buf = SecretBuffer::default();
+                let tag = seal_record(cipher, nonce_source, KIND_SIGNATURE, &signature, &mut buf)?;
+                if !sender.send(cipher, nonce_source, buf, Some(&tag))? {
+                    return Ok(()); // consumer stopped; error occurred
+                }
+            }
+            if sender.part_size.is_some() && !sender.send_trailer(cipher, nonce_source, true)? {
+                return Ok(()); // consumer stopped; error occurred
+            }
+            sender.send_eof();
             return Ok(());
         }
 
-        let nonce = C::generate_nonce(&mut *rng);
-        match cipher.encrypt_in_place(&nonce, b"", &mut buf) {
-            Ok(_) => {}
-            Err(e) => {
-                let msg = format!("encrypt error: {e}");
-                return Err(msg);
-            }
+        if let Some(sha256) = sha256.as_mut() {
+            sha256.update(&buf[offset..]);
         }
-
-        match ciphertext_tx.send(Some(CipherText {
-            nonce: nonce.to_vec(),
-            vec: buf,
-        })) {
-            Ok(_) => {}
-            Err(_) => {
-                return Ok(()); // consumer stopped; error occurred
+        let tag = seal_in_place(cipher, nonce_source, KIND_DATA, &mut buf)?;
+        let group_complete = match parity_encoder.as_mut() {
+            Some(encoder) => encoder.push(&buf[HEADER_SIZE..]),
+            None => false,
+        };
+        if !sender.send(cipher, nonce_source, buf, Some(&tag))? {
+            return Ok(()); // consumer stopped; error occurred
+        }
+        if group_complete {
+            for parity_buf in parity_encoder.as_mut().unwrap().finish_group() {
+                if !sender.send(cipher, nonce_source, parity_buf, None)? {
+                    return Ok(()); // consumer stopped; error occurred
+                }
             }
         }
     }
 }
 
-fn consume_ciphertext<W: Write>(
-    writer: &mut W,
-    ciphertext_rx: Receiver<Option<CipherText>>,
+// Encrypts a record whose buffer begins with the placeholder for the header and the nonce.
+fn seal_in_place<C: AeadInPlace, N: NonceSource>(
+    cipher: &C,
+    nonce_source: &mut N,
+    kind: u8,
+    buf: &mut SecretBuffer,
+) -> Result<Tag<C::TagSize>, String> {
+    let nonce_size = C::NonceSize::to_usize();
+    let body_size = buf.len() - HEADER_SIZE + C::TagSize::to_usize();
+    buf[..HEADER_SIZE].copy_from_slice(&encode_header(kind, body_size));
+    let (header, rest) = buf.split_at_mut(HEADER_SIZE);
+    let (nonce_buf, plaintext) = rest.split_at_mut(nonce_size);
+    let nonce = nonce_source.next_nonce::<C>(plaintext);
+    nonce_buf.copy_from_slice(&nonce);
+    let aad = associated_data(header);
+    let tag = match cipher.encrypt_in_place_detached(&nonce, aad, plaintext) {
+        Ok(t) => t,
+        Err(e) => {
+            let msg = format!("encrypt error: {e}");
+            return Err(msg);
+        }
+    };
+    buf.extend_from_slice(&tag);
+    Ok(tag)
+}
+
+fn seal_record<C: AeadInPlace, N: NonceSource>(
+    cipher: &C,
+    nonce_source: &mut N,
+    kind: u8,
+    plaintext: &[u8],
+    buf: &mut SecretBuffer,
+) -> Result<Tag<C::TagSize>, String> {
+    buf.clear();
+    buf.resize(HEADER_SIZE + C::NonceSize::to_usize(), 0);
+    buf.extend_from_slice(plaintext);
+    seal_in_place(cipher, nonce_source, kind, buf)
+}
+
+fn consume_ciphertext<O: RecordWriter>(
+    writer: &mut O,
+    ciphertext_rx: Receiver<Option<SecretBuffer>>,
+    recycler: BufferRecycler,
 ) -> Result<(), String> {
     loop {
         let ciphertext = match ciphertext_rx.recv() {
@@ -169,32 +497,50 @@ fn consume_ciphertext<W: Write>(
             Some(c) => c,
         };
 
-        let len = u32::try_from(ciphertext.len()).unwrap().to_le_bytes();
-        match writer.write_all(&len) {
-            Ok(_) => {}
-            Err(e) => {
-                let msg = format!("write error: {e}");
-                return Err(msg);
-            }
-        }
-        match writer.write_all(&ciphertext.nonce) {
-            Ok(_) => {}
-            Err(e) => {
-                let msg = format!("write error: {e}");
-                return Err(msg);
-            }
-        }
-        match writer.write_all(&ciphertext.vec) {
+        match writer.write_record(&ciphertext) {
             Ok(_) => {}
             Err(e) => {
                 let msg = format!("write error: {e}");
                 return Err(msg);
             }
         }
+        recycler.recycle(ciphertext);
     }
 }
 
 pub fn decrypt<R, W, C>(reader: &mut R, writer: &mut W, cipher: &C) -> Result<(), Vec<String>>
+where
+    R: Read,
+    W: Write,
+    C: AeadInPlace,
+{
+    decrypt_with_options(reader, writer, cipher, &DecryptOptions::default()).map(|_| ())
+}
+
+/// Decrypts like `decrypt`. Damaged chunks of files with parity are repaired, and with
+/// `best_effort` the ones which can't be repaired are zero-filled and reported.
+pub fn decrypt_with_options<R, W, C>(
+    reader: &mut R,
+    writer: &mut W,
+    cipher: &C,
+    options: &DecryptOptions,
+) -> Result<DecryptReport, Vec<String>>
+where
+    R: Read,
+    W: Write,
+    C: AeadInPlace,
+{
+    decrypt_records(reader, writer, cipher, options, false)
+}
+
+// With `split`, the records must end with the trailer of the last part.
+pub(crate) fn decrypt_records<R, W, C>(
+    reader: &mut R,
+    writer: &mut W,
+    cipher: &C,
+    options: &DecryptOptions,
+    split: bool,
+) -> Result<DecryptReport, Vec<String>>
 where
     R: Read,
     W: Write,
@@ -203,95 +549,160 @@ where
     let (ciphertext_tx, ciphertext_rx) = crossbeam_channel::unbounded();
     let (plaintext_tx, plaintext_rx) = crossbeam_channel::unbounded();
 
-    let ptr = reader as *mut R as usize;
     let nonce_size = C::NonceSize::to_usize();
     let tag_size = C::TagSize::to_usize();
+    let (pool, recycler) = buffer_pool(HEADER_SIZE + nonce_size + CHUNK_SIZE + tag_size);
+
+    let ptr = reader as *mut R as usize;
     let h1 = spawn(move || {
         let reader = unsafe { (ptr as *mut R).as_mut() }.unwrap();
-        produce_ciphertext(reader, ciphertext_tx, nonce_size, tag_size)
+        produce_ciphertext(reader, pool, ciphertext_tx, nonce_size, tag_size)
     });
 
     let ptr = cipher as *const C as usize;
+    let best_effort = options.best_effort;
+    let verify_signer = options.verify_signer;
     let h2 = spawn(move || {
         let cipher = unsafe { (ptr as *const C).as_ref() }.unwrap();
-        do_decrypt(cipher, ciphertext_rx, plaintext_tx)
+        do_decrypt(
+            cipher,
+            ciphertext_rx,
+            plaintext_tx,
+            best_effort,
+            verify_signer,
+            split,
+        )
     });
 
     let ptr = writer as *mut W as usize;
     let h3 = spawn(move || {
         let writer = unsafe { (ptr as *mut W).as_mut() }.unwrap();
-        consume_plaintext(writer, plaintext_rx)
+        consume_plaintext(writer, plaintext_rx, recycler, HEADER_SIZE + nonce_size)
     });
 
     let r1 = h1.join().unwrap();
     let r2 = h2.join().unwrap();
     let r3 = h3.join().unwrap();
 
+    let mut report = DecryptReport::default();
     let mut msg_vec = vec![];
     match r1 {
-        Ok(_) => {}
+        Ok(repaired) => {
+            report.repaired_chunks = repaired;
+        }
         Err(msg) => {
             msg_vec.push(msg);
         }
     }
+    let mut recorded_sha256 = None;
     match r2 {
-        Ok(_) => {}
+        Ok(decrypted) => {
+            report.damaged = decrypted.damaged;
+            report.signer = decrypted.signer;
+            recorded_sha256 = decrypted.sha256;
+        }
         Err(msg) => {
             msg_vec.push(msg);
         }
     }
     match r3 {
-        Ok(_) => {}
+        Ok(sha256) => {
+            report.sha256 = sha256;
+        }
         Err(msg) => {
             msg_vec.push(msg);
         }
     }
+    if let Some(recorded) = recorded_sha256 {
+        // zero-filled chunks can't match
+        if recorded == report.sha256 {
+            report.sha256_recorded = true;
+        } else if report.damaged.is_empty() && msg_vec.is_empty() {
+            msg_vec.push("the plaintext doesn't match its recorded SHA-256".to_string());
+        }
+    }
     if msg_vec.is_empty() {
-        Ok(())
+        Ok(report)
     } else {
         Err(msg_vec)
     }
 }
 
+// Reads the records; the records of files with parity are held back group by group and repaired
+// before they are passed on. Returns the number of repaired records.
 fn produce_ciphertext<R: Read>(
     reader: &mut R,
-    ciphertext_tx: Sender<Option<CipherText>>,
+    mut pool: BufferPool,
+    ciphertext_tx: Sender<Option<SecretBuffer>>,
     nonce_size: usize,
     tag_size: usize,
-) -> Result<(), String> {
+) -> Result<u64, String> {
+    let mut reader = Rewind::new(reader);
+    let mut group_reader: Option<GroupReader> = None;
+    let mut first = true;
     loop {
-        let mut len_buf = vec![0u8; 4];
-        let len = match reader.read(&mut len_buf) {
-            Ok(len) => {
-                if len == 0 {
-                    let _ = ciphertext_tx.send(None);
-                    return Ok(());
+        let header = match read_header(&mut reader)? {
+            None => {
+                if let Some(group_reader) = group_reader.as_mut() {
+                    for buf in group_reader.finish(&mut pool) {
+                        if ciphertext_tx.send(Some(buf)).is_err() {
+                            return Ok(0); // decryptor stopped; error occurred
+                        }
+                    }
                 }
-                if len != 4 {
-                    let msg = format!("read error, illegal length {len}");
+                let _ = ciphertext_tx.send(None);
+                return Ok(group_reader.map_or(0, |g| g.repaired));
+            }
+            Some(h) => h,
+        };
+        if first && decode_header(&header) == (KIND_PARITY_INFO, INFO_SIZE) {
+            let mut info = [0u8; INFO_SIZE];
+            match reader.read_exact(&mut info) {
+                Ok(_) => {}
+                Err(e) => {
+                    let msg = format!("read error: {e}");
                     return Err(msg);
                 }
-                let mut bytes = [0u8; 4];
-                bytes.copy_from_slice(&len_buf);
-                (u32::from_le_bytes(bytes)) as usize
             }
-            Err(e) => {
-                let msg = format!("read error: {e}");
-                return Err(msg);
+            let parity = match decode_info(&info) {
+                Some(p) => p,
+                None => {
+                    return Err("damaged parity info".to_string());
+                }
+            };
+            let shard_size = nonce_size + CHUNK_SIZE + tag_size;
+            group_reader = Some(GroupReader::new(parity, shard_size, nonce_size + tag_size));
+            first = false;
+            continue;
+        }
+        first = false;
+
+        let (kind, len, mut damaged) = match group_reader.as_ref() {
+            Some(g) => g.frame(&header),
+            None => {
+                let (kind, len) = decode_header(&header);
+                (kind, len, false)
             }
         };
+        if len <= tag_size + nonce_size {
+            let msg = format!("read error, illegal record length {len}");
+            return Err(msg);
+        }
 
-        assert!(len > tag_size + nonce_size);
-        let mut nonce = vec![0u8; nonce_size];
-        match reader.read_exact(&mut nonce) {
-            Ok(_) => {}
-            Err(e) => {
-                let msg = format!("read error: {e}");
-                return Err(msg);
+        let holding = group_reader.as_ref().is_some_and(|g| g.is_holding());
+        let mut buf = if holding {
+            pool.take_now()
+        } else {
+            match pool.take() {
+                Some(b) => b,
+                None => {
+                    return Ok(0); // consumer stopped; error occurred
+                }
             }
-        }
-        let mut buf = vec![0u8; len - nonce_size];
-        match reader.read_exact(&mut buf) {
+        };
+        buf.resize(HEADER_SIZE + len, 0);
+        buf[..HEADER_SIZE].copy_from_slice(&header);
+        match reader.read_exact(&mut buf[HEADER_SIZE..]) {
             Ok(_) => {}
             Err(e) => {
                 let msg = format!("read error: {e}");
@@ -299,98 +710,311 @@ fn produce_ciphertext<R: Read>(
             }
         }
 
-        match ciphertext_tx.send(Some(CipherText { nonce, vec: buf })) {
-            Ok(_) => {}
-            Err(_) => {
-                return Ok(()); // decryptor stopped; error occurred
+        let group_reader = match group_reader.as_mut() {
+            None => {
+                if ciphertext_tx.send(Some(buf)).is_err() {
+                    return Ok(0); // decryptor stopped; error occurred
+                }
+                continue;
+            }
+            Some(g) => g,
+        };
+        let full_size = nonce_size + CHUNK_SIZE + tag_size;
+        if kind == KIND_DATA && !damaged && len < full_size {
+            // only the last data record is short; if the next record doesn't look like the
+            // parity following it, the length was damaged
+            if let Some(next) = read_header(&mut reader)? {
+                reader.unread(&next);
+                if !group_reader.may_follow_last(&next) {
+                    buf.resize(HEADER_SIZE + full_size, 0);
+                    match reader.read_exact(&mut buf[HEADER_SIZE + len..]) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            let msg = format!("read error: {e}");
+                            return Err(msg);
+                        }
+                    }
+                    damaged = true;
+                }
+            }
+        }
+        for buf in group_reader.push(kind, buf, damaged, &mut pool) {
+            if ciphertext_tx.send(Some(buf)).is_err() {
+                return Ok(0); // decryptor stopped; error occurred
             }
         }
     }
 }
 
+#[derive(Default)]
+struct Decrypted {
+    // the plaintext ranges which were zero-filled in best-effort mode
+    damaged: Vec<Range<u64>>,
+    signer: Option<VerifyingKey>,
+    sha256: Option<[u8; 32]>,
+}
+
+// Decrypts the records in place; the buffers sent to the consumer still begin with the header and
+// the nonce. The trailers of split archives and the signature are verified and passed on without
+// payload, as is the recorded SHA-256 of the plaintext, which is left to the caller to compare.
 fn do_decrypt<C: AeadInPlace>(
     cipher: &C,
-    ciphertext_rx: Receiver<Option<CipherText>>,
-    plaintext_tx: Sender<Vec<u8>>,
-) -> Result<(), String> {
+    ciphertext_rx: Receiver<Option<SecretBuffer>>,
+    plaintext_tx: Sender<SecretBuffer>,
+    best_effort: bool,
+    verify_signer: Option<VerifyingKey>,
+    split: bool,
+) -> Result<Decrypted, String> {
+    let offset = HEADER_SIZE + C::NonceSize::to_usize();
+    let tag_size = C::TagSize::to_usize();
+    let mut tag_chain = Sha256::new();
+    let mut signature_chain = SignatureChain::new();
+    let mut signed = false;
+    let mut next_part = 0u32;
+    let mut last_part_seen = false;
+    let mut plaintext_len = 0u64;
+    let mut decrypted = Decrypted::default();
+    let mut chain_broken = false; // the chain can't tell anything once a record is lost
     loop {
         let ciphertext = match ciphertext_rx.recv() {
             Ok(c) => c,
             Err(_) => {
-                return Ok(()); // producer stopped; error occurred
+                return Ok(decrypted); // producer stopped; error occurred
             }
         };
 
-        let mut ciphertext = match ciphertext {
+        let mut buf = match ciphertext {
             None => {
-                let _ = plaintext_tx.send(vec![]); // indicates consumer about eof
-                return Ok(());
+                if (split || next_part > 0) && !last_part_seen && !chain_broken {
+                    let msg = match next_part {
+                        0 => "the trailer of part 1 is missing".to_string(),
+                        n => format!("missing part(s) after part {n}"),
+                    };
+                    return Err(msg);
+                }
+                if let Some(expected) = verify_signer {
+                    match decrypted.signer {
+                        Some(key) if key == expected => {}
+                        Some(key) => {
+                            let msg = format!(
+                                "signed by {} instead of the expected signer",
+                                encode_verifying_key(&key)
+                            );
+                            return Err(msg);
+                        }
+                        None if signed => {
+                            let msg = "the signature can't be verified as some chunks are damaged";
+                            return Err(msg.to_string());
+                        }
+                        None => {
+                            return Err("the file is not signed".to_string());
+                        }
+                    }
+                }
+                let _ = plaintext_tx.send(SecretBuffer::default()); // indicates consumer about eof
+                return Ok(decrypted);
             }
             Some(c) => c,
         };
 
-        match cipher.decrypt_in_place(
-            Nonce::from_slice(&ciphertext.nonce),
-            b"",
-            &mut ciphertext.vec,
-        ) {
-            Ok(_) => {}
-            Err(e) => {
-                let msg = format!("decrypt error: {e}");
+        if last_part_seen {
+            return Err("unexpected data after the last part".to_string());
+        }
+        let (kind, _) = decode_header(&buf);
+        if signed && kind != KIND_PART {
+            return Err("unexpected data after the signature".to_string());
+        }
+        if decrypted.sha256.is_some() && kind != KIND_SIGNATURE && kind != KIND_PART {
+            return Err("unexpected data after the plaintext SHA-256".to_string());
+        }
+        if kind == KIND_DATA || kind == KIND_PART || kind == KIND_SHA256 {
+            // a record which fails to open breaks the chain or ends the decryption anyway
+            let (header, body) = buf.split_at(HEADER_SIZE);
+            signature_chain.update(header, body);
+        }
+        let tag = match open_in_place(cipher, &mut buf) {
+            Ok(t) => Some(t),
+            Err(_) if best_effort && (kind == KIND_DATA || kind == KIND_PART) => None,
+            Err(msg) => {
+                return Err(msg);
+            }
+        };
+        match (kind, tag) {
+            (KIND_DATA, Some(tag)) => {
+                tag_chain.update(&tag);
+                plaintext_len += (buf.len() - offset) as u64;
+            }
+            (KIND_DATA, None) => {
+                let len = buf.len() - tag_size;
+                buf.truncate(len);
+                buf[offset..].fill(0);
+                let start = plaintext_len;
+                plaintext_len += (len - offset) as u64;
+                decrypted.damaged.push(start..plaintext_len);
+                chain_broken = true;
+            }
+            (KIND_PART, Some(tag)) => {
+                let chain_digest: [u8; 32] = tag_chain.clone().finalize().into();
+                tag_chain.update(&tag);
+                let trailer = match PartTrailer::decode(&buf[offset..]) {
+                    Some(t) => t,
+                    None => {
+                        return Err("illegal part trailer".to_string());
+                    }
+                };
+                if trailer.index != next_part {
+                    let msg = format!(
+                        "found part {} where part {} is expected",
+                        trailer.index as u64 + 1,
+                        next_part + 1
+                    );
+                    return Err(msg);
+                }
+                if trailer.chain != chain_digest && !chain_broken {
+                    let msg = format!(
+                        "part {} doesn't follow the preceding data; some data is missing or altered",
+                        next_part + 1
+                    );
+                    return Err(msg);
+                }
+                next_part += 1;
+                last_part_seen = trailer.last;
+                buf.truncate(offset);
+            }
+            (KIND_SHA256, Some(tag)) => {
+                tag_chain.update(&tag);
+                decrypted.sha256 = match buf[offset..].try_into() {
+                    Ok(sha256) => Some(sha256),
+                    Err(_) => {
+                        return Err("illegal plaintext SHA-256 record".to_string());
+                    }
+                };
+                buf.truncate(offset);
+            }
+            (KIND_SIGNATURE, Some(tag)) => {
+                match signature_chain.verify(&buf[offset..]) {
+                    Ok(key) => {
+                        decrypted.signer = Some(key);
+                    }
+                    Err(_) if chain_broken => {} // zero-filled chunks can't match
+                    Err(msg) => {
+                        return Err(msg);
+                    }
+                }
+                tag_chain.update(&tag);
+                signed = true;
+                buf.truncate(offset);
+            }
+            (KIND_PART, None) => {
+                // a damaged trailer takes no plaintext along, but whether it was the last is lost
+                chain_broken = true;
+                next_part += 1;
+                buf.truncate(offset);
+            }
+            _ => {
+                let msg = format!("unknown record kind {kind}");
                 return Err(msg);
             }
         }
 
-        match plaintext_tx.send(ciphertext.vec) {
+        match plaintext_tx.send(buf) {
             Ok(_) => {}
             Err(_) => {
-                return Ok(()); // consumer stopped; error occurred
+                return Ok(decrypted); // consumer stopped; error occurred
             }
         }
     }
 }
 
+// Decrypts a record in place and strips the tag; returns the tag.
+fn open_in_place<C: AeadInPlace>(
+    cipher: &C,
+    buf: &mut SecretBuffer,
+) -> Result<Tag<C::TagSize>, String> {
+    let nonce_size = C::NonceSize::to_usize();
+    let tag_size = C::TagSize::to_usize();
+    let len = buf.len();
+    let (header, rest) = buf.split_at_mut(HEADER_SIZE);
+    let (nonce, rest) = rest.split_at_mut(nonce_size);
+    let (ciphertext, tag) = rest.split_at_mut(len - HEADER_SIZE - nonce_size - tag_size);
+    let tag = Tag::<C::TagSize>::clone_from_slice(tag);
+    match cipher.decrypt_in_place_detached(
+        Nonce::from_slice(nonce),
+        associated_data(header),
+        ciphertext,
+        &tag,
+    ) {
+        Ok(_) => {}
+        Err(e) => {
+            let msg = format!("decrypt error: {e}");
+            return Err(msg);
+        }
+    }
+    buf.truncate(len - tag_size);
+    Ok(tag)
+}
+
+// Returns the SHA-256 of the plaintext written.
 fn consume_plaintext<W: Write>(
     writer: &mut W,
-    plaintext_rx: Receiver<Vec<u8>>,
-) -> Result<(), String> {
+    plaintext_rx: Receiver<SecretBuffer>,
+    recycler: BufferRecycler,
+    offset: usize,
+) -> Result<[u8; 32], String> {
+    let mut sha256 = Sha256::new();
     loop {
         let buf = match plaintext_rx.recv() {
             Ok(b) => b,
             Err(_) => {
-                return Ok(()); // decryptor stopped; error occurred
+                return Ok([0u8; 32]); // decryptor stopped; error occurred
             }
         };
 
         if buf.is_empty() {
-            return Ok(());
+            return Ok(sha256.finalize().into());
         }
 
-        match writer.write_all(&buf) {
+        sha256.update(&buf[offset..]);
+        match writer.write_all(&buf[offset..]) {
             Ok(_) => {}
             Err(e) => {
                 let msg = format!("write error: {e}");
                 return Err(msg);
             }
         }
+        recycler.recycle(buf);
     }
 }
 
 pub fn new_aes128gcm_cipher(key: &str) -> Aes128Gcm {
-    let hash = Sha256::digest(key);
+    let hash = hash_key(key);
     Aes128Gcm::new_from_slice(&hash[..16]).unwrap()
 }
 
 pub fn new_aes256gcm_cipher(key: &str) -> Aes256Gcm {
-    let hash = Sha256::digest(key);
-    Aes256Gcm::new_from_slice(&hash).unwrap()
+    let hash = hash_key(key);
+    Aes256Gcm::new_from_slice(&*hash).unwrap()
+}
+
+fn hash_key(key: &str) -> Zeroizing<[u8; 32]> {
+    let mut hash = Zeroizing::new([0u8; 32]);
+    Sha256::new()
+        .chain_update(key)
+        .finalize_into(GenericArray::from_mut_slice(&mut *hash));
+    hash
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
 
-    use crate::{new_aes128gcm_cipher, decrypt, encrypt};
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        decrypt, decrypt_with_options, encrypt, encrypt_with_options, generate_signing_key,
+        new_aes128gcm_cipher, new_aes256gcm_cipher, DecryptOptions, DeterministicNonce,
+        EncryptOptions, Parity,
+    };
 
     #[test]
     fn test() {
@@ -415,4 +1039,96 @@ mod tests {
         });
         assert_eq!(raw_bytes.len(), decrypted.len());
     }
+
+    #[test]
+    fn test_deterministic() {
+        let cipher = new_aes256gcm_cipher("test");
+
+        let mut raw_bytes = vec![0u8; 4096 * 3 + 100];
+        for b in raw_bytes.iter_mut() {
+            *b = rand::random();
+        }
+
+        let encrypt_with = |key: &str| {
+            let mut cursor = Cursor::new(&raw_bytes);
+            let mut encrypted = vec![];
+            let mut nonce_source = DeterministicNonce::new(key);
+            encrypt(&mut cursor, &mut encrypted, &cipher, &mut nonce_source).unwrap();
+            encrypted
+        };
+        let encrypted = encrypt_with("test");
+        assert_eq!(encrypted, encrypt_with("test"));
+        assert_ne!(encrypted, encrypt_with("another"));
+
+        let mut rng = rand::thread_rng();
+        let mut cursor = Cursor::new(&raw_bytes);
+        let mut randomized = vec![];
+        encrypt(&mut cursor, &mut randomized, &cipher, &mut rng).unwrap();
+        assert_ne!(encrypted, randomized);
+
+        let mut cursor = Cursor::new(&encrypted);
+        let mut decrypted = vec![];
+        decrypt(&mut cursor, &mut decrypted, &cipher).unwrap();
+        assert_eq!(raw_bytes, decrypted);
+    }
+
+    #[test]
+    fn test_sha256() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let raw_bytes: Vec<u8> = (0..4096 * 5 + 7).map(|_| rand::random()).collect();
+        let sha256: [u8; 32] = Sha256::digest(&raw_bytes).into();
+        let decrypt_report = |encrypted: &[u8]| {
+            let mut decrypted = vec![];
+            let mut cursor = Cursor::new(encrypted);
+            let options = DecryptOptions::default();
+            decrypt_with_options(&mut cursor, &mut decrypted, &cipher, &options)
+                .inspect(|_| assert_eq!(decrypted, raw_bytes))
+        };
+
+        for parity in [None, Some(Parity::new(2, 1).unwrap())] {
+            let options = EncryptOptions {
+                parity,
+                signing_key: Some(generate_signing_key()),
+                record_sha256: true,
+            };
+            let mut encrypted = vec![];
+            let mut cursor = Cursor::new(&raw_bytes);
+            encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+            let report = decrypt_report(&encrypted).unwrap();
+            assert_eq!(report.sha256, sha256);
+            assert!(report.sha256_recorded);
+            assert!(report.signer.is_some());
+        }
+
+        // the hash is computed either way
+        let mut encrypted = vec![];
+        encrypt(
+            &mut Cursor::new(&raw_bytes),
+            &mut encrypted,
+            &cipher,
+            &mut rng,
+        )
+        .unwrap();
+        let report = decrypt_report(&encrypted).unwrap();
+        assert_eq!(report.sha256, sha256);
+        assert!(!report.sha256_recorded);
+
+        // reordered chunks decrypt one by one, but don't match the recorded hash
+        let options = EncryptOptions {
+            record_sha256: true,
+            ..Default::default()
+        };
+        let mut encrypted = vec![];
+        let mut cursor = Cursor::new(&raw_bytes);
+        encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+        let record_len = 4 + 12 + 4096 + 16;
+        let mut swapped = encrypted[record_len..2 * record_len].to_vec();
+        swapped.extend_from_slice(&encrypted[..record_len]);
+        swapped.extend_from_slice(&encrypted[2 * record_len..]);
+        let mut cursor = Cursor::new(&swapped);
+        let options = DecryptOptions::default();
+        let msgs = decrypt_with_options(&mut cursor, &mut vec![], &cipher, &options).unwrap_err();
+        assert!(msgs[0].contains("SHA-256"));
+    }
 }
@@ -0,0 +1,214 @@
+use aes_gcm::aes::cipher::Unsigned;
+use aes_gcm::AeadInPlace;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::record::{
+    decode_header, HEADER_SIZE, KIND_DATA, KIND_PARITY, KIND_PARITY_INFO, KIND_SHA256,
+    KIND_SIGNATURE,
+};
+use crate::{open_in_place, seal_record, NonceSource, SecretBuffer, CHUNK_SIZE};
+
+/// Encrypts a small payload in memory, without the threads of `encrypt`. The result is the same
+/// format as the one `encrypt` writes.
+pub fn encrypt_to_vec<C, N>(
+    data: &[u8],
+    cipher: &C,
+    nonce_source: &mut N,
+) -> Result<Vec<u8>, String>
+where
+    C: AeadInPlace,
+    N: NonceSource,
+{
+    let overhead = HEADER_SIZE + C::NonceSize::to_usize() + C::TagSize::to_usize();
+    let mut encrypted = Vec::with_capacity(data.len() + data.len().div_ceil(CHUNK_SIZE) * overhead);
+    let mut buf = SecretBuffer::default();
+    for chunk in data.chunks(CHUNK_SIZE) {
+        seal_record(cipher, nonce_source, KIND_DATA, chunk, &mut buf)?;
+        encrypted.extend_from_slice(&buf);
+    }
+    Ok(encrypted)
+}
+
+/// Decrypts a small payload in memory, without the threads of `decrypt`. Parity records are
+/// skipped; payloads with a plaintext hash or a signature are refused, as they aren't verified
+/// here (`decrypt` does). Split archives aren't supported.
+pub fn decrypt_from_slice<C: AeadInPlace>(data: &[u8], cipher: &C) -> Result<SecretBuffer, String> {
+    let offset = HEADER_SIZE + C::NonceSize::to_usize();
+    let tag_size = C::TagSize::to_usize();
+    let mut decrypted = SecretBuffer::new(Vec::with_capacity(data.len()));
+    let mut buf = SecretBuffer::default();
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < HEADER_SIZE {
+            let msg = format!("read error, illegal length {}", rest.len());
+            return Err(msg);
+        }
+        let (kind, len) = decode_header(rest);
+        if rest.len() < HEADER_SIZE + len {
+            return Err("read error: failed to fill whole buffer".to_string());
+        }
+        let (record, next) = rest.split_at(HEADER_SIZE + len);
+        rest = next;
+        match kind {
+            KIND_DATA => {}
+            KIND_PARITY | KIND_PARITY_INFO => {
+                continue;
+            }
+            KIND_SHA256 | KIND_SIGNATURE => {
+                let msg = "hashed or signed data isn't verified in memory; use decrypt instead";
+                return Err(msg.to_string());
+            }
+            _ => {
+                let msg = format!("unsupported record kind {kind}");
+                return Err(msg);
+            }
+        }
+        if len <= offset - HEADER_SIZE + tag_size {
+            let msg = format!("read error, illegal record length {len}");
+            return Err(msg);
+        }
+        buf.clear();
+        buf.extend_from_slice(record);
+        open_in_place(cipher, &mut buf)?;
+        decrypted.extend_from_slice(&buf[offset..]);
+    }
+    Ok(decrypted)
+}
+
+const ARMOR_BEGIN: &str = "-----BEGIN FILE-CRYPTO MESSAGE-----";
+const ARMOR_END: &str = "-----END FILE-CRYPTO MESSAGE-----";
+const ARMOR_LINE_LEN: usize = 64;
+
+/// Wraps encrypted data into text, for pasting into tickets and config files: base64 lines
+/// between begin and end markers.
+pub fn armor(data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+    let mut text = String::with_capacity(encoded.len() + encoded.len() / ARMOR_LINE_LEN + 80);
+    text.push_str(ARMOR_BEGIN);
+    text.push('\n');
+    for line in encoded.as_bytes().chunks(ARMOR_LINE_LEN) {
+        text.push_str(std::str::from_utf8(line).unwrap());
+        text.push('\n');
+    }
+    text.push_str(ARMOR_END);
+    text.push('\n');
+    text
+}
+
+/// Whether the text begins (after whitespace) with the armor begin marker.
+pub fn is_armored(text: &[u8]) -> bool {
+    let start = text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    text[start..].starts_with(ARMOR_BEGIN.as_bytes())
+}
+
+/// Unwraps the data armored by `armor`. Text around the markers and whitespace within the base64
+/// (e.g. from reflowing or CRLF line ends) is ignored.
+pub fn dearmor(text: &str) -> Result<Vec<u8>, String> {
+    let start = match text.find(ARMOR_BEGIN) {
+        Some(i) => i + ARMOR_BEGIN.len(),
+        None => {
+            return Err("armor begin marker not found".to_string());
+        }
+    };
+    let len = match text[start..].find(ARMOR_END) {
+        Some(len) => len,
+        None => {
+            return Err("armor end marker not found".to_string());
+        }
+    };
+    let encoded: String = text[start..start + len]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    match STANDARD.decode(encoded) {
+        Ok(data) => Ok(data),
+        Err(e) => Err(format!("illegal armored data; {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        armor, dearmor, decrypt, decrypt_from_slice, encrypt, encrypt_to_vec, encrypt_with_options,
+        generate_signing_key, is_armored, new_aes128gcm_cipher, DeterministicNonce, EncryptOptions,
+    };
+
+    #[test]
+    fn test_memory() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        for len in [0, 1, 4096, 4096 * 3 + 100] {
+            let raw_bytes: Vec<u8> = (0..len).map(|_| rand::random()).collect();
+
+            let encrypted = encrypt_to_vec(&raw_bytes, &cipher, &mut rng).unwrap();
+            let decrypted = decrypt_from_slice(&encrypted, &cipher).unwrap();
+            assert_eq!(*decrypted, raw_bytes);
+
+            // the same format as the streaming functions
+            let mut nonce_source = DeterministicNonce::new("test");
+            let encrypted = encrypt_to_vec(&raw_bytes, &cipher, &mut nonce_source).unwrap();
+            let mut streamed = vec![];
+            let mut nonce_source = DeterministicNonce::new("test");
+            let mut cursor = Cursor::new(&raw_bytes);
+            encrypt(&mut cursor, &mut streamed, &cipher, &mut nonce_source).unwrap();
+            assert_eq!(encrypted, streamed);
+            let mut decrypted = vec![];
+            decrypt(&mut Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+            assert_eq!(decrypted, raw_bytes);
+
+            if len > 0 {
+                let mut damaged = encrypted.clone();
+                damaged[len / 2 + 20] ^= 1;
+                assert!(decrypt_from_slice(&damaged, &cipher).is_err());
+                let truncated = &encrypted[..encrypted.len() - 1];
+                assert!(decrypt_from_slice(truncated, &cipher).is_err());
+            }
+        }
+
+        let raw_bytes = b"hello".to_vec();
+        let signing_key = generate_signing_key();
+        for options in [
+            EncryptOptions {
+                record_sha256: true,
+                ..Default::default()
+            },
+            EncryptOptions {
+                signing_key: Some(signing_key),
+                ..Default::default()
+            },
+        ] {
+            let mut encrypted = vec![];
+            let mut cursor = Cursor::new(&raw_bytes);
+            encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+            assert!(decrypt_from_slice(&encrypted, &cipher).is_err());
+        }
+    }
+
+    #[test]
+    fn test_armor() {
+        let data: Vec<u8> = (0..1000).map(|_| rand::random()).collect();
+        let text = armor(&data);
+        assert!(text
+            .lines()
+            .all(|line| line.len() <= 64 || line.starts_with("-----")));
+        assert!(is_armored(text.as_bytes()));
+        assert_eq!(dearmor(&text).unwrap(), data);
+
+        let pasted = format!(
+            "see below:\r\n\r\n{}\r\nthanks",
+            text.replace('\n', "\r\n  ")
+        );
+        assert!(!is_armored(pasted.as_bytes()));
+        assert_eq!(dearmor(&pasted).unwrap(), data);
+
+        assert!(dearmor(&text[..text.len() - 10]).is_err());
+        assert!(dearmor(&text.replacen('\n', "\n*", 1)).is_err());
+        assert_eq!(dearmor(&armor(b"")).unwrap(), b"");
+    }
+}
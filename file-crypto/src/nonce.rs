@@ -0,0 +1,72 @@
+use aes_gcm::aead::{AeadCore, Nonce};
+use aes_gcm::aes::cipher::Unsigned;
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, RngCore};
+use sha2::digest::generic_array::GenericArray;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::secret::wipe_bytes;
+
+pub trait NonceSource {
+    fn next_nonce<C: AeadCore>(&mut self, plaintext: &[u8]) -> Nonce<C>;
+
+    /// Skips the nonces of `count` records, which were sealed by an interrupted run.
+    fn skip(&mut self, _count: u64) {}
+}
+
+impl<RNG: CryptoRng + RngCore> NonceSource for RNG {
+    fn next_nonce<C: AeadCore>(&mut self, _plaintext: &[u8]) -> Nonce<C> {
+        let mut nonce = Nonce::<C>::default();
+        self.fill_bytes(&mut nonce);
+        nonce
+    }
+}
+
+/// Derives the nonce of each chunk from an HMAC of the chunk index and the chunk plaintext, so
+/// encrypting the same file with the same key always produces the same output, which allows the
+/// encrypted files to be deduplicated by content hash.
+///
+/// The price is that the ciphertext is no longer indistinguishable: anyone who can compare
+/// encrypted files learns whether two files are identical, and whether chunks at the same offset
+/// of two files are identical. Nothing else about the plaintext is revealed, but don't use this
+/// mode for data whose equality is itself sensitive (e.g. low-entropy files that can be guessed
+/// and compared).
+///
+/// A `DeterministicNonce` counts chunks, so create a new one for every file.
+pub struct DeterministicNonce {
+    mac: Hmac<Sha256>,
+    index: u64,
+}
+impl DeterministicNonce {
+    pub fn new(key: &str) -> Self {
+        let mut hash = Zeroizing::new([0u8; 32]);
+        Sha256::new()
+            .chain_update(b"file-crypto deterministic nonce")
+            .chain_update(key)
+            .finalize_into(GenericArray::from_mut_slice(&mut *hash));
+        let mac = Hmac::new_from_slice(&*hash).unwrap();
+        Self { mac, index: 0 }
+    }
+}
+impl NonceSource for DeterministicNonce {
+    fn next_nonce<C: AeadCore>(&mut self, plaintext: &[u8]) -> Nonce<C> {
+        let mut mac = self.mac.clone();
+        mac.update(&self.index.to_le_bytes());
+        mac.update(plaintext);
+        self.index += 1;
+        let hash = mac.finalize_reset().into_bytes();
+        unsafe { wipe_bytes(&mut mac) };
+        Nonce::<C>::clone_from_slice(&hash[..C::NonceSize::to_usize()])
+    }
+
+    fn skip(&mut self, count: u64) {
+        self.index += count;
+    }
+}
+impl Drop for DeterministicNonce {
+    fn drop(&mut self) {
+        // the HMAC state holds the (hashed) key, and doesn't wipe it on drop
+        unsafe { wipe_bytes(self) };
+    }
+}
@@ -0,0 +1,518 @@
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+
+use crate::pool::BufferPool;
+use crate::record::{
+    decode_header, encode_header, HEADER_SIZE, KIND_DATA, KIND_PARITY, KIND_PART, KIND_SHA256,
+    KIND_SIGNATURE, SHA256_SIZE, TRAILER_SIZE,
+};
+use crate::signature::SIGNATURE_SIZE;
+use crate::SecretBuffer;
+
+pub const MAX_DATA_CHUNKS: usize = 32;
+pub const MAX_PARITY_CHUNKS: usize = 16;
+
+/// Reed-Solomon parity over the encrypted chunks: every group of `data_chunks` data records is
+/// followed by `parity_chunks` parity records, so up to `parity_chunks` damaged records of each
+/// group can be repaired when decrypting.
+///
+/// The parity records carry the lengths and checksums of the records of their group, which is how
+/// damaged records are told. Records are repaired as long as their boundaries can be told, so
+/// damage to the header of the very last data record, of a part trailer, or of the record which
+/// announces the parity at the beginning of the file can't be repaired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parity {
+    pub data_chunks: usize,
+    pub parity_chunks: usize,
+}
+impl Parity {
+    pub fn new(data_chunks: usize, parity_chunks: usize) -> Result<Self, String> {
+        if data_chunks == 0 || data_chunks > MAX_DATA_CHUNKS {
+            let msg = format!("data chunks per parity group must be within 1..={MAX_DATA_CHUNKS}");
+            return Err(msg);
+        }
+        if parity_chunks == 0 || parity_chunks > MAX_PARITY_CHUNKS {
+            let msg = format!("parity chunks per group must be within 1..={MAX_PARITY_CHUNKS}");
+            return Err(msg);
+        }
+        Ok(Self {
+            data_chunks,
+            parity_chunks,
+        })
+    }
+
+    // data count, parity index, data lengths, data and parity checksums, and the meta checksum
+    fn meta_size(&self) -> usize {
+        let checksums = self.data_chunks + self.parity_chunks + 1;
+        2 + 2 * self.data_chunks + CHECKSUM_SIZE * checksums
+    }
+
+    pub(crate) fn record_body_size(&self, shard_size: usize) -> usize {
+        self.meta_size() + shard_size
+    }
+}
+
+// The body of the record announcing the parity, the first record of the file. The counts are
+// repeated inverted, as the record itself has no parity.
+pub(crate) const INFO_SIZE: usize = 4;
+
+pub(crate) fn encode_info(parity: &Parity) -> [u8; INFO_SIZE] {
+    let (k, m) = (parity.data_chunks as u8, parity.parity_chunks as u8);
+    [k, m, !k, !m]
+}
+
+pub(crate) fn decode_info(bytes: &[u8]) -> Option<Parity> {
+    if bytes.len() != INFO_SIZE || bytes[0] != !bytes[2] || bytes[1] != !bytes[3] {
+        return None;
+    }
+    Parity::new(bytes[0] as usize, bytes[1] as usize).ok()
+}
+
+const CHECKSUM_SIZE: usize = 8;
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let hash = Sha256::digest(bytes);
+    let mut checksum = [0u8; CHECKSUM_SIZE];
+    checksum.copy_from_slice(&hash[..CHECKSUM_SIZE]);
+    checksum
+}
+
+// Every parity record of a group carries the same meta, so any intact one will do.
+struct Meta {
+    data_count: usize,
+    lens: Vec<usize>,
+    checksums: Vec<[u8; CHECKSUM_SIZE]>,
+}
+impl Meta {
+    fn encode(&self, parity: &Parity, parity_index: usize, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.push(self.data_count as u8);
+        out.push(parity_index as u8);
+        for i in 0..parity.data_chunks {
+            let len = self.lens.get(i).copied().unwrap_or(0);
+            out.extend_from_slice(&(len as u16).to_le_bytes());
+        }
+        for checksum in &self.checksums {
+            out.extend_from_slice(checksum);
+        }
+        let checksum = checksum(&out[start..]);
+        out.extend_from_slice(&checksum);
+    }
+
+    // returns the meta and the index of the parity record
+    fn decode(parity: &Parity, bytes: &[u8]) -> Option<(Self, usize)> {
+        let size = parity.meta_size();
+        if bytes.len() < size {
+            return None;
+        }
+        let (bytes, meta_checksum) = bytes[..size].split_at(size - CHECKSUM_SIZE);
+        if checksum(bytes) != meta_checksum {
+            return None;
+        }
+        let data_count = bytes[0] as usize;
+        let parity_index = bytes[1] as usize;
+        if data_count == 0
+            || data_count > parity.data_chunks
+            || parity_index >= parity.parity_chunks
+        {
+            return None;
+        }
+        let lens = bytes[2..2 + 2 * data_count]
+            .chunks(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .collect();
+        let checksums = bytes[2 + 2 * parity.data_chunks..]
+            .chunks(CHECKSUM_SIZE)
+            .map(|b| {
+                let mut checksum = [0u8; CHECKSUM_SIZE];
+                checksum.copy_from_slice(b);
+                checksum
+            })
+            .collect();
+        let meta = Self {
+            data_count,
+            lens,
+            checksums,
+        };
+        Some((meta, parity_index))
+    }
+}
+
+/// Collects the bodies of the data records of a group and computes its parity records.
+pub(crate) struct ParityEncoder {
+    parity: Parity,
+    shard_size: usize,
+    shards: Vec<Vec<u8>>,
+    lens: Vec<usize>,
+}
+impl ParityEncoder {
+    // `shard_size` is the size of the body of a full data record
+    pub(crate) fn new(parity: Parity, shard_size: usize) -> Self {
+        Self {
+            parity,
+            shard_size,
+            shards: vec![],
+            lens: vec![],
+        }
+    }
+
+    /// Adds the body of a data record; returns whether the group is complete.
+    pub(crate) fn push(&mut self, body: &[u8]) -> bool {
+        let mut shard = Vec::with_capacity(self.shard_size);
+        shard.extend_from_slice(body);
+        shard.resize(self.shard_size, 0);
+        self.shards.push(shard);
+        self.lens.push(body.len());
+        self.shards.len() == self.parity.data_chunks
+    }
+
+    /// Returns the parity records of the group (none if it's empty) and starts the next group.
+    pub(crate) fn finish_group(&mut self) -> Vec<SecretBuffer> {
+        let data_count = self.shards.len();
+        if data_count == 0 {
+            return vec![];
+        }
+        let mut checksums: Vec<_> = self
+            .shards
+            .iter()
+            .zip(&self.lens)
+            .map(|(shard, len)| checksum(&shard[..*len]))
+            .collect();
+        checksums.resize(self.parity.data_chunks, [0u8; CHECKSUM_SIZE]);
+
+        let mut shards = std::mem::take(&mut self.shards);
+        let parity_count = self.parity.parity_chunks;
+        shards.resize(data_count + parity_count, vec![0u8; self.shard_size]);
+        let codec = ReedSolomon::new(data_count, parity_count).unwrap();
+        codec.encode(&mut shards).unwrap();
+        let parity_shards = shards.split_off(data_count);
+        checksums.extend(parity_shards.iter().map(|shard| checksum(shard)));
+
+        let meta = Meta {
+            data_count,
+            lens: std::mem::take(&mut self.lens),
+            checksums,
+        };
+        let body_size = self.parity.record_body_size(self.shard_size);
+        let mut records = Vec::with_capacity(parity_count);
+        for (i, shard) in parity_shards.iter().enumerate() {
+            let mut buf = SecretBuffer::new(Vec::with_capacity(HEADER_SIZE + body_size));
+            buf.extend_from_slice(&encode_header(KIND_PARITY, body_size));
+            meta.encode(&self.parity, i, &mut buf);
+            buf.extend_from_slice(shard);
+            records.push(buf);
+        }
+        records
+    }
+}
+
+enum Held {
+    Data(SecretBuffer, bool),
+    Other(SecretBuffer),
+}
+
+/// Used by the reader thread to frame the records of a file with parity, and to hold back the
+/// records of a group until its parity records are read, so the damaged ones can be repaired.
+pub(crate) struct GroupReader {
+    parity: Parity,
+    shard_size: usize,
+    min_body_size: usize,
+    held: Vec<Held>,
+    data_count: usize,
+    parity_records: Vec<SecretBuffer>,
+    pub(crate) repaired: u64,
+}
+impl GroupReader {
+    // `shard_size` is the size of the body of a full data record, `min_body_size` the size of
+    // the body of an empty one
+    pub(crate) fn new(parity: Parity, shard_size: usize, min_body_size: usize) -> Self {
+        Self {
+            parity,
+            shard_size,
+            min_body_size,
+            held: vec![],
+            data_count: 0,
+            parity_records: vec![],
+            repaired: 0,
+        }
+    }
+
+    pub(crate) fn is_holding(&self) -> bool {
+        !self.held.is_empty() || !self.parity_records.is_empty()
+    }
+
+    fn parity_phase(&self) -> bool {
+        !self.parity_records.is_empty() || self.data_count == self.parity.data_chunks
+    }
+
+    /// Checks the header of the next record against the layout of the group. A header which
+    /// can't be right is taken as damaged and replaced by the most likely one. Returns the kind
+    /// and the body size of the record, and whether the header is damaged.
+    pub(crate) fn frame(&self, header: &[u8]) -> (u8, usize, bool) {
+        let (kind, len) = decode_header(header);
+        let parity_size = self.parity.record_body_size(self.shard_size);
+        let legit = match kind {
+            KIND_DATA => !self.parity_phase() && len > self.min_body_size && len <= self.shard_size,
+            KIND_PART => len == self.min_body_size + TRAILER_SIZE,
+            KIND_SHA256 => len == self.min_body_size + SHA256_SIZE,
+            KIND_SIGNATURE => len == self.min_body_size + SIGNATURE_SIZE,
+            KIND_PARITY => len == parity_size && self.data_count > 0,
+            _ => false,
+        };
+        if legit {
+            (kind, len, false)
+        } else if self.parity_phase() {
+            (KIND_PARITY, parity_size, true)
+        } else {
+            (KIND_DATA, self.shard_size, true)
+        }
+    }
+
+    /// Whether a header may follow a short data record, which must be the last one.
+    pub(crate) fn may_follow_last(&self, header: &[u8]) -> bool {
+        match decode_header(header) {
+            (KIND_PARITY, len) => len == self.parity.record_body_size(self.shard_size),
+            (KIND_PART, len) => len == self.min_body_size + TRAILER_SIZE,
+            (KIND_SHA256, len) => len == self.min_body_size + SHA256_SIZE,
+            (KIND_SIGNATURE, len) => len == self.min_body_size + SIGNATURE_SIZE,
+            _ => false,
+        }
+    }
+
+    /// Takes the next record; returns the records which are ready to be decrypted.
+    pub(crate) fn push(
+        &mut self,
+        kind: u8,
+        mut buf: SecretBuffer,
+        damaged: bool,
+        pool: &mut BufferPool,
+    ) -> Vec<SecretBuffer> {
+        if damaged {
+            let len = buf.len() - HEADER_SIZE;
+            buf[..HEADER_SIZE].copy_from_slice(&encode_header(kind, len));
+        }
+        match kind {
+            KIND_DATA => {
+                self.held.push(Held::Data(buf, damaged));
+                self.data_count += 1;
+            }
+            KIND_PARITY => {
+                self.parity_records.push(buf);
+                if self.parity_records.len() == self.parity.parity_chunks {
+                    return self.finish(pool);
+                }
+            }
+            _ => {
+                if !self.is_holding() {
+                    return vec![buf];
+                }
+                self.held.push(Held::Other(buf));
+            }
+        }
+        vec![]
+    }
+
+    /// Repairs the group as far as possible and returns its records.
+    pub(crate) fn finish(&mut self, pool: &mut BufferPool) -> Vec<SecretBuffer> {
+        self.repair();
+        for buf in self.parity_records.drain(..) {
+            pool.give_back(buf);
+        }
+        self.data_count = 0;
+        self.held
+            .drain(..)
+            .map(|held| match held {
+                Held::Data(buf, _) => buf,
+                Held::Other(buf) => buf,
+            })
+            .collect()
+    }
+
+    fn repair(&mut self) {
+        let parity = self.parity;
+        let meta = self
+            .parity_records
+            .iter()
+            .find_map(|buf| Meta::decode(&parity, &buf[HEADER_SIZE..]));
+        let meta = match meta {
+            Some((m, _)) if m.data_count == self.data_count => m,
+            _ => {
+                return; // nothing to repair with; the decryptor reports the damaged records
+            }
+        };
+        // the checksum of the meta isn't keyed, so it may well be forged
+        if meta
+            .lens
+            .iter()
+            .any(|len| !(self.min_body_size..=self.shard_size).contains(len))
+        {
+            return;
+        }
+
+        let mut shards: Vec<Option<Vec<u8>>> = vec![];
+        let mut erased = vec![];
+        let data = self.held.iter().filter_map(|held| match held {
+            Held::Data(buf, damaged) => Some((buf, *damaged)),
+            Held::Other(_) => None,
+        });
+        for (i, (buf, damaged)) in data.enumerate() {
+            let body = &buf[HEADER_SIZE..];
+            if body.len() == meta.lens[i] && checksum(body) == meta.checksums[i] {
+                if damaged {
+                    self.repaired += 1; // only the header was damaged
+                }
+                let mut shard = body.to_vec();
+                shard.resize(self.shard_size, 0);
+                shards.push(Some(shard));
+            } else {
+                erased.push(i);
+                shards.push(None);
+            }
+        }
+        if erased.is_empty() {
+            return;
+        }
+        for j in 0..parity.parity_chunks {
+            let shard = self.parity_records.iter().find_map(|buf| {
+                match Meta::decode(&parity, &buf[HEADER_SIZE..]) {
+                    Some((_, index)) if index == j => {
+                        let shard = &buf[HEADER_SIZE + parity.meta_size()..];
+                        let expected = meta.checksums[parity.data_chunks + j];
+                        (checksum(shard) == expected).then(|| shard.to_vec())
+                    }
+                    _ => None,
+                }
+            });
+            shards.push(shard);
+        }
+
+        let codec = ReedSolomon::new(self.data_count, parity.parity_chunks).unwrap();
+        if codec.reconstruct_data(&mut shards).is_err() {
+            return; // too many damaged records
+        }
+        let data = self.held.iter_mut().filter_map(|held| match held {
+            Held::Data(buf, _) => Some(buf),
+            Held::Other(_) => None,
+        });
+        for (i, buf) in data.enumerate() {
+            if !erased.contains(&i) {
+                continue;
+            }
+            let body = &shards[i].as_ref().unwrap()[..meta.lens[i]];
+            buf.clear();
+            buf.extend_from_slice(&encode_header(KIND_DATA, body.len()));
+            buf.extend_from_slice(body);
+            self.repaired += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        decrypt, decrypt_with_options, encrypt_with_options, new_aes128gcm_cipher, DecryptOptions,
+        EncryptOptions, Parity,
+    };
+
+    // the layout with AES-128-GCM and groups of 4 data and 2 parity records
+    const INFO: usize = 8;
+    const DATA: usize = 4 + 12 + 4096 + 16;
+    const PARITY: usize = 4 + 66 + 12 + 4096 + 16;
+
+    fn data_offset(index: usize) -> usize {
+        INFO + index * DATA + index / 4 * 2 * PARITY
+    }
+
+    #[test]
+    fn test_parity() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let mut raw_bytes = vec![0u8; 4096 * 10 + 100];
+        for b in raw_bytes.iter_mut() {
+            *b = rand::random();
+        }
+        let options = EncryptOptions {
+            parity: Some(Parity::new(4, 2).unwrap()),
+            ..Default::default()
+        };
+        let mut encrypted = vec![];
+        let mut cursor = Cursor::new(&raw_bytes);
+        encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+        assert_eq!(
+            encrypted.len(),
+            data_offset(10) + 100 + DATA - 4096 + 2 * PARITY
+        );
+
+        let decrypt_damaged = |damaged: &[u8], best_effort| {
+            let mut decrypted = vec![];
+            let options = DecryptOptions {
+                best_effort,
+                ..Default::default()
+            };
+            let mut cursor = Cursor::new(damaged);
+            decrypt_with_options(&mut cursor, &mut decrypted, &cipher, &options)
+                .map(|report| (decrypted, report))
+        };
+
+        let (decrypted, report) = decrypt_damaged(&encrypted, false).unwrap();
+        assert_eq!(decrypted, raw_bytes);
+        assert_eq!(report.repaired_chunks, 0);
+
+        // damaged bodies, including the short last record and a parity record
+        let mut damaged = encrypted.clone();
+        damaged[data_offset(0) + 100] ^= 0x01;
+        damaged[data_offset(2) + 4000] ^= 0x80;
+        damaged[data_offset(8) - PARITY + 10] ^= 0x10;
+        damaged[data_offset(10) + 50] ^= 0x01;
+        let (decrypted, report) = decrypt_damaged(&damaged, false).unwrap();
+        assert_eq!(decrypted, raw_bytes);
+        assert_eq!(report.repaired_chunks, 3);
+
+        // damaged headers: a shortened length, an unknown kind and a damaged parity header
+        let mut damaged = encrypted.clone();
+        damaged[data_offset(1)] ^= 0x04;
+        damaged[data_offset(5) + 3] ^= 0x40;
+        damaged[data_offset(8) - PARITY + 1] ^= 0x01;
+        let (decrypted, report) = decrypt_damaged(&damaged, false).unwrap();
+        assert_eq!(decrypted, raw_bytes);
+        assert_eq!(report.repaired_chunks, 2);
+
+        // too many damaged records in a group
+        let mut damaged = encrypted.clone();
+        for i in 4..7 {
+            damaged[data_offset(i) + 20] ^= 0x01;
+        }
+        let mut cursor = Cursor::new(&damaged);
+        assert!(decrypt(&mut cursor, &mut vec![], &cipher).is_err());
+
+        let (decrypted, report) = decrypt_damaged(&damaged, true).unwrap();
+        assert_eq!(report.repaired_chunks, 0);
+        assert_eq!(
+            report.damaged,
+            vec![4096 * 4..4096 * 5, 4096 * 5..4096 * 6, 4096 * 6..4096 * 7]
+        );
+        assert_eq!(decrypted.len(), raw_bytes.len());
+        assert!(decrypted[4096 * 4..4096 * 7].iter().all(|b| *b == 0));
+        assert_eq!(decrypted[..4096 * 4], raw_bytes[..4096 * 4]);
+        assert_eq!(decrypted[4096 * 7..], raw_bytes[4096 * 7..]);
+
+        // a damaged record, and forged lengths in the meta of the parity records of its group
+        for len in [5000u16, 10] {
+            let mut damaged = encrypted.clone();
+            damaged[data_offset(0) + 20] ^= 0x01;
+            for j in 0..2 {
+                let meta = data_offset(4) - (2 - j) * PARITY + 4;
+                damaged[meta + 2..meta + 4].copy_from_slice(&len.to_le_bytes());
+                let checksum = Sha256::digest(&damaged[meta..meta + 58]);
+                damaged[meta + 58..meta + 66].copy_from_slice(&checksum[..8]);
+            }
+            let (decrypted, report) = decrypt_damaged(&damaged, true).unwrap();
+            assert_eq!(report.damaged, vec![0..4096]);
+            assert_eq!(decrypted[4096..], raw_bytes[4096..]);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::ffi::OsString;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::AeadCore;
+use aes_gcm::aes::cipher::Unsigned;
+use aes_gcm::AeadInPlace;
+
+use crate::parity::INFO_SIZE;
+use crate::record::{decode_header, RecordWriter, HEADER_SIZE, KIND_PART, TRAILER_SIZE};
+use crate::{
+    decrypt_records, encrypt_records, DecryptOptions, DecryptReport, EncryptOptions, NonceSource,
+    CHUNK_SIZE,
+};
+
+/// Encrypts into a split archive whose parts are at most `part_size` bytes long. `open_part` is
+/// called with the index (starting from 0) of every part to be written. Returns the number of
+/// parts.
+///
+/// Each part ends with an authenticated trailer carrying its index and a digest chained over all
+/// preceding records, so `decrypt_parts` detects missing, reordered, truncated or foreign parts.
+pub fn encrypt_parts<R, F, W, C, N>(
+    reader: &mut R,
+    open_part: F,
+    part_size: u64,
+    cipher: &C,
+    nonce_source: &mut N,
+    options: &EncryptOptions,
+) -> Result<usize, Vec<String>>
+where
+    R: Read,
+    F: FnMut(usize) -> std::io::Result<W>,
+    W: Write,
+    C: AeadInPlace,
+    N: NonceSource,
+{
+    let min_size = min_part_size::<C>(options);
+    if part_size < min_size {
+        return Err(vec![format!("part size must be at least {min_size} bytes")]);
+    }
+    let mut writer = PartsWriter {
+        open_part,
+        index: 0,
+        current: None,
+    };
+    encrypt_records(
+        reader,
+        &mut writer,
+        cipher,
+        nonce_source,
+        options,
+        Some(part_size),
+        None,
+    )?;
+    Ok(writer.index)
+}
+
+/// Decrypts a split archive. `open_part` is called with the index (starting from 0) of every part
+/// to be read, and returns `None` if there is no such part.
+pub fn decrypt_parts<F, R, W, C>(
+    open_part: F,
+    writer: &mut W,
+    cipher: &C,
+    options: &DecryptOptions,
+) -> Result<DecryptReport, Vec<String>>
+where
+    F: FnMut(usize) -> std::io::Result<Option<R>>,
+    R: Read,
+    W: Write,
+    C: AeadInPlace,
+{
+    let mut reader = PartsReader {
+        open_part,
+        index: 0,
+        current: None,
+        done: false,
+    };
+    decrypt_records(&mut reader, writer, cipher, options, true)
+}
+
+/// The path of a part of a split archive: `backup.enc` is split into `backup.enc.001`,
+/// `backup.enc.002`, etc.
+pub fn part_path(path: &Path, index: usize) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(format!(".{:03}", index + 1));
+    PathBuf::from(path)
+}
+
+/// The size of the largest record and a trailer, the least a part must be able to hold. With
+/// parity, the first part also holds the record announcing it.
+pub fn min_part_size<C: AeadCore>(options: &EncryptOptions) -> u64 {
+    let overhead = HEADER_SIZE + C::NonceSize::to_usize() + C::TagSize::to_usize();
+    let record_size = match options.parity {
+        None => overhead + CHUNK_SIZE,
+        Some(parity) => {
+            let shard_size = overhead - HEADER_SIZE + CHUNK_SIZE;
+            HEADER_SIZE + INFO_SIZE + HEADER_SIZE + parity.record_body_size(shard_size)
+        }
+    };
+    (record_size + overhead + TRAILER_SIZE) as u64
+}
+
+struct PartsWriter<F, W> {
+    open_part: F,
+    index: usize,
+    current: Option<W>,
+}
+impl<F, W> RecordWriter for PartsWriter<F, W>
+where
+    F: FnMut(usize) -> std::io::Result<W>,
+    W: Write,
+{
+    fn write_record(&mut self, record: &[u8]) -> std::io::Result<()> {
+        let writer = match self.current.as_mut() {
+            Some(w) => w,
+            None => self.current.insert((self.open_part)(self.index)?),
+        };
+        writer.write_all(record)?;
+        if decode_header(record).0 == KIND_PART {
+            // the trailer ends the part
+            let mut writer = self.current.take().unwrap();
+            writer.flush()?;
+            self.index += 1;
+        }
+        Ok(())
+    }
+}
+
+struct PartsReader<F, R> {
+    open_part: F,
+    index: usize,
+    current: Option<R>,
+    done: bool,
+}
+impl<F, R> Read for PartsReader<F, R>
+where
+    F: FnMut(usize) -> std::io::Result<Option<R>>,
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while !self.done {
+            let reader = match self.current.as_mut() {
+                Some(r) => r,
+                None => match (self.open_part)(self.index)? {
+                    None => {
+                        self.done = true;
+                        break;
+                    }
+                    Some(r) => self.current.insert(r),
+                },
+            };
+            match reader.read(buf) {
+                Ok(0) if !buf.is_empty() => {
+                    self.current = None;
+                    self.index += 1;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                result => return result,
+            }
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Cursor, Write};
+
+    use aes_gcm::Aes128Gcm;
+
+    use crate::record::{HEADER_SIZE, TRAILER_SIZE};
+    use crate::{
+        decrypt, decrypt_parts, encrypt_parts, min_part_size, new_aes128gcm_cipher, DecryptOptions,
+        EncryptOptions,
+    };
+
+    struct Part<'a>(&'a RefCell<Vec<Vec<u8>>>, usize);
+    impl Write for Part<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut()[self.1].extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn encrypt_to_parts(data: &[u8], key: &str) -> Vec<Vec<u8>> {
+        let cipher = new_aes128gcm_cipher(key);
+        let mut rng = rand::thread_rng();
+        let parts = RefCell::new(vec![]);
+        let options = EncryptOptions::default();
+        let part_size = min_part_size::<Aes128Gcm>(&options) * 2;
+        let count = encrypt_parts(
+            &mut Cursor::new(data),
+            |i| {
+                parts.borrow_mut().push(vec![]);
+                Ok(Part(&parts, i))
+            },
+            part_size,
+            &cipher,
+            &mut rng,
+            &options,
+        )
+        .unwrap();
+        let parts = parts.into_inner();
+        assert_eq!(count, parts.len());
+        for part in &parts {
+            assert!(part.len() as u64 <= part_size);
+        }
+        parts
+    }
+
+    fn decrypt_from_parts(parts: &[Vec<u8>]) -> Result<Vec<u8>, Vec<String>> {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut decrypted = vec![];
+        decrypt_parts(
+            |i| Ok(parts.get(i).map(Cursor::new)),
+            &mut decrypted,
+            &cipher,
+            &DecryptOptions::default(),
+        )?;
+        Ok(decrypted)
+    }
+
+    #[test]
+    fn test_parts() {
+        let mut raw_bytes = vec![0u8; 4096 * 10 + 100];
+        for b in raw_bytes.iter_mut() {
+            *b = rand::random();
+        }
+
+        let parts = encrypt_to_parts(&raw_bytes, "test");
+        assert_eq!(parts.len(), 6);
+        assert_eq!(decrypt_from_parts(&parts).unwrap(), raw_bytes);
+
+        let empty = encrypt_to_parts(b"", "test");
+        assert_eq!(empty.len(), 1);
+        assert!(decrypt_from_parts(&empty).unwrap().is_empty());
+
+        // the parts concatenated are a valid encrypted file as well
+        let cipher = new_aes128gcm_cipher("test");
+        let mut decrypted = vec![];
+        decrypt(&mut Cursor::new(parts.concat()), &mut decrypted, &cipher).unwrap();
+        assert_eq!(decrypted, raw_bytes);
+
+        let mut missing_last = parts.clone();
+        missing_last.pop();
+        assert!(decrypt_from_parts(&missing_last).is_err());
+
+        let mut missing_middle = parts.clone();
+        missing_middle.remove(2);
+        assert!(decrypt_from_parts(&missing_middle).is_err());
+
+        let mut swapped = parts.clone();
+        swapped.swap(1, 2);
+        assert!(decrypt_from_parts(&swapped).is_err());
+
+        let mut truncated = parts.clone();
+        let len = truncated[1].len();
+        truncated[1].drain(..len / 2);
+        assert!(decrypt_from_parts(&truncated).is_err());
+
+        // truncated at a record boundary, before the trailer of the only or the first part
+        let trailer_len = HEADER_SIZE + 12 + TRAILER_SIZE + 16;
+        let single = encrypt_to_parts(&raw_bytes[..100], "test");
+        assert_eq!(single.len(), 1);
+        let len = single[0].len() - trailer_len;
+        assert!(decrypt_from_parts(&[single[0][..len].to_vec()]).is_err());
+        let len = parts[0].len() - trailer_len;
+        assert!(decrypt_from_parts(&[parts[0][..len].to_vec()]).is_err());
+
+        // same key and part layout, but from another archive
+        let other = encrypt_to_parts(&raw_bytes, "test");
+        let mut foreign = parts.clone();
+        foreign[3] = other[3].clone();
+        assert!(decrypt_from_parts(&foreign).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+
+use crate::SecretBuffer;
+
+const POOL_SIZE: usize = 64;
+
+/// Hands out buffers to the reader thread. At most `POOL_SIZE` buffers are allocated; once they
+/// are all in flight, `take` blocks until the writer thread recycles one, which also keeps the
+/// reader from running far ahead of the writer.
+pub(crate) struct BufferPool {
+    rx: Receiver<SecretBuffer>,
+    allocated: usize,
+    buffer_size: usize,
+    spare: Vec<SecretBuffer>,
+}
+
+pub(crate) struct BufferRecycler {
+    tx: Sender<SecretBuffer>,
+}
+
+pub(crate) fn buffer_pool(buffer_size: usize) -> (BufferPool, BufferRecycler) {
+    let (tx, rx) = crossbeam_channel::bounded(POOL_SIZE);
+    let pool = BufferPool {
+        rx,
+        allocated: 0,
+        buffer_size,
+        spare: vec![],
+    };
+    (pool, BufferRecycler { tx })
+}
+
+impl BufferPool {
+    /// Returns `None` if the recycler is gone, i.e. the writer thread has stopped.
+    pub(crate) fn take(&mut self) -> Option<SecretBuffer> {
+        if let Some(buf) = self.spare.pop() {
+            return Some(buf);
+        }
+        match self.rx.try_recv() {
+            Ok(buf) => return Some(buf),
+            Err(TryRecvError::Disconnected) => return None,
+            Err(TryRecvError::Empty) => {}
+        }
+        if self.allocated < POOL_SIZE {
+            self.allocated += 1;
+            return Some(SecretBuffer::new(Vec::with_capacity(self.buffer_size)));
+        }
+        self.rx.recv().ok()
+    }
+
+    /// Like `take`, but allocates rather than waits. For the reader thread while it holds back
+    /// buffers the writer thread would otherwise wait for.
+    pub(crate) fn take_now(&mut self) -> SecretBuffer {
+        if let Some(buf) = self.spare.pop() {
+            return buf;
+        }
+        match self.rx.try_recv() {
+            Ok(buf) => buf,
+            Err(_) => {
+                self.allocated += 1;
+                SecretBuffer::new(Vec::with_capacity(self.buffer_size))
+            }
+        }
+    }
+
+    /// Returns a buffer which the reader thread didn't pass on.
+    pub(crate) fn give_back(&mut self, mut buf: SecretBuffer) {
+        buf.clear();
+        self.spare.push(buf);
+    }
+}
+
+impl BufferRecycler {
+    pub(crate) fn recycle(&self, mut buf: SecretBuffer) {
+        buf.clear();
+        let _ = self.tx.send(buf); // the reader may have stopped; just drop the buffer
+    }
+}
@@ -0,0 +1,193 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+use aes_gcm::aes::cipher::Unsigned;
+use aes_gcm::AeadInPlace;
+
+use crate::record::{
+    decode_header, read_header, HEADER_SIZE, KIND_DATA, KIND_PARITY, KIND_PARITY_INFO, KIND_SHA256,
+    KIND_SIGNATURE,
+};
+use crate::{open_in_place, SecretBuffer};
+
+/// Reads the plaintext of an encrypted file at any offset, decrypting only the chunks which are
+/// read. Opening scans the record headers (not the bodies) to index the data records. Parity
+/// records are skipped and damaged chunks are not repaired, nor are signatures and plaintext
+/// hashes verified; split archives aren't supported.
+pub struct RandomAccess<'a, R, C> {
+    reader: R,
+    cipher: &'a C,
+    // the offset of each data record in the file, of its plaintext, and its body length
+    records: Vec<(u64, u64, usize)>,
+    len: u64,
+    cached: Option<(usize, SecretBuffer)>,
+}
+
+impl<'a, R: Read + Seek, C: AeadInPlace> RandomAccess<'a, R, C> {
+    pub fn new(mut reader: R, cipher: &'a C) -> Result<Self, String> {
+        let min_len = C::NonceSize::to_usize() + C::TagSize::to_usize();
+        let file_len = match reader.seek(SeekFrom::End(0)) {
+            Ok(l) => l,
+            Err(e) => {
+                let msg = format!("seek error: {e}");
+                return Err(msg);
+            }
+        };
+        let mut records = vec![];
+        let mut offset = 0u64;
+        let mut len = 0u64;
+        while offset < file_len {
+            if let Err(e) = reader.seek(SeekFrom::Start(offset)) {
+                let msg = format!("seek error: {e}");
+                return Err(msg);
+            }
+            let header = match read_header(&mut reader)? {
+                Some(h) => h,
+                None => break,
+            };
+            let (kind, body_len) = decode_header(&header);
+            match kind {
+                KIND_DATA => {
+                    if body_len <= min_len {
+                        let msg = format!("read error, illegal record length {body_len}");
+                        return Err(msg);
+                    }
+                    records.push((offset, len, body_len));
+                    len += (body_len - min_len) as u64;
+                }
+                KIND_PARITY | KIND_PARITY_INFO | KIND_SHA256 | KIND_SIGNATURE => {}
+                _ => {
+                    let msg = format!("unsupported record kind {kind}");
+                    return Err(msg);
+                }
+            }
+            offset += (HEADER_SIZE + body_len) as u64;
+        }
+        if offset > file_len {
+            return Err("read error: failed to fill whole buffer".to_string());
+        }
+        Ok(Self {
+            reader,
+            cipher,
+            records,
+            len,
+            cached: None,
+        })
+    }
+
+    /// The length of the plaintext.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads the plaintext at `offset` into `buf`; returns the number of bytes read, which is
+    /// only less than the length of `buf` at the end of the plaintext.
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, String> {
+        let plaintext_offset = HEADER_SIZE + C::NonceSize::to_usize();
+        let mut read = 0;
+        while read < buf.len() {
+            let pos = offset + read as u64;
+            if pos >= self.len {
+                break;
+            }
+            let index = self.records.partition_point(|r| r.1 <= pos) - 1;
+            let start = plaintext_offset + (pos - self.records[index].1) as usize;
+            let chunk = self.chunk(index)?;
+            let len = (buf.len() - read).min(chunk.len() - start);
+            buf[read..read + len].copy_from_slice(&chunk[start..start + len]);
+            read += len;
+        }
+        Ok(read)
+    }
+
+    fn chunk(&mut self, index: usize) -> Result<&SecretBuffer, String> {
+        if self.cached.as_ref().is_none_or(|c| c.0 != index) {
+            let mut buf = self.cached.take().map(|c| c.1).unwrap_or_default();
+            let (offset, _, indexed_len) = self.records[index];
+            buf.clear();
+            buf.resize(HEADER_SIZE, 0);
+            self.read_exact_at(offset, &mut buf)?;
+            // the file may have changed since it was indexed
+            let (kind, body_len) = decode_header(&buf);
+            if kind != KIND_DATA || body_len != indexed_len {
+                return Err(format!("the record at offset {offset} has changed"));
+            }
+            buf.resize(HEADER_SIZE + body_len, 0);
+            self.read_exact_at(offset + HEADER_SIZE as u64, &mut buf[HEADER_SIZE..])?;
+            open_in_place(self.cipher, &mut buf)?;
+            self.cached = Some((index, buf));
+        }
+        Ok(&self.cached.as_ref().unwrap().1)
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), String> {
+        if let Err(e) = self.reader.seek(SeekFrom::Start(offset)) {
+            let msg = format!("seek error: {e}");
+            return Err(msg);
+        }
+        match self.reader.read_exact(buf) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                Err("read error: failed to fill whole buffer".to_string())
+            }
+            Err(e) => Err(format!("read error: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{encrypt_with_options, new_aes128gcm_cipher, EncryptOptions, Parity, RandomAccess};
+
+    #[test]
+    fn test_random_access() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let raw_bytes: Vec<u8> = (0..4096 * 40 + 123).map(|_| rand::random()).collect();
+        for parity in [None, Some(Parity::new(4, 2).unwrap())] {
+            let mut encrypted = vec![];
+            let options = EncryptOptions {
+                parity,
+                ..Default::default()
+            };
+            let mut cursor = Cursor::new(&raw_bytes);
+            encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+
+            let mut file = RandomAccess::new(Cursor::new(&encrypted), &cipher).unwrap();
+            assert_eq!(file.len(), raw_bytes.len() as u64);
+            for (offset, len) in [(0, 10), (4090, 20), (5000, 3 * 4096), (4096 * 40, 1000)] {
+                let mut buf = vec![0u8; len];
+                let read = file.read_at(offset as u64, &mut buf).unwrap();
+                let expected = &raw_bytes[offset..(offset + len).min(raw_bytes.len())];
+                assert_eq!(&buf[..read], expected);
+            }
+            let mut buf = [0u8; 10];
+            assert_eq!(file.read_at(raw_bytes.len() as u64 + 5, &mut buf), Ok(0));
+
+            let mut damaged = encrypted.clone();
+            damaged[100] ^= 1; // in the first data record
+            let mut file = RandomAccess::new(Cursor::new(&damaged), &cipher).unwrap();
+            assert!(file.read_at(0, &mut buf).is_err());
+            assert!(file.read_at(4096 * 2, &mut buf).is_ok());
+
+            let truncated = &encrypted[..encrypted.len() - 1];
+            assert!(RandomAccess::new(Cursor::new(truncated), &cipher).is_err());
+
+            // the first data record replaced by a shorter valid one after indexing
+            let mut short = vec![];
+            let mut cursor = Cursor::new(&raw_bytes[..10]);
+            encrypt_with_options(&mut cursor, &mut short, &cipher, &mut rng, &options).unwrap();
+            let mut file = RandomAccess::new(Cursor::new(encrypted.clone()), &cipher).unwrap();
+            let record_len = 4 + 12 + 10 + 16;
+            let data_offset = file.records[0].0 as usize;
+            file.reader.get_mut()[data_offset..data_offset + record_len]
+                .copy_from_slice(&short[data_offset..data_offset + record_len]);
+            assert!(file.read_at(4000, &mut buf).is_err());
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::{ErrorKind, Read, Write};
+
+// Every record begins with a little-endian u32 header: the lower 24 bits are the length of the
+// record body (nonce, ciphertext and tag), the upper 8 bits the kind of the record. Data records
+// are of kind 0 and authenticated without associated data, so files which only consist of data
+// records are readable by older versions. Other records use the header as associated data, except
+// for the parity records, which are computed over the encrypted records and stored as they are.
+pub(crate) const HEADER_SIZE: usize = 4;
+const MAX_BODY_SIZE: usize = 0x00FF_FFFF;
+
+pub(crate) const KIND_DATA: u8 = 0;
+pub(crate) const KIND_PART: u8 = 1;
+pub(crate) const KIND_PARITY: u8 = 2;
+pub(crate) const KIND_PARITY_INFO: u8 = 3;
+pub(crate) const KIND_SIGNATURE: u8 = 4;
+pub(crate) const KIND_SHA256: u8 = 5;
+
+// The record of kind `KIND_SHA256` holds the SHA-256 of the whole plaintext. It follows the last
+// data (and parity) record and comes before the signature, so the signature covers it.
+pub(crate) const SHA256_SIZE: usize = 32;
+
+pub(crate) fn encode_header(kind: u8, body_size: usize) -> [u8; HEADER_SIZE] {
+    assert!(body_size <= MAX_BODY_SIZE);
+    (body_size as u32 | (kind as u32) << 24).to_le_bytes()
+}
+
+pub(crate) fn decode_header(header: &[u8]) -> (u8, usize) {
+    let mut bytes = [0u8; HEADER_SIZE];
+    bytes.copy_from_slice(&header[..HEADER_SIZE]);
+    let value = u32::from_le_bytes(bytes);
+    ((value >> 24) as u8, (value & MAX_BODY_SIZE as u32) as usize)
+}
+
+pub(crate) fn associated_data(record: &[u8]) -> &[u8] {
+    match decode_header(record).0 {
+        KIND_DATA => b"",
+        _ => &record[..HEADER_SIZE],
+    }
+}
+
+/// Reads a record header; returns `None` at the end of input.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> Result<Option<[u8; HEADER_SIZE]>, String> {
+    let mut header = [0u8; HEADER_SIZE];
+    let mut len = 0;
+    while len < HEADER_SIZE {
+        match reader.read(&mut header[len..]) {
+            Ok(0) => {
+                if len == 0 {
+                    return Ok(None);
+                }
+                let msg = format!("read error, illegal length {len}");
+                return Err(msg);
+            }
+            Ok(n) => {
+                len += n;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                let msg = format!("read error: {e}");
+                return Err(msg);
+            }
+        }
+    }
+    Ok(Some(header))
+}
+
+/// A reader which can be handed back the bytes read ahead, e.g. a header which turned out to
+/// belong to the next record.
+pub(crate) struct Rewind<'a, R> {
+    reader: &'a mut R,
+    pending: Vec<u8>,
+}
+impl<'a, R: Read> Rewind<'a, R> {
+    pub(crate) fn new(reader: &'a mut R) -> Self {
+        Self {
+            reader,
+            pending: vec![],
+        }
+    }
+
+    pub(crate) fn unread(&mut self, bytes: &[u8]) {
+        self.pending.splice(..0, bytes.iter().copied());
+    }
+}
+impl<R: Read> Read for Rewind<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            return self.reader.read(buf);
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+/// Where the writer thread puts the records. Any `Write` takes them as a single stream; split
+/// archives put them into multiple parts.
+pub(crate) trait RecordWriter {
+    fn write_record(&mut self, record: &[u8]) -> std::io::Result<()>;
+}
+
+impl<W: Write> RecordWriter for W {
+    fn write_record(&mut self, record: &[u8]) -> std::io::Result<()> {
+        self.write_all(record)
+    }
+}
+
+// The trailer which ends every part of a split archive: the index of the part, whether it's the
+// last part, and the digest of the chain of record tags up to (excluding) the trailer itself.
+pub(crate) const TRAILER_SIZE: usize = 4 + 1 + 32;
+
+pub(crate) struct PartTrailer {
+    pub(crate) index: u32,
+    pub(crate) last: bool,
+    pub(crate) chain: [u8; 32],
+}
+impl PartTrailer {
+    pub(crate) fn encode(&self) -> [u8; TRAILER_SIZE] {
+        let mut bytes = [0u8; TRAILER_SIZE];
+        bytes[..4].copy_from_slice(&self.index.to_le_bytes());
+        bytes[4] = self.last as u8;
+        bytes[5..].copy_from_slice(&self.chain);
+        bytes
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != TRAILER_SIZE || bytes[4] > 1 {
+            return None;
+        }
+        let mut index = [0u8; 4];
+        index.copy_from_slice(&bytes[..4]);
+        let mut chain = [0u8; 32];
+        chain.copy_from_slice(&bytes[5..]);
+        Some(Self {
+            index: u32::from_le_bytes(index),
+            last: bytes[4] == 1,
+            chain,
+        })
+    }
+}
@@ -0,0 +1,320 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use aes_gcm::aes::cipher::Unsigned;
+use aes_gcm::AeadInPlace;
+use sha2::{Digest, Sha256};
+
+use crate::parity::{decode_info, encode_info, ParityEncoder};
+use crate::record::{
+    decode_header, read_header, HEADER_SIZE, KIND_DATA, KIND_PARITY, KIND_PARITY_INFO,
+};
+use crate::signature::SignatureChain;
+use crate::{
+    encrypt_records, open_in_place, EncryptOptions, NonceSource, SecretBuffer, CHUNK_SIZE,
+};
+
+/// The state of the encryptor at the point an interrupted run is continued from.
+pub(crate) struct Resumed {
+    pub(crate) signature_chain: SignatureChain,
+    pub(crate) sha256: Sha256,
+    // the bodies of the data records of the parity group which is not complete yet
+    pub(crate) group: Vec<Vec<u8>>,
+}
+
+// A position in the partial output which encryption can be continued from: right after a data
+// record, except one which completes a parity group, as its parity records must follow, or right
+// after the parity records of a group.
+#[derive(Clone)]
+struct Point {
+    offset: u64,
+    plaintext_len: u64,
+    // the number of records sealed before, which the deterministic nonces count
+    sealed: u64,
+    last_chunk: SecretBuffer,
+    short: bool,
+    signature_chain: SignatureChain,
+    sha256: Sha256,
+}
+
+/// Continues an encryption into `output`, the partial output of an interrupted run with the same
+/// key and options. The records written already are authenticated, and kept up to the last one
+/// encryption can continue from; the rest is cut off and the input is read on from the end of the
+/// kept data. The result is the same as that of an uninterrupted run, byte for byte with
+/// deterministic nonces. Returns the input offset encryption resumed at.
+///
+/// The records which end a file (the parity of the last group, the SHA-256 and the signature) are
+/// always written again, so a complete output is rewritten from its end. Split archives can't be
+/// resumed.
+pub fn resume_encryption<R, C, N>(
+    reader: &mut R,
+    output: &mut File,
+    cipher: &C,
+    nonce_source: &mut N,
+    options: &EncryptOptions,
+) -> Result<u64, Vec<String>>
+where
+    R: Read + Seek,
+    C: AeadInPlace,
+    N: NonceSource,
+{
+    let (point, group) = scan(output, cipher, options).map_err(|msg| vec![msg])?;
+    check_input(reader, &point).map_err(|msg| vec![msg])?;
+
+    let seek = output
+        .set_len(point.offset)
+        .and_then(|_| output.seek(SeekFrom::Start(point.offset)))
+        .and_then(|_| reader.seek(SeekFrom::Start(point.plaintext_len)));
+    if let Err(e) = seek {
+        return Err(vec![format!("seek error: {e}")]);
+    }
+    // nothing kept, not even the parity info record
+    let resumed = (point.offset > 0).then_some(Resumed {
+        signature_chain: point.signature_chain,
+        sha256: point.sha256,
+        group,
+    });
+    nonce_source.skip(point.sealed);
+    encrypt_records(reader, output, cipher, nonce_source, options, None, resumed)?;
+    Ok(point.plaintext_len)
+}
+
+// Returns the last point to continue from and the data records of its incomplete parity group.
+fn scan<C: AeadInPlace>(
+    output: &mut File,
+    cipher: &C,
+    options: &EncryptOptions,
+) -> Result<(Point, Vec<Vec<u8>>), String> {
+    let nonce_size = C::NonceSize::to_usize();
+    let tag_size = C::TagSize::to_usize();
+    let full_size = nonce_size + CHUNK_SIZE + tag_size;
+    let offset = HEADER_SIZE + nonce_size;
+    if let Err(e) = output.seek(SeekFrom::Start(0)) {
+        return Err(format!("seek error: {e}"));
+    }
+    let mut reader = BufReader::new(output);
+
+    let mut point = Point {
+        offset: 0,
+        plaintext_len: 0,
+        sealed: 0,
+        last_chunk: SecretBuffer::default(),
+        short: false,
+        signature_chain: SignatureChain::new(),
+        sha256: Sha256::new(),
+    };
+    let mut group = vec![];
+    let mut parity_records: Vec<SecretBuffer> = vec![]; // expected after a complete group
+    let mut parity_read = 0;
+    let mut current = point.clone();
+    let mut buf = SecretBuffer::default();
+    // until the end, or a partial header
+    while let Ok(Some(header)) = read_header(&mut reader) {
+        let (kind, len) = decode_header(&header);
+        buf.clear();
+        buf.extend_from_slice(&header);
+        buf.resize(HEADER_SIZE + len, 0);
+        if reader.read_exact(&mut buf[HEADER_SIZE..]).is_err() {
+            break;
+        }
+
+        if current.offset == 0 {
+            let mismatch = "the partial output has other parity than requested";
+            let is_info = kind == KIND_PARITY_INFO && decode_info(&buf[HEADER_SIZE..]).is_some();
+            match (is_info, options.parity) {
+                (true, Some(parity)) if buf[HEADER_SIZE..] == encode_info(&parity) => {
+                    current.offset = buf.len() as u64;
+                    point = current.clone();
+                    continue;
+                }
+                (true, _) => {
+                    return Err(mismatch.to_string());
+                }
+                (false, Some(_)) => {
+                    // a data record tells a file without parity, anything else is damage
+                    let data = kind == KIND_DATA && len > nonce_size + tag_size;
+                    if data && open_in_place(cipher, &mut buf.clone()).is_ok() {
+                        return Err(mismatch.to_string());
+                    }
+                    break;
+                }
+                (false, None) => {}
+            }
+        }
+
+        match kind {
+            KIND_DATA
+                if len > nonce_size + tag_size
+                    && len <= full_size
+                    && parity_records.is_empty()
+                    && !current.short =>
+            {
+                let body = buf[HEADER_SIZE..].to_vec();
+                match open_in_place(cipher, &mut buf) {
+                    Ok(_) => {}
+                    Err(_) if current.sealed == 0 => {
+                        let msg = "the partial output doesn't decrypt with the key given";
+                        return Err(msg.to_string());
+                    }
+                    Err(_) => break,
+                }
+                current.offset += (HEADER_SIZE + len) as u64;
+                current.plaintext_len += (buf.len() - offset) as u64;
+                current.sealed += 1;
+                current.last_chunk.clear();
+                current.last_chunk.extend_from_slice(&buf[offset..]);
+                current.short = len < full_size;
+                current.signature_chain.update(&header, &body);
+                current.sha256.update(&buf[offset..]);
+                if let Some(parity) = options.parity {
+                    group.push(body);
+                    if group.len() == parity.data_chunks {
+                        let mut encoder = ParityEncoder::new(parity, full_size);
+                        for body in &group {
+                            encoder.push(body);
+                        }
+                        parity_records = encoder.finish_group();
+                        continue;
+                    }
+                }
+                point = current.clone();
+            }
+            KIND_PARITY if !parity_records.is_empty() => {
+                // parity records aren't authenticated, but they're computed from the data
+                if buf != parity_records[parity_read] {
+                    break;
+                }
+                parity_read += 1;
+                current.offset += buf.len() as u64;
+                if parity_read == parity_records.len() {
+                    parity_records.clear();
+                    parity_read = 0;
+                    group.clear();
+                    point = current.clone();
+                }
+            }
+            _ => break, // the records ending the file, or damage
+        }
+    }
+    if !parity_records.is_empty() {
+        group.pop(); // the group is continued from before its last data record
+    }
+    Ok((point, group))
+}
+
+// The input must be the one which was being encrypted, as far as the last chunk kept tells;
+// comparing all of it would mean reading as much again as the output holds.
+fn check_input<R: Read + Seek>(reader: &mut R, point: &Point) -> Result<(), String> {
+    let chunk = &point.last_chunk;
+    let start = point.plaintext_len - chunk.len() as u64;
+    if let Err(e) = reader.seek(SeekFrom::Start(start)) {
+        return Err(format!("seek error: {e}"));
+    }
+    // a short chunk must end the input
+    let len = chunk.len() + point.short as usize;
+    let mut input = SecretBuffer::new(Vec::with_capacity(len));
+    if let Err(e) = reader.take(len as u64).read_to_end(&mut input) {
+        return Err(format!("read error: {e}"));
+    }
+    if input[..] != chunk[..] {
+        return Err("the input doesn't match the partial output".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::{Cursor, Write};
+
+    use crate::{
+        decrypt, encrypt_with_options, generate_signing_key, new_aes128gcm_cipher,
+        resume_encryption, DeterministicNonce, EncryptOptions, Parity,
+    };
+
+    #[test]
+    fn test_resume() {
+        let cipher = new_aes128gcm_cipher("test");
+        let raw_bytes: Vec<u8> = (0..4096 * 7 + 100).map(|_| rand::random()).collect();
+        let path = std::env::temp_dir().join(format!("file-crypto-test-{}", rand::random::<u64>()));
+
+        let parities = [None, Some(Parity::new(3, 2).unwrap())];
+        for (parity, record_sha256) in parities.into_iter().zip([true, false]) {
+            let options = EncryptOptions {
+                parity,
+                signing_key: Some(generate_signing_key()),
+                record_sha256,
+            };
+            let mut complete = vec![];
+            let mut nonce_source = DeterministicNonce::new("test");
+            let mut cursor = Cursor::new(&raw_bytes);
+            encrypt_with_options(
+                &mut cursor,
+                &mut complete,
+                &cipher,
+                &mut nonce_source,
+                &options,
+            )
+            .unwrap();
+
+            // cut anywhere, within records and between them, and garbage at the end
+            let cuts = (0..complete.len()).step_by(1531).chain([complete.len()]);
+            for cut in cuts {
+                for garbage in [&b""[..], &[0u8; 100][..]] {
+                    let mut partial = complete[..cut].to_vec();
+                    partial.extend_from_slice(garbage);
+                    fs::write(&path, &partial).unwrap();
+                    let mut output = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .open(&path)
+                        .unwrap();
+                    let mut nonce_source = DeterministicNonce::new("test");
+                    let mut cursor = Cursor::new(&raw_bytes);
+                    let offset = resume_encryption(
+                        &mut cursor,
+                        &mut output,
+                        &cipher,
+                        &mut nonce_source,
+                        &options,
+                    )
+                    .unwrap();
+                    assert!(offset <= raw_bytes.len() as u64);
+                    output.flush().unwrap();
+                    assert!(fs::read(&path).unwrap() == complete, "cut at {cut}");
+                }
+            }
+
+            // another input, or another key, isn't continued
+            fs::write(&path, &complete[..complete.len() / 2]).unwrap();
+            let mut output = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let other: Vec<u8> = (0..raw_bytes.len()).map(|_| rand::random()).collect();
+            let mut rng = rand::thread_rng();
+            resume_encryption(
+                &mut Cursor::new(&other),
+                &mut output,
+                &cipher,
+                &mut rng,
+                &options,
+            )
+            .unwrap_err();
+            let other_cipher = new_aes128gcm_cipher("other");
+            let mut cursor = Cursor::new(&raw_bytes);
+            resume_encryption(&mut cursor, &mut output, &other_cipher, &mut rng, &options)
+                .unwrap_err();
+
+            // random nonces resume into a valid file as well
+            let mut cursor = Cursor::new(&raw_bytes);
+            resume_encryption(&mut cursor, &mut output, &cipher, &mut rng, &options).unwrap();
+            let mut decrypted = vec![];
+            let encrypted = fs::read(&path).unwrap();
+            decrypt(&mut Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+            assert_eq!(decrypted, raw_bytes);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::mem::{size_of, ManuallyDrop};
+use std::ops::Deref;
+use std::{ptr, slice};
+
+use zeroize::{Zeroize, Zeroizing};
+
+/// A string (e.g. the secret key from the command line) which is wiped on drop.
+pub type SecretString = Zeroizing<String>;
+
+/// A plaintext buffer which is wiped on drop.
+pub type SecretBuffer = Zeroizing<Vec<u8>>;
+
+/// Keeps the value (usually a cipher, which wipes its key material on drop) on the heap, so it
+/// is not copied around while in use. Its memory is wiped once dropped, whatever the value left
+/// there. With the `mlock` feature on Linux, the pages holding the
+/// value are also locked into memory, so the key material is never written to swap.
+///
+/// Note that the value passed to `new` may still leave a copy on the stack of the caller.
+pub struct SecretBox<T> {
+    inner: ManuallyDrop<Box<T>>,
+}
+impl<T> SecretBox<T> {
+    pub fn new(value: T) -> Self {
+        let inner = Box::new(value);
+        lock_memory(&*inner);
+        Self {
+            inner: ManuallyDrop::new(inner),
+        }
+    }
+}
+impl<T> Deref for SecretBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+impl<T> Drop for SecretBox<T> {
+    fn drop(&mut self) {
+        // drop and wipe the value before unlocking its pages, then free the memory
+        let raw = Box::into_raw(unsafe { ManuallyDrop::take(&mut self.inner) });
+        unsafe { drop_and_wipe(raw) };
+        unlock_memory(raw as *const T);
+        drop(unsafe { Box::from_raw(raw as *mut ManuallyDrop<T>) });
+    }
+}
+
+// the memory is left zeroed, to be freed without dropping again
+unsafe fn drop_and_wipe<T>(value: *mut T) {
+    ptr::drop_in_place(value);
+    wipe_bytes(value);
+}
+
+/// Zeroes the memory of a value in place, padding included. For plain data (integers and arrays,
+/// to which zeros are valid) holding key material without wiping it on drop, like HMAC states.
+///
+/// # Safety
+/// `value` must be valid for writes, and nothing must read it as `T` afterwards unless zeros are
+/// a valid `T`.
+pub(crate) unsafe fn wipe_bytes<T>(value: *mut T) {
+    slice::from_raw_parts_mut(value as *mut u8, size_of::<T>()).zeroize();
+}
+
+// mlock isn't counted: munlock unlocks a page for all the values on it, so each locked page
+// counts the values on it, and is unlocked with the last one
+#[cfg(all(feature = "mlock", target_os = "linux"))]
+static LOCKED_PAGES: std::sync::Mutex<std::collections::BTreeMap<usize, usize>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+// the addresses of the pages holding the value
+#[cfg(all(feature = "mlock", target_os = "linux"))]
+fn pages<T>(value: *const T) -> impl Iterator<Item = usize> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let len = std::mem::size_of::<T>();
+    let start = value as usize / page_size * page_size;
+    let end = if len == 0 {
+        start
+    } else {
+        value as usize + len
+    };
+    (start..end).step_by(page_size)
+}
+
+#[cfg(all(feature = "mlock", target_os = "linux"))]
+fn lock_memory<T>(value: *const T) {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap();
+    for page in pages(value) {
+        let count = locked_pages.entry(page).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            continue;
+        }
+        if unsafe { libc::mlock(page as *const libc::c_void, page_size) } != 0 {
+            let e = std::io::Error::last_os_error();
+            eprintln!("warning: failed to lock key material into memory; {e}");
+        }
+    }
+}
+#[cfg(all(feature = "mlock", target_os = "linux"))]
+fn unlock_memory<T>(value: *const T) {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap();
+    for page in pages(value) {
+        let count = locked_pages.get_mut(&page).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            locked_pages.remove(&page);
+            unsafe { libc::munlock(page as *const libc::c_void, page_size) };
+        }
+    }
+}
+
+#[cfg(not(all(feature = "mlock", target_os = "linux")))]
+fn lock_memory<T>(_value: *const T) {}
+#[cfg(not(all(feature = "mlock", target_os = "linux")))]
+fn unlock_memory<T>(_value: *const T) {}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::mem::{size_of, ManuallyDrop};
+    use std::slice;
+
+    use zeroize::{Zeroize, Zeroizing};
+
+    use super::drop_and_wipe;
+    use crate::{DeterministicNonce, NonceSource, SecretBox, SecretBuffer, SecretString};
+
+    fn is_zeroed<T>(value: *const T) -> bool {
+        let bytes = unsafe { slice::from_raw_parts(value as *const u8, size_of::<T>()) };
+        bytes.iter().all(|&b| b == 0)
+    }
+
+    struct DropCounter<'a>(&'a Cell<u32>);
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_secret_box() {
+        let count = Cell::new(0);
+        let secret = SecretBox::new(DropCounter(&count));
+        assert_eq!(secret.0.get(), 0);
+        drop(secret);
+        assert_eq!(count.get(), 1);
+    }
+
+    // what drop does, before the memory is freed
+    #[test]
+    fn test_zeroize() {
+        let mut buf = SecretBuffer::new(Vec::with_capacity(64));
+        buf.extend_from_slice(&[0xaa; 48]);
+        let (ptr, capacity) = (buf.as_ptr(), buf.capacity());
+        buf.zeroize();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+        let memory = unsafe { slice::from_raw_parts(ptr, capacity) };
+        assert!(memory.iter().all(|&b| b == 0));
+
+        let mut key = SecretString::new("correct horse battery staple".to_string());
+        let (ptr, len) = (key.as_ptr(), key.len());
+        key.zeroize();
+        assert!(key.is_empty());
+        let memory = unsafe { slice::from_raw_parts(ptr, len) };
+        assert!(memory.iter().all(|&b| b == 0));
+    }
+
+    // dropped in place rather than freed, so the memory can be inspected
+    #[test]
+    fn test_wipe_on_drop() {
+        // the HMAC key state of deterministic nonces doesn't wipe itself
+        let mut nonce = ManuallyDrop::new(DeterministicNonce::new("test"));
+        nonce.next_nonce::<aes_gcm::Aes128Gcm>(b"chunk");
+        assert!(!is_zeroed(&*nonce));
+        unsafe { ManuallyDrop::drop(&mut nonce) };
+        assert!(is_zeroed(&*nonce));
+
+        let mut key = ManuallyDrop::new(Zeroizing::new([0xaau8; 32]));
+        unsafe { ManuallyDrop::drop(&mut key) };
+        assert!(is_zeroed(&*key));
+
+        // as SecretBox drops its value, before freeing it
+        let raw = Box::into_raw(Box::new([0xaau8; 100]));
+        unsafe { drop_and_wipe(raw) };
+        assert!(is_zeroed(raw));
+        drop(unsafe { Box::from_raw(raw as *mut ManuallyDrop<[u8; 100]>) });
+        let secret = SecretBox::new(DeterministicNonce::new("test"));
+        drop(secret);
+    }
+
+    #[cfg(feature = "mlock")]
+    #[test]
+    fn test_secret_box_mlock() {
+        // small enough to share a page, most likely
+        let a = SecretBox::new([1u8; 32]);
+        let b = SecretBox::new([2u8; 32]);
+        assert_eq!(*a, [1; 32]);
+        drop(a);
+        #[cfg(target_os = "linux")]
+        {
+            let locked_pages = super::LOCKED_PAGES.lock().unwrap();
+            for page in super::pages(&*b as *const [u8; 32]) {
+                assert!(locked_pages.get(&page).is_some_and(|&n| n > 0));
+            }
+        }
+        assert_eq!(*b, [2; 32]);
+        drop(b);
+        let big = SecretBox::new([3u8; 10000]); // over several pages
+        assert_eq!(big[9999], 3);
+    }
+}
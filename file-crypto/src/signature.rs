@@ -0,0 +1,283 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::SecretString;
+
+// The signature record ends the file (or comes right before the trailer of the last part). Its
+// plaintext is the verifying key of the signer followed by an Ed25519 signature over a SHA-256
+// digest chained over every data record, part trailer and plaintext SHA-256 before it, as
+// encrypted. A GCM tag alone doesn't do: whoever has the key can alter a record and keep its tag.
+// Files signed over the headers and tags only (v1) still verify, but aren't written anymore.
+pub(crate) const SIGNATURE_SIZE: usize = 32 + 64;
+const DOMAIN: &[u8] = b"file-crypto signature v2\0";
+const LEGACY_DOMAIN: &[u8] = b"file-crypto signature v1\0";
+const TAG_SIZE: usize = 16; // of both AES-GCM ciphers
+
+#[derive(Clone)]
+pub(crate) struct SignatureChain {
+    digest: Sha256,
+    legacy: Sha256,
+}
+impl SignatureChain {
+    pub(crate) fn new() -> Self {
+        Self {
+            digest: Sha256::new_with_prefix(DOMAIN),
+            legacy: Sha256::new_with_prefix(LEGACY_DOMAIN),
+        }
+    }
+
+    // `body` is the nonce, the ciphertext and the tag
+    pub(crate) fn update(&mut self, header: &[u8], body: &[u8]) {
+        self.digest.update(header);
+        self.digest.update(body);
+        self.legacy.update(header);
+        self.legacy.update(&body[body.len() - TAG_SIZE..]);
+    }
+
+    pub(crate) fn sign(&self, key: &SigningKey) -> [u8; SIGNATURE_SIZE] {
+        let signature = key.sign(&self.digest.clone().finalize());
+        let mut bytes = [0u8; SIGNATURE_SIZE];
+        bytes[..32].copy_from_slice(key.verifying_key().as_bytes());
+        bytes[32..].copy_from_slice(&signature.to_bytes());
+        bytes
+    }
+
+    // returns the key which made the signature
+    pub(crate) fn verify(&self, bytes: &[u8]) -> Result<VerifyingKey, String> {
+        if bytes.len() != SIGNATURE_SIZE {
+            return Err("illegal signature record".to_string());
+        }
+        let key = match VerifyingKey::from_bytes(bytes[..32].try_into().unwrap()) {
+            Ok(k) => k,
+            Err(_) => {
+                return Err("illegal signer key".to_string());
+            }
+        };
+        let signature = Signature::from_bytes(bytes[32..].try_into().unwrap());
+        let verify = |digest: &Sha256| key.verify_strict(&digest.clone().finalize(), &signature);
+        match verify(&self.digest).or_else(|_| verify(&self.legacy)) {
+            Ok(_) => Ok(key),
+            Err(_) => Err("bad signature; the content doesn't match what was signed".to_string()),
+        }
+    }
+}
+
+/// Generates a signing key for `EncryptOptions::signing_key`.
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut rand::thread_rng())
+}
+
+/// Encodes a signing key as base64 text, as read by `parse_signing_key`.
+pub fn encode_signing_key(key: &SigningKey) -> SecretString {
+    SecretString::new(STANDARD.encode(key.as_bytes()))
+}
+
+pub fn parse_signing_key(text: &str) -> Result<SigningKey, String> {
+    let mut bytes = zeroize::Zeroizing::new([0u8; 32]);
+    match STANDARD.decode_slice(text.trim(), &mut bytes[..]) {
+        Ok(32) => Ok(SigningKey::from_bytes(&bytes)),
+        _ => Err("illegal signing key; expected 32 bytes in base64".to_string()),
+    }
+}
+
+/// Encodes a verifying (public) key as base64 text, as read by `parse_verifying_key`.
+pub fn encode_verifying_key(key: &VerifyingKey) -> String {
+    STANDARD.encode(key.as_bytes())
+}
+
+pub fn parse_verifying_key(text: &str) -> Result<VerifyingKey, String> {
+    let mut bytes = [0u8; 32];
+    match STANDARD.decode_slice(text.trim(), &mut bytes) {
+        Ok(32) => {}
+        _ => {
+            return Err("illegal verifying key; expected 32 bytes in base64".to_string());
+        }
+    }
+    match VerifyingKey::from_bytes(&bytes) {
+        Ok(k) => Ok(k),
+        Err(_) => Err("illegal verifying key".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Cursor, Write};
+
+    use aes_gcm::aes::cipher::{BlockEncrypt, KeyInit};
+    use aes_gcm::aes::Aes128;
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        decrypt, decrypt_with_options, encode_signing_key, encode_verifying_key, encrypt_parts,
+        encrypt_with_options, generate_signing_key, new_aes128gcm_cipher, open_in_place,
+        parse_signing_key, parse_verifying_key, DecryptOptions, EncryptOptions, Parity,
+        SecretBuffer,
+    };
+
+    struct Shared<'a>(&'a RefCell<Vec<u8>>);
+    impl Write for Shared<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let key = generate_signing_key();
+        let key = parse_signing_key(&encode_signing_key(&key)).unwrap();
+        let signer = parse_verifying_key(&encode_verifying_key(&key.verifying_key())).unwrap();
+        let other = generate_signing_key().verifying_key();
+        let raw_bytes: Vec<u8> = (0..4096 * 20 + 5).map(|_| rand::random()).collect();
+        let verify = |encrypted: &[u8], verify_signer| {
+            let options = DecryptOptions {
+                verify_signer,
+                ..Default::default()
+            };
+            let mut decrypted = vec![];
+            let mut cursor = Cursor::new(encrypted);
+            decrypt_with_options(&mut cursor, &mut decrypted, &cipher, &options)
+                .map(|report| (report.signer, decrypted))
+        };
+
+        for parity in [None, Some(Parity::new(4, 2).unwrap())] {
+            let options = EncryptOptions {
+                parity,
+                signing_key: Some(key.clone()),
+                ..Default::default()
+            };
+            let mut encrypted = vec![];
+            let mut cursor = Cursor::new(&raw_bytes);
+            encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+
+            let (reported, decrypted) = verify(&encrypted, Some(signer)).unwrap();
+            assert_eq!(reported, Some(signer));
+            assert_eq!(decrypted, raw_bytes);
+            assert_eq!(verify(&encrypted, None).unwrap().0, Some(signer));
+            assert!(verify(&encrypted, Some(other)).is_err());
+
+            // a dropped record is told, which the records alone don't tell
+            if parity.is_none() {
+                let record_len = 4 + 12 + 4096 + 16;
+                let mut dropped = encrypted[..record_len].to_vec();
+                dropped.extend_from_slice(&encrypted[2 * record_len..]);
+                let mut cursor = Cursor::new(&dropped);
+                decrypt(&mut cursor, &mut vec![], &cipher).unwrap_err();
+            }
+
+            // stripping the signature is told when a signer is expected
+            let mut unsigned = vec![];
+            let options = EncryptOptions {
+                parity,
+                signing_key: None,
+                ..Default::default()
+            };
+            let mut cursor = Cursor::new(&raw_bytes);
+            encrypt_with_options(&mut cursor, &mut unsigned, &cipher, &mut rng, &options).unwrap();
+            assert_eq!(verify(&unsigned, None).unwrap().0, None);
+            assert!(verify(&unsigned, Some(signer)).is_err());
+        }
+
+        // the signature goes into the last part, before its trailer; the parts concatenated are
+        // a valid encrypted file
+        let options = EncryptOptions {
+            parity: None,
+            signing_key: Some(key.clone()),
+            ..Default::default()
+        };
+        let concat = RefCell::new(vec![]);
+        let count = encrypt_parts(
+            &mut Cursor::new(&raw_bytes),
+            |_| Ok(Shared(&concat)),
+            10000,
+            &cipher,
+            &mut rng,
+            &options,
+        )
+        .unwrap();
+        assert!(count > 1);
+        let (reported, decrypted) = verify(&concat.into_inner(), Some(signer)).unwrap();
+        assert_eq!(reported, Some(signer));
+        assert_eq!(decrypted, raw_bytes);
+
+        // readers which don't care still decrypt signed files
+        let mut decrypted = vec![];
+        let mut encrypted = vec![];
+        let options = EncryptOptions {
+            parity: None,
+            signing_key: Some(key),
+            ..Default::default()
+        };
+        let mut cursor = Cursor::new(&raw_bytes);
+        encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+        decrypt(&mut Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+        assert_eq!(decrypted, raw_bytes);
+    }
+
+    // a multiplication in GF(2^128) as GHASH does it
+    fn gf_mul(x: u128, y: u128) -> u128 {
+        let mut z = 0;
+        let mut v = y;
+        for i in 0..128 {
+            if (x >> (127 - i)) & 1 == 1 {
+                z ^= v;
+            }
+            v = match v & 1 {
+                1 => (v >> 1) ^ (0xe1 << 120),
+                _ => v >> 1,
+            };
+        }
+        z
+    }
+
+    #[test]
+    fn test_signature_forgery() {
+        let cipher = new_aes128gcm_cipher("test");
+        let mut rng = rand::thread_rng();
+        let key = generate_signing_key();
+        let raw_bytes: Vec<u8> = (0..4096 * 3).map(|_| rand::random()).collect();
+        let options = EncryptOptions {
+            signing_key: Some(key.clone()),
+            ..Default::default()
+        };
+        let mut encrypted = vec![];
+        let mut cursor = Cursor::new(&raw_bytes);
+        encrypt_with_options(&mut cursor, &mut encrypted, &cipher, &mut rng, &options).unwrap();
+
+        // whoever has the key knows the GHASH key H; flipping a block by d and the next by d * H
+        // leaves the tag of the record as it was
+        let aes_key = Sha256::digest("test");
+        let mut h = [0u8; 16].into();
+        Aes128::new_from_slice(&aes_key[..16])
+            .unwrap()
+            .encrypt_block(&mut h);
+        let h = u128::from_be_bytes(h.into());
+        let d = 0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128;
+        let mut forged = encrypted.clone();
+        for (start, delta) in [(16, d), (32, gf_mul(d, h))] {
+            let block = u128::from_be_bytes(forged[start..start + 16].try_into().unwrap());
+            forged[start..start + 16].copy_from_slice(&(block ^ delta).to_be_bytes());
+        }
+        let record_len = 4 + 12 + 4096 + 16;
+        let mut record = SecretBuffer::new(forged[..record_len].to_vec());
+        open_in_place(&cipher, &mut record).unwrap();
+        assert_ne!(&record[16..], &raw_bytes[..4096]);
+
+        let options = DecryptOptions {
+            verify_signer: Some(key.verifying_key()),
+            ..Default::default()
+        };
+        let mut cursor = Cursor::new(&forged);
+        let result = decrypt_with_options(&mut cursor, &mut vec![], &cipher, &options);
+        assert!(result.is_err());
+        assert!(decrypt(&mut Cursor::new(&forged), &mut vec![], &cipher).is_err());
+    }
+}
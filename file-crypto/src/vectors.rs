@@ -0,0 +1,365 @@
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+
+use aes_gcm::AeadInPlace;
+
+use crate::{
+    decrypt_with_options, encrypt_parts, encrypt_with_options, new_aes128gcm_cipher,
+    new_aes256gcm_cipher, parse_signing_key, DecryptOptions, DeterministicNonce, EncryptOptions,
+    Parity,
+};
+
+// Known-answer test vectors are kept as text: a `[name]` line begins each vector, followed by
+// `field = value` lines. Lines beginning with whitespace continue the value above them, which is
+// how long hex values are wrapped; lines beginning with '#' are comments. The fields are:
+//
+//   algorithm   aes-128-gcm or aes-256-gcm
+//   key         the secret key (passphrase) as given on the command line
+//   parity      optional, K+M: M parity chunks for every group of K chunks
+//   part-size   optional, the part size of a split archive; the ciphertext is the parts
+//               concatenated, which is a valid encrypted file as well
+//   signing-key optional, the base64 Ed25519 signing key (seed)
+//   record-sha256 optional, yes to record the SHA-256 of the plaintext
+//   plaintext   hex
+//   ciphertext  hex, produced with the deterministic nonces derived from the key
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Aes128Gcm,
+    Aes256Gcm,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestVector {
+    pub name: String,
+    pub algorithm: Algorithm,
+    pub key: String,
+    pub parity: Option<Parity>,
+    pub part_size: Option<u64>,
+    pub signing_key: Option<String>,
+    pub record_sha256: bool,
+    pub plaintext: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl TestVector {
+    /// Checks that encrypting the plaintext reproduces the ciphertext byte for byte, and that the
+    /// ciphertext decrypts (and verifies, if signed) to the plaintext.
+    pub fn check(&self) -> Result<(), String> {
+        match self.algorithm {
+            Algorithm::Aes128Gcm => self.check_with(&new_aes128gcm_cipher(&self.key)),
+            Algorithm::Aes256Gcm => self.check_with(&new_aes256gcm_cipher(&self.key)),
+        }
+    }
+
+    /// Checks only that the ciphertext decrypts (and verifies, if signed) to the plaintext; for
+    /// vectors of an older format version, which aren't written anymore.
+    pub fn check_decryption(&self) -> Result<(), String> {
+        match self.algorithm {
+            Algorithm::Aes128Gcm => self.check_decryption_with(&new_aes128gcm_cipher(&self.key)),
+            Algorithm::Aes256Gcm => self.check_decryption_with(&new_aes256gcm_cipher(&self.key)),
+        }
+    }
+
+    fn check_with<C: AeadInPlace>(&self, cipher: &C) -> Result<(), String> {
+        let name = &self.name;
+        let signing_key = match &self.signing_key {
+            Some(key) => Some(parse_signing_key(key)?),
+            None => None,
+        };
+        let options = EncryptOptions {
+            parity: self.parity,
+            signing_key,
+            record_sha256: self.record_sha256,
+        };
+        let encrypted = self.encrypt(cipher, &options)?;
+        if encrypted != self.ciphertext {
+            let at = encrypted
+                .iter()
+                .zip(&self.ciphertext)
+                .position(|(a, b)| a != b)
+                .unwrap_or(encrypted.len().min(self.ciphertext.len()));
+            let msg = format!("'{name}': the ciphertext isn't reproduced; it differs at byte {at}");
+            return Err(msg);
+        }
+        self.check_decryption_with(cipher)
+    }
+
+    fn check_decryption_with<C: AeadInPlace>(&self, cipher: &C) -> Result<(), String> {
+        let name = &self.name;
+        let verify_signer = match &self.signing_key {
+            Some(key) => Some(parse_signing_key(key)?.verifying_key()),
+            None => None,
+        };
+        let options = DecryptOptions {
+            best_effort: false,
+            verify_signer,
+        };
+        let mut decrypted = vec![];
+        let mut cursor = Cursor::new(&self.ciphertext);
+        let report = match decrypt_with_options(&mut cursor, &mut decrypted, cipher, &options) {
+            Ok(r) => r,
+            Err(msgs) => {
+                let msg = format!("'{name}': failed to decrypt; {msgs:?}");
+                return Err(msg);
+            }
+        };
+        if decrypted != self.plaintext {
+            let msg = format!("'{name}': the ciphertext doesn't decrypt to the plaintext");
+            return Err(msg);
+        }
+        if report.sha256_recorded != self.record_sha256 {
+            let msg = format!("'{name}': the plaintext SHA-256 isn't recorded as expected");
+            return Err(msg);
+        }
+        Ok(())
+    }
+
+    fn encrypt<C: AeadInPlace>(
+        &self,
+        cipher: &C,
+        options: &EncryptOptions,
+    ) -> Result<Vec<u8>, String> {
+        let mut nonce_source = DeterministicNonce::new(&self.key);
+        let mut reader = Cursor::new(&self.plaintext);
+        let result = match self.part_size {
+            None => {
+                let mut encrypted = vec![];
+                encrypt_with_options(
+                    &mut reader,
+                    &mut encrypted,
+                    cipher,
+                    &mut nonce_source,
+                    options,
+                )
+                .map(|_| encrypted)
+            }
+            Some(part_size) => {
+                let encrypted = RefCell::new(vec![]);
+                encrypt_parts(
+                    &mut reader,
+                    |_| Ok(Concat(&encrypted)),
+                    part_size,
+                    cipher,
+                    &mut nonce_source,
+                    options,
+                )
+                .map(|_| encrypted.into_inner())
+            }
+        };
+        result.map_err(|msgs| format!("'{}': failed to encrypt; {msgs:?}", self.name))
+    }
+}
+
+// takes the parts of a split archive one after another
+struct Concat<'a>(&'a RefCell<Vec<u8>>);
+impl Write for Concat<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn parse_test_vectors(text: &str) -> Result<Vec<TestVector>, String> {
+    let mut sections: Vec<(String, Vec<(String, String)>)> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            let value = match sections.last_mut().and_then(|s| s.1.last_mut()) {
+                Some((_, v)) => v,
+                None => {
+                    return Err(format!("line {line_no}: continuation without a field"));
+                }
+            };
+            value.push_str(line.trim());
+        } else if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((name.to_string(), vec![]));
+        } else {
+            let (field, value) = match (sections.last_mut(), line.split_once('=')) {
+                (Some(section), Some(pair)) => (&mut section.1, pair),
+                _ => {
+                    return Err(format!(
+                        "line {line_no}: expected '[name]' or 'field = value'"
+                    ));
+                }
+            };
+            field.push((value.0.trim().to_string(), value.1.trim().to_string()));
+        }
+    }
+    sections
+        .into_iter()
+        .map(|(name, fields)| parse_vector(name, fields))
+        .collect()
+}
+
+fn parse_vector(name: String, fields: Vec<(String, String)>) -> Result<TestVector, String> {
+    let mut vector = TestVector {
+        name,
+        algorithm: Algorithm::Aes128Gcm,
+        key: String::new(),
+        parity: None,
+        part_size: None,
+        signing_key: None,
+        record_sha256: false,
+        plaintext: vec![],
+        ciphertext: vec![],
+    };
+    let mut seen = vec![];
+    for (field, value) in fields {
+        let illegal = |what: &str| format!("'{}': illegal {field} '{value}'; {what}", vector.name);
+        match field.as_str() {
+            "algorithm" => {
+                vector.algorithm = match value.as_str() {
+                    "aes-128-gcm" => Algorithm::Aes128Gcm,
+                    "aes-256-gcm" => Algorithm::Aes256Gcm,
+                    _ => {
+                        return Err(illegal("expected aes-128-gcm or aes-256-gcm"));
+                    }
+                }
+            }
+            "key" => {
+                vector.key = value.clone();
+            }
+            "parity" => {
+                let counts = value
+                    .split_once('+')
+                    .and_then(|(k, m)| Some((k.parse().ok()?, m.parse().ok()?)));
+                vector.parity = match counts {
+                    Some((k, m)) => Some(Parity::new(k, m).map_err(|e| illegal(&e))?),
+                    None => {
+                        return Err(illegal("expected K+M"));
+                    }
+                };
+            }
+            "part-size" => {
+                vector.part_size = Some(value.parse().map_err(|_| illegal("expected a number"))?);
+            }
+            "signing-key" => {
+                vector.signing_key = Some(value.clone());
+            }
+            "record-sha256" => {
+                vector.record_sha256 = match value.as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => {
+                        return Err(illegal("expected yes or no"));
+                    }
+                }
+            }
+            "plaintext" => {
+                vector.plaintext = decode_hex(&value).ok_or_else(|| illegal("expected hex"))?;
+            }
+            "ciphertext" => {
+                vector.ciphertext = decode_hex(&value).ok_or_else(|| illegal("expected hex"))?;
+            }
+            _ => {
+                return Err(format!("'{}': unknown field '{field}'", vector.name));
+            }
+        }
+        seen.push(field);
+    }
+    for required in ["algorithm", "key", "plaintext", "ciphertext"] {
+        if !seen.iter().any(|f| f == required) {
+            return Err(format!("'{}': missing field '{required}'", vector.name));
+        }
+    }
+    Ok(vector)
+}
+
+/// Formats the vectors as read by `parse_test_vectors`, wrapping the hex values at 64 columns.
+pub fn format_test_vectors(vectors: &[TestVector]) -> String {
+    let mut text = String::new();
+    for vector in vectors {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        let algorithm = match vector.algorithm {
+            Algorithm::Aes128Gcm => "aes-128-gcm",
+            Algorithm::Aes256Gcm => "aes-256-gcm",
+        };
+        writeln!(text, "[{}]", vector.name).unwrap();
+        writeln!(text, "algorithm = {algorithm}").unwrap();
+        writeln!(text, "key = {}", vector.key).unwrap();
+        if let Some(parity) = vector.parity {
+            let (k, m) = (parity.data_chunks, parity.parity_chunks);
+            writeln!(text, "parity = {k}+{m}").unwrap();
+        }
+        if let Some(part_size) = vector.part_size {
+            writeln!(text, "part-size = {part_size}").unwrap();
+        }
+        if let Some(signing_key) = &vector.signing_key {
+            writeln!(text, "signing-key = {signing_key}").unwrap();
+        }
+        if vector.record_sha256 {
+            writeln!(text, "record-sha256 = yes").unwrap();
+        }
+        for (field, bytes) in [
+            ("plaintext", &vector.plaintext),
+            ("ciphertext", &vector.ciphertext),
+        ] {
+            writeln!(text, "{field} =").unwrap();
+            for chunk in bytes.chunks(32) {
+                text.push_str("    ");
+                for b in chunk {
+                    write!(text, "{b:02x}").unwrap();
+                }
+                text.push('\n');
+            }
+        }
+    }
+    text
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format_test_vectors, parse_test_vectors};
+
+    // frozen; a change to the format must keep these files decryptable, so never regenerate them
+    const VECTORS: &str = include_str!("../vectors/v2.txt");
+    const VECTORS_V1: &str = include_str!("../vectors/v1.txt");
+
+    #[test]
+    fn test_vectors() {
+        let vectors = parse_test_vectors(VECTORS).unwrap();
+        assert!(vectors.len() >= 10);
+        for vector in &vectors {
+            vector.check().unwrap();
+        }
+        // only the signatures changed since
+        for vector in parse_test_vectors(VECTORS_V1).unwrap() {
+            match vector.signing_key {
+                Some(_) => vector.check_decryption().unwrap(),
+                None => vector.check().unwrap(),
+            }
+        }
+        assert_eq!(
+            parse_test_vectors(&format_test_vectors(&vectors)).unwrap(),
+            vectors
+        );
+
+        let mut damaged = vectors[1].clone();
+        let last = damaged.ciphertext.len() - 1;
+        damaged.ciphertext[last] ^= 1;
+        assert!(damaged.check().is_err());
+        assert!(parse_test_vectors("[x]\nalgorithm = aes-128-gcm\nkey = k\n").is_err());
+        assert!(parse_test_vectors("algorithm = aes-128-gcm\n").is_err());
+    }
+}
@@ -0,0 +1,10 @@
+#!/bin/sh
+# Builds the cdylib and runs the C test harness against it.
+set -e
+cd "$(dirname "$0")/../.."
+cargo rustc --lib --release --features cdylib --crate-type cdylib
+target_dir=$(cargo metadata --format-version 1 --no-deps | sed 's/.*"target_directory":"\([^"]*\)".*/\1/')
+scratch=$(mktemp -d)
+trap 'rm -rf "$scratch"' EXIT
+${CC:-cc} -Wall -Wextra -Iinclude tests/ffi/test_ffi.c -L"$target_dir/release" -lfile_crypto -o "$scratch/test_ffi"
+LD_LIBRARY_PATH="$target_dir/release" "$scratch/test_ffi" "$scratch"
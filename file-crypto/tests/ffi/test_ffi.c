@@ -0,0 +1,101 @@
+// Exercises the C API; run by tests/ffi/run.sh.
+#include <stdio.h>
+#include <string.h>
+
+#include "file_crypto.h"
+
+#define CHECK(cond)                                                            \
+    do {                                                                       \
+        if (!(cond)) {                                                         \
+            const char *msg = fc_last_error();                                 \
+            fprintf(stderr, "%s:%d: check failed: %s (%s)\n", __FILE__,        \
+                    __LINE__, #cond, msg ? msg : "no error message");          \
+            return 1;                                                          \
+        }                                                                      \
+    } while (0)
+
+static int test_buffers(const FcContext *ctx) {
+    uint8_t data[10000];
+    for (size_t i = 0; i < sizeof(data); i++) {
+        data[i] = (uint8_t)(i * 7);
+    }
+
+    uint8_t *encrypted = NULL, *decrypted = NULL;
+    size_t encrypted_len = 0, decrypted_len = 0;
+    CHECK(fc_encrypt_buffer(ctx, data, sizeof(data), &encrypted, &encrypted_len) == FC_OK);
+    CHECK(encrypted_len > sizeof(data));
+    CHECK(fc_decrypt_buffer(ctx, encrypted, encrypted_len, &decrypted, &decrypted_len) == FC_OK);
+    CHECK(decrypted_len == sizeof(data));
+    CHECK(memcmp(decrypted, data, sizeof(data)) == 0);
+    fc_buffer_free(decrypted, decrypted_len);
+
+    encrypted[100] ^= 1;
+    CHECK(fc_decrypt_buffer(ctx, encrypted, encrypted_len, &decrypted, &decrypted_len) ==
+          FC_CRYPTO_ERROR);
+    CHECK(fc_last_error() != NULL);
+    fc_buffer_free(encrypted, encrypted_len);
+
+    CHECK(fc_encrypt_buffer(ctx, NULL, 0, &encrypted, &encrypted_len) == FC_OK);
+    CHECK(fc_decrypt_buffer(ctx, encrypted, encrypted_len, &decrypted, &decrypted_len) == FC_OK);
+    CHECK(decrypted_len == 0);
+    fc_buffer_free(encrypted, encrypted_len);
+    fc_buffer_free(decrypted, decrypted_len);
+
+    CHECK(fc_encrypt_buffer(ctx, NULL, 1, &encrypted, &encrypted_len) == FC_INVALID_ARGUMENT);
+    return 0;
+}
+
+static int test_files(const FcContext *ctx, const char *dir) {
+    char plain[4096], encrypted[4096], decrypted[4096];
+    snprintf(plain, sizeof(plain), "%s/plain", dir);
+    snprintf(encrypted, sizeof(encrypted), "%s/plain.enc", dir);
+    snprintf(decrypted, sizeof(decrypted), "%s/decrypted", dir);
+
+    FILE *f = fopen(plain, "wb");
+    CHECK(f != NULL);
+    for (int i = 0; i < 100000; i++) {
+        fputc(i % 251, f);
+    }
+    fclose(f);
+
+    CHECK(fc_encrypt_file(ctx, plain, encrypted) == FC_OK);
+    CHECK(fc_decrypt_file(ctx, encrypted, decrypted) == FC_OK);
+
+    FILE *a = fopen(plain, "rb"), *b = fopen(decrypted, "rb");
+    CHECK(a != NULL && b != NULL);
+    int ca, cb;
+    do {
+        ca = fgetc(a);
+        cb = fgetc(b);
+        CHECK(ca == cb);
+    } while (ca != EOF);
+    fclose(a);
+    fclose(b);
+
+    CHECK(fc_decrypt_file(ctx, plain, decrypted) != FC_OK);
+    CHECK(fc_encrypt_file(ctx, "/nonexistent/file", encrypted) == FC_IO_ERROR);
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <scratch directory>\n", argv[0]);
+        return 2;
+    }
+
+    CHECK(fc_context_new(NULL, 0) == NULL);
+    CHECK(fc_context_new("", 0) == NULL);
+
+    for (int aes256 = 0; aes256 <= 1; aes256++) {
+        FcContext *ctx = fc_context_new("test", aes256);
+        CHECK(ctx != NULL);
+        if (test_buffers(ctx) != 0 || test_files(ctx, argv[1]) != 0) {
+            fc_context_free(ctx);
+            return 1;
+        }
+        fc_context_free(ctx);
+    }
+
+    printf("ok\n");
+    return 0;
+}
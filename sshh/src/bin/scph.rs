@@ -1,5 +1,5 @@
+use std::io::{stdout, IsTerminal};
 use std::process::exit;
-use std::str::FromStr;
 
 use clap::Parser;
 
@@ -13,7 +13,29 @@ struct Arg {
     #[arg(short, long = "recursive", help = "Copy entire directory recursively")]
     recursive: bool,
 
-    #[arg(help = "Local or remote paths")]
+    #[arg(short, long, help = "Preserve modification times and modes")]
+    preserve: bool,
+
+    #[arg(short = 'C', long, help = "Compress the data in transit")]
+    compress: bool,
+
+    #[arg(
+        short,
+        long = "limit",
+        value_name = "KIB",
+        help = "Limit the bandwidth in KiB/s"
+    )]
+    limit: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Copy with rsync, resuming partial transfers, instead of scp"
+    )]
+    rsync: bool,
+
+    #[arg(
+        help = "Local or remote paths, the remote ones as TARGET:PATH with TARGET as for sshh, :PATH to pick it interactively, or user@host:PATH; with none, an entry is picked and its URI printed (listed if not on a terminal)"
+    )]
     paths: Vec<String>,
 }
 
@@ -24,7 +46,8 @@ fn main() {
         lib::edit_entries();
     }
 
-    if arg.paths.is_empty() {
+    // no paths picks an entry on a terminal and prints its URI, and lists them otherwise
+    if arg.paths.is_empty() && !stdout().is_terminal() {
         lib::print_entries();
     }
 
@@ -41,36 +64,61 @@ fn main() {
         }
     };
 
-    let mut paths = Vec::with_capacity(arg.paths.len());
+    if arg.paths.is_empty() {
+        let entry = &entries[lib::pick_entry_or_exit(&entries)];
+        println!("{}/", entry.uri("scp"));
+        exit(0);
+    }
+
+    let mut specs = Vec::with_capacity(arg.paths.len());
     for path in &arg.paths {
-        let parts = path.splitn(2, ':').collect::<Vec<&str>>();
-        if parts.len() < 2 {
-            paths.push(parts[0].to_string());
-        } else {
-            let target = match usize::from_str(parts[0]) {
-                Ok(i) => i,
-                Err(_) => {
-                    eprintln!(
-                        "error: invalid path argument '{path}'; target index is not a number"
-                    );
-                    exit(1);
-                }
-            };
-            if target >= entries.len() {
-                eprintln!("error: invalid path argument '{path}'; invalid target index {target}");
+        match lib::parse_path_arg(path) {
+            Ok(spec) => specs.push(spec),
+            Err(e) => {
+                eprintln!("error: {e}");
                 exit(1);
             }
-            let entry = entries[target].split(' ').next().unwrap().trim();
-            let path = parts[1];
-            let target_uri = format!("scp://{entry}/{path}");
-            paths.push(target_uri);
         }
     }
-
-    let mut cmd = win_exec::ExecCommand::new("scp");
-    if arg.recursive {
-        cmd.arg("-r");
+    let mut paths = Vec::with_capacity(specs.len());
+    for (spec, path) in specs.iter().zip(&arg.paths) {
+        paths.push(match spec {
+            lib::PathSpec::Local(p) => lib::PathArg::Local(p.clone()),
+            lib::PathSpec::Raw(entry, p) => lib::PathArg::Remote(entry, p.clone()),
+            lib::PathSpec::Target(target, p) => {
+                let target = match target.as_str() {
+                    "" => Ok(lib::pick_entry_or_exit(&entries)),
+                    t => lib::resolve_target(&entries, t),
+                };
+                match target {
+                    Ok(i) => lib::PathArg::Remote(&entries[i], p.clone()),
+                    Err(e) => {
+                        eprintln!("error: invalid path argument '{path}'; {e}");
+                        exit(1);
+                    }
+                }
+            }
+        });
     }
-    cmd.args(paths);
+
+    let backend = match arg.rsync {
+        true => lib::Backend::Rsync,
+        false => lib::Backend::Scp,
+    };
+    let options = lib::TransferOptions {
+        recursive: arg.recursive,
+        preserve: arg.preserve,
+        compress: arg.compress,
+        bandwidth_limit: arg.limit,
+    };
+    let (program, args) = match lib::transfer_command(backend, &paths, &options) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    };
+    let mut cmd = win_exec::ExecCommand::new(program);
+    cmd.args(args);
     cmd.exec(true);
 }
@@ -1,16 +1,38 @@
+use std::io::{stdout, IsTerminal};
 use std::process::exit;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use sshh as lib;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Arg {
+    #[command(subcommand)]
+    subcommand: Option<Command>,
+
     #[arg(short, long, help = "Edit the entries")]
     edit: bool,
 
-    #[arg(help = "The index of remote target")]
-    target: Option<usize>,
+    #[arg(
+        long,
+        help = "Add the hosts of ~/.ssh/config and ~/.ssh/known_hosts to the entries"
+    )]
+    import: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 8,
+        help = "The number of targets to run the command on at the same time"
+    )]
+    jobs: usize,
+
+    #[arg(
+        help = "The remote target: its index, alias, or a unique part of it; picked interactively if not given. @TAG or targets separated by commas run the command on each"
+    )]
+    target: Option<String>,
 
     #[arg(help = "Command to be executed in remote target")]
     command: Option<String>,
@@ -22,19 +44,110 @@ struct Arg {
     )]
     args: Vec<String>,
 }
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Check the entries, and whether their hosts are reachable")]
+    Check {
+        #[arg(
+            short,
+            long,
+            value_name = "SECS",
+            default_value_t = 3,
+            help = "How long to wait for a host to accept a connection"
+        )]
+        timeout: u64,
+
+        #[arg(long, help = "Check the syntax only, without connecting to the hosts")]
+        no_connect: bool,
+    },
+
+    #[command(about = "Bring up a tunnel profile of an entry, or list the profiles")]
+    Tunnel {
+        #[arg(help = "The entry: its index, alias, or a unique part of it; all if not given")]
+        target: Option<String>,
+
+        #[arg(help = "The profile to bring up; the profiles are listed if not given")]
+        profile: Option<String>,
+    },
+
+    #[command(about = "Add an entry")]
+    Add {
+        #[arg(help = "The destination, [user@]host[:port]")]
+        destination: String,
+
+        #[arg(short, long, help = "The name to address the entry by")]
+        alias: Option<String>,
+
+        #[arg(short, long, help = "The identity file to log in with")]
+        identity_file: Option<String>,
+
+        #[arg(short = 'J', long, help = "The jump host to connect through")]
+        jump_host: Option<String>,
+
+        #[arg(short, long = "tag", help = "A tag of the entry, may be repeated")]
+        tags: Vec<String>,
+
+        #[arg(short, long, help = "The description of the entry")]
+        description: Option<String>,
+    },
+
+    #[command(about = "Remove an entry")]
+    Rm {
+        #[arg(help = "The entry: its index, alias, or a unique part of it")]
+        target: String,
+    },
+
+    #[command(about = "Move an entry to another index")]
+    Mv {
+        #[arg(help = "The entry: its index, alias, or a unique part of it")]
+        target: String,
+
+        #[arg(help = "The index to move it to")]
+        index: usize,
+    },
+
+    #[command(about = "Set the alias of an entry")]
+    Rename {
+        #[arg(help = "The entry: its index, alias, or a unique part of it")]
+        target: String,
+
+        #[arg(help = "The new alias")]
+        alias: String,
+    },
+
+    #[command(about = "Add tags to an entry, or remove them")]
+    Tag {
+        #[arg(help = "The entry: its index, alias, or a unique part of it")]
+        target: String,
+
+        #[arg(required = true, help = "The tags")]
+        tags: Vec<String>,
+
+        #[arg(short, long, help = "Remove the tags instead")]
+        remove: bool,
+    },
+}
+
 fn main() {
     let arg = Arg::parse();
 
+    if let Some(command) = arg.subcommand {
+        run_subcommand(command);
+    }
+
     if arg.edit {
         lib::edit_entries();
     }
 
-    let target = match arg.target {
-        None => {
-            lib::print_entries();
-        }
-        Some(i) => i,
-    };
+    if arg.import {
+        lib::import_ssh_config();
+    }
+
+    // no target picks one on a terminal, and lists them otherwise
+    if arg.target.is_none() && !stdout().is_terminal() {
+        lib::print_entries();
+    }
     let entries = match lib::load_entries() {
         Ok(v) => v,
         Err(e) => {
@@ -42,14 +155,24 @@ fn main() {
             exit(1);
         }
     };
-    if target >= entries.len() {
-        eprintln!("error: invalid target index {target}");
-        exit(1);
+    if let Some(target) = arg.target.as_deref().filter(|t| lib::is_multi_target(t)) {
+        run_on_targets(&arg, &entries, target);
     }
-    let entry = entries[target].split(' ').next().unwrap().trim();
-    let target_uri = format!("ssh://{entry}");
+    let entry = match arg
+        .target
+        .as_deref()
+        .map(|t| lib::resolve_target(&entries, t))
+    {
+        None => &entries[lib::pick_entry_or_exit(&entries)],
+        Some(Ok(i)) => &entries[i],
+        Some(Err(e)) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    };
     let mut cmd = win_exec::ExecCommand::new("ssh");
-    cmd.arg(target_uri);
+    cmd.args(entry.ssh_options());
+    cmd.arg(entry.uri("ssh"));
     match arg.command {
         None => {}
         Some(s) => {
@@ -59,3 +182,129 @@ fn main() {
     cmd.args(arg.args);
     cmd.exec(true);
 }
+
+fn run_on_targets(arg: &Arg, entries: &[lib::Entry], target: &str) -> ! {
+    let selected = match lib::select_entries(entries, target) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    };
+    let command = match &arg.command {
+        None => {
+            eprintln!("error: a command is required to run on multiple targets");
+            exit(1);
+        }
+        Some(c) => [c.clone()].into_iter().chain(arg.args.iter().cloned()),
+    };
+    let command: Vec<String> = command.collect();
+    let selected: Vec<&lib::Entry> = selected.iter().map(|&i| &entries[i]).collect();
+    let results = lib::run_on_entries(&selected, &command, arg.jobs);
+
+    let mut failed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(0) => eprintln!("info: '{name}' exited with 0"),
+            Ok(code) => {
+                failed += 1;
+                eprintln!("warning: '{name}' exited with {code}");
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("error: '{name}'; {e}");
+            }
+        }
+    }
+    eprintln!(
+        "info: {} of {} target(s) succeeded",
+        results.len() - failed,
+        results.len()
+    );
+    exit(if failed == 0 { 0 } else { 1 });
+}
+
+fn run_subcommand(command: Command) -> ! {
+    let edit = match command {
+        Command::Tunnel {
+            target,
+            profile: Some(profile),
+        } => tunnel(target.as_deref().unwrap(), &profile),
+        Command::Tunnel { target, .. } => lib::print_tunnels(target.as_deref()),
+        Command::Check {
+            timeout,
+            no_connect,
+        } => {
+            let timeout = (!no_connect).then_some(Duration::from_secs(timeout));
+            lib::check_entries(timeout);
+        }
+        Command::Add {
+            destination,
+            alias,
+            identity_file,
+            jump_host,
+            tags,
+            description,
+        } => {
+            let entry = match lib::Entry::from_line(&destination) {
+                Ok(e) if !destination.contains(' ') => e,
+                Ok(_) => {
+                    eprintln!("error: invalid destination '{destination}'");
+                    exit(1);
+                }
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    exit(1);
+                }
+            };
+            lib::EntryEdit::Add(lib::Entry {
+                alias,
+                identity_file,
+                jump_host,
+                tags,
+                description,
+                ..entry
+            })
+        }
+        Command::Rm { target } => lib::EntryEdit::Remove(target),
+        Command::Mv { target, index } => lib::EntryEdit::Move(target, index),
+        Command::Rename { target, alias } => lib::EntryEdit::Rename(target, alias),
+        Command::Tag {
+            target,
+            tags,
+            remove,
+        } => lib::EntryEdit::Tag {
+            target,
+            tags,
+            remove,
+        },
+    };
+    if let Err(e) = lib::apply_edit(&edit) {
+        eprintln!("error: {e}");
+        exit(1);
+    }
+    exit(0);
+}
+
+fn tunnel(target: &str, profile: &str) -> ! {
+    let entries = match lib::load_entries() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    };
+    let args =
+        lib::resolve_target(&entries, target).and_then(|i| lib::tunnel_args(&entries[i], profile));
+    match args {
+        Ok(args) => {
+            let mut cmd = win_exec::ExecCommand::new("ssh");
+            cmd.args(args);
+            cmd.exec(true);
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    }
+}
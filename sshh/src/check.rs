@@ -0,0 +1,104 @@
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use crate::entry::{is_structured, parse_plain};
+use crate::{parse_entries, Entry};
+
+/// Checks the text of the entry file. Returns the errors of all invalid lines, or else warnings
+/// about entries with the same destination.
+pub fn check_entry_file(text: &str) -> Result<Vec<String>, Vec<String>> {
+    let entries = match is_structured(text) {
+        true => parse_entries(text).map_err(|e| vec![e])?,
+        false => parse_plain(text)?,
+    };
+    let mut warnings = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        let destination = entry.destination();
+        if let Some(j) = entries[..i]
+            .iter()
+            .position(|e| e.destination() == destination)
+        {
+            warnings.push(format!(
+                "[{i}] {} has the same destination as [{j}] {}: {destination}",
+                entry.name(),
+                entries[j].name()
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+/// Tries to open a TCP connection to the host and port (22 if not given) of the entry.
+pub fn probe_entry(entry: &Entry, timeout: Duration) -> Result<(), String> {
+    let port = entry.port.unwrap_or(22);
+    let addrs = (entry.host.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve '{}'; {e}", entry.host))?;
+    let mut error = format!("no address for '{}'", entry.host);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => error = format!("cannot connect to {addr}; {e}"),
+        }
+    }
+    Err(error)
+}
+
+/// Probes the entries at the same time (see `probe_entry`).
+pub fn probe_entries(entries: &[&Entry], timeout: Duration) -> Vec<Result<(), String>> {
+    thread::scope(|s| {
+        let handles: Vec<_> = entries
+            .iter()
+            .map(|&entry| s.spawn(move || probe_entry(entry, timeout)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use super::{check_entry_file, probe_entries};
+    use crate::Entry;
+
+    #[test]
+    fn test_check_entry_file() {
+        let warnings = check_entry_file("# hosts\n[web]\nroot@web1\nweb2\n").unwrap();
+        assert!(warnings.is_empty());
+        let warnings = check_entry_file("root@web1 a\nweb2\nroot@web1 b\n").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("[2] web1 has the same destination as [0] web1"));
+
+        let errors = check_entry_file("web1\nweb2:ssh\n@web3\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("line 2: "));
+        assert!(errors[1].starts_with("line 3: "));
+
+        let errors = check_entry_file("[[entry]]\nhost = \"a\"\nport = \"22\"\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_probe_entries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        // a port nothing listens on anymore
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let entry = |port| Entry {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            ..Default::default()
+        };
+        let entries = [entry(open), entry(closed)];
+        let results = probe_entries(&[&entries[0], &entries[1]], Duration::from_secs(3));
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+}
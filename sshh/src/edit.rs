@@ -0,0 +1,223 @@
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::entry::{is_structured, plain_to_structured};
+use crate::{parse_entries, resolve_target, Entry};
+
+/// A change to the entry file, with entries referred to by target as `resolve_target` takes.
+pub enum EntryEdit {
+    Add(Entry),
+    Remove(String),
+    /// Moves an entry to the index given.
+    Move(String, usize),
+    /// Sets the alias of an entry.
+    Rename(String, String),
+    /// Adds tags to an entry, or removes them.
+    Tag {
+        target: String,
+        tags: Vec<String>,
+        remove: bool,
+    },
+}
+
+/// Applies `edit` to the text of the entry file, keeping its comments and layout. A file in the
+/// plain format is converted to the structured one first.
+pub fn edit_entry_file(text: &str, edit: &EntryEdit) -> Result<String, String> {
+    let entries = parse_entries(text)?;
+    let converted;
+    let text = if is_structured(text) {
+        text
+    } else {
+        converted = plain_to_structured(text)?;
+        &converted
+    };
+    let mut doc: DocumentMut = text.parse().map_err(|e| format!("{e}"))?;
+    let leading_values = !doc.as_table().is_empty() && !doc.contains_key("entry");
+    if !doc.contains_key("entry") {
+        doc.insert("entry", Item::ArrayOfTables(ArrayOfTables::new()));
+    }
+    let tables = match doc.get_mut("entry").and_then(Item::as_array_of_tables_mut) {
+        Some(t) => t,
+        None => return Err("'entry' is not an array of tables".to_string()),
+    };
+
+    match edit {
+        EntryEdit::Add(entry) => tables.push(entry_table(entry)),
+        EntryEdit::Remove(target) => tables.remove(resolve_target(&entries, target)?),
+        EntryEdit::Move(target, to) => {
+            let from = resolve_target(&entries, target)?;
+            if *to >= entries.len() {
+                return Err(format!("invalid index {to}"));
+            }
+            // the tables are written in the order of their positions, which stay where they are
+            let mut moved: Vec<Table> = tables.iter().cloned().collect();
+            let positions: Vec<Option<usize>> = moved.iter().map(Table::position).collect();
+            let table = moved.remove(from);
+            moved.insert(*to, table);
+            tables.clear();
+            for (mut table, position) in moved.into_iter().zip(positions) {
+                if let Some(p) = position {
+                    table.set_position(p);
+                }
+                tables.push(table);
+            }
+        }
+        EntryEdit::Rename(target, alias) => {
+            let i = resolve_target(&entries, target)?;
+            let table = tables.get_mut(i).unwrap();
+            table["alias"] = value(alias);
+            table.sort_values_by(|k1, _, k2, _| (k2 == "alias").cmp(&(k1 == "alias")));
+        }
+        EntryEdit::Tag {
+            target,
+            tags,
+            remove,
+        } => {
+            let i = resolve_target(&entries, target)?;
+            let table = tables.get_mut(i).unwrap();
+            if !table.contains_key("tags") {
+                table["tags"] = value(Array::new());
+            }
+            let array = match table["tags"].as_array_mut() {
+                Some(a) => a,
+                None => return Err("'tags' is not an array".to_string()),
+            };
+            if *remove {
+                array.retain(|v| !tags.iter().any(|t| v.as_str() == Some(t)));
+            } else {
+                for tag in tags {
+                    if !array.iter().any(|v| v.as_str() == Some(tag)) {
+                        array.push(tag);
+                    }
+                }
+            }
+            if array.is_empty() {
+                table.remove("tags");
+            }
+        }
+    }
+
+    space_tables(tables, leading_values);
+    let text = doc.to_string();
+    parse_entries(&text)?; // e.g. a duplicate alias
+    Ok(text)
+}
+
+// a blank line between tables, whatever order they're in now; those after comments are left alone
+fn space_tables(tables: &mut ArrayOfTables, leading_values: bool) {
+    for (i, table) in tables.iter_mut().enumerate() {
+        let decor = table.decor_mut();
+        let prefix = decor.prefix().and_then(|p| p.as_str());
+        if prefix.is_none_or(|p| p.trim().is_empty()) {
+            decor.set_prefix(if i == 0 && !leading_values { "" } else { "\n" });
+        }
+    }
+}
+
+fn entry_table(entry: &Entry) -> Table {
+    let mut table = Table::new();
+    if let Some(alias) = &entry.alias {
+        table["alias"] = value(alias);
+    }
+    table["host"] = value(&entry.host);
+    if let Some(user) = &entry.user {
+        table["user"] = value(user);
+    }
+    if let Some(port) = entry.port {
+        table["port"] = value(port as i64);
+    }
+    if let Some(identity_file) = &entry.identity_file {
+        table["identity_file"] = value(identity_file);
+    }
+    if let Some(jump_host) = &entry.jump_host {
+        table["jump_host"] = value(jump_host);
+    }
+    if !entry.tags.is_empty() {
+        table["tags"] = value(Array::from_iter(&entry.tags));
+    }
+    if let Some(group) = &entry.group {
+        table["group"] = value(group);
+    }
+    if let Some(description) = &entry.description {
+        table["description"] = value(description);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_entry_file, EntryEdit};
+    use crate::{parse_entries, Entry};
+
+    const TEXT: &str = r#"# my hosts
+
+# the frontend
+[[entry]]
+alias = "web1"
+host = "10.0.0.1"
+tags = ["web"]  # more to come
+
+[[entry]]
+alias = "db"
+host = "10.0.0.2"
+"#;
+
+    fn names(text: &str) -> Vec<String> {
+        let entries = parse_entries(text).unwrap();
+        entries.iter().map(|e| e.name().to_string()).collect()
+    }
+
+    #[test]
+    fn test_edit_entry_file() {
+        let entry = Entry {
+            alias: Some("web2".to_string()),
+            host: "10.0.0.3".to_string(),
+            port: Some(2222),
+            tags: vec!["web".to_string()],
+            ..Default::default()
+        };
+        let added = edit_entry_file(TEXT, &EntryEdit::Add(entry.clone())).unwrap();
+        assert!(added.starts_with(TEXT), "{added}");
+        assert_eq!(parse_entries(&added).unwrap()[2], entry);
+        assert!(edit_entry_file(&added, &EntryEdit::Add(entry)).is_err());
+
+        let removed = edit_entry_file(TEXT, &EntryEdit::Remove("db".to_string())).unwrap();
+        assert_eq!(names(&removed), ["web1"]);
+        assert!(removed.contains("# my hosts"));
+
+        let moved = edit_entry_file(&added, &EntryEdit::Move("web2".to_string(), 0)).unwrap();
+        assert_eq!(names(&moved), ["web2", "web1", "db"]);
+        assert!(moved.contains("# the frontend\n[[entry]]\nalias = \"web1\""));
+        assert!(edit_entry_file(TEXT, &EntryEdit::Move("db".to_string(), 2)).is_err());
+
+        let renamed = edit_entry_file(TEXT, &EntryEdit::Rename("1".into(), "pg".into())).unwrap();
+        assert_eq!(names(&renamed), ["web1", "pg"]);
+        let renamed = edit_entry_file(TEXT, &EntryEdit::Rename("1".into(), "web1".into()));
+        assert!(renamed.is_err());
+
+        let tag = |target: &str, tags: &[&str], remove| EntryEdit::Tag {
+            target: target.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            remove,
+        };
+        let tagged = edit_entry_file(TEXT, &tag("web1", &["web", "prod"], false)).unwrap();
+        assert!(tagged.contains("tags = [\"web\", \"prod\"]  # more to come"));
+        let tagged = edit_entry_file(&tagged, &tag("db", &["prod"], false)).unwrap();
+        let untagged = edit_entry_file(&tagged, &tag("web1", &["web", "prod"], true)).unwrap();
+        let entries = parse_entries(&untagged).unwrap();
+        assert!(entries[0].tags.is_empty());
+        assert_eq!(entries[1].tags, ["prod"]);
+    }
+
+    #[test]
+    fn test_edit_plain_entry_file() {
+        let text = "root@10.0.0.1 web\n10.0.0.2\n";
+        let renamed = edit_entry_file(text, &EntryEdit::Rename("0".into(), "web1".into())).unwrap();
+        let entries = parse_entries(&renamed).unwrap();
+        assert_eq!(entries[0].name(), "web1");
+        assert_eq!(entries[0].description.as_deref(), Some("web"));
+        assert_eq!(entries[1].host, "10.0.0.2");
+
+        let added = edit_entry_file("", &EntryEdit::Add(entries[1].clone())).unwrap();
+        assert_eq!(parse_entries(&added).unwrap(), [entries[1].clone()]);
+    }
+}
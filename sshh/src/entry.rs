@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Tunnel;
+
+/// A remote target of the entry file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump_host: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tunnels: BTreeMap<String, Tunnel>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct EntryFile {
+    // merge the hosts of ~/.ssh/config into the entries
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub(crate) import_ssh_config: bool,
+    #[serde(default)]
+    pub(crate) entry: Vec<Entry>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl Entry {
+    /// Parses a line of the plain format, `[user@]host[:port] description`.
+    pub fn from_line(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (destination, description) = match line.split_once(' ') {
+            Some((d, rest)) => (d, Some(rest.trim().to_string())),
+            None => (line, None),
+        };
+        let (user, host_port) = match destination.rsplit_once('@') {
+            Some((u, rest)) => (Some(u.to_string()), rest),
+            None => (None, destination),
+        };
+        let (host, port) =
+            split_port(host_port).ok_or_else(|| format!("invalid destination '{destination}'"))?;
+        if host.is_empty() || user.as_deref() == Some("") {
+            return Err(format!("invalid destination '{destination}'"));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            user,
+            port,
+            description,
+            ..Default::default()
+        })
+    }
+
+    /// The alias, or the host for entries without one.
+    pub fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.host)
+    }
+
+    /// `[user@]host[:port]`, with IPv6 addresses in brackets.
+    pub fn destination(&self) -> String {
+        let mut s = String::new();
+        if let Some(user) = &self.user {
+            s.push_str(user);
+            s.push('@');
+        }
+        if self.host.contains(':') {
+            s.push_str(&format!("[{}]", self.host));
+        } else {
+            s.push_str(&self.host);
+        }
+        if let Some(port) = self.port {
+            s.push_str(&format!(":{port}"));
+        }
+        s
+    }
+
+    /// The URI of the target for `ssh` or `scp`, e.g. `ssh://user@host:22`.
+    pub fn uri(&self, scheme: &str) -> String {
+        format!("{scheme}://{}", self.destination())
+    }
+
+    /// The options for the identity file and jump host, understood by both `ssh` and `scp`.
+    pub fn ssh_options(&self) -> Vec<String> {
+        let mut options = vec![];
+        if let Some(identity_file) = &self.identity_file {
+            options.push("-i".to_string());
+            options.push(identity_file.clone());
+        }
+        if let Some(jump_host) = &self.jump_host {
+            options.push("-J".to_string());
+            options.push(jump_host.clone());
+        }
+        options
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(alias) = &self.alias {
+            write!(f, "{alias} ")?;
+        }
+        write!(f, "{}", self.destination())?;
+        for tag in &self.tags {
+            write!(f, " @{tag}")?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, " {description}")?;
+        }
+        Ok(())
+    }
+}
+
+// "host", "host:22", "[::1]" or "[::1]:22"; None if the port or the brackets are invalid
+fn split_port(s: &str) -> Option<(&str, Option<u16>)> {
+    let (host, port) = match s.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':')?)),
+            }
+        }
+        None => match s.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (s, None),
+        },
+    };
+    match port {
+        None => Some((host, None)),
+        Some(p) => Some((host, Some(p.parse().ok()?))),
+    }
+}
+
+/// Parses the entry file, either the structured TOML format with an `[[entry]]` table per target,
+/// or the plain format with a target per line.
+pub fn parse_entries(text: &str) -> Result<Vec<Entry>, String> {
+    parse_entry_file(text).map(|f| f.entry)
+}
+
+pub(crate) fn parse_entry_file(text: &str) -> Result<EntryFile, String> {
+    if !is_structured(text) {
+        let entry = parse_plain(text).map_err(|errors| errors.join("; "))?;
+        return Ok(EntryFile {
+            entry,
+            ..Default::default()
+        });
+    }
+
+    let file: EntryFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    let mut aliases = HashSet::new();
+    for entry in &file.entry {
+        if entry.host.is_empty() {
+            return Err("entry with an empty host".to_string());
+        }
+        if let Some(alias) = &entry.alias {
+            if alias.is_empty() || alias.contains(char::is_whitespace) {
+                return Err(format!("invalid alias '{alias}'"));
+            }
+            // resolve_target takes a number for an index
+            if alias.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!(
+                    "invalid alias '{alias}', which is taken for an index"
+                ));
+            }
+            if !aliases.insert(alias) {
+                return Err(format!("duplicate alias '{alias}'"));
+            }
+        }
+        for (profile, tunnel) in &entry.tunnels {
+            if let Err(e) = tunnel.validate() {
+                let name = entry.name();
+                return Err(format!("tunnel profile '{profile}' of '{name}'; {e}"));
+            }
+        }
+    }
+    Ok(file)
+}
+
+enum PlainLine {
+    Blank,
+    Comment,
+    Group(String),
+    Entry(Entry),
+}
+
+// "# comment", "[group]", or an entry
+fn parse_plain_line(line: &str) -> Result<PlainLine, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(PlainLine::Blank);
+    }
+    if line.starts_with('#') {
+        return Ok(PlainLine::Comment);
+    }
+    let group = line.strip_prefix('[').and_then(|l| l.strip_suffix(']'));
+    if let Some(group) = group.filter(|g| is_group_name(g)) {
+        return Ok(PlainLine::Group(group.to_string()));
+    }
+    Entry::from_line(line).map(PlainLine::Entry)
+}
+
+// unlike an IPv6 address in brackets
+fn is_group_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+// the entries, or the errors of all lines which aren't valid
+pub(crate) fn parse_plain(text: &str) -> Result<Vec<Entry>, Vec<String>> {
+    let mut entries = vec![];
+    let mut errors = vec![];
+    let mut group = None;
+    for (n, line) in text.lines().enumerate() {
+        match parse_plain_line(line) {
+            Ok(PlainLine::Blank | PlainLine::Comment) => {}
+            Ok(PlainLine::Group(g)) => group = Some(g),
+            Ok(PlainLine::Entry(entry)) => entries.push(Entry {
+                group: group.clone(),
+                ..entry
+            }),
+            Err(e) => errors.push(format!("line {}: {e}", n + 1)),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(entries),
+        false => Err(errors),
+    }
+}
+
+/// Converts a file in the plain format to the structured one, keeping its comments.
+pub(crate) fn plain_to_structured(text: &str) -> Result<String, String> {
+    parse_plain(text).map_err(|errors| errors.join("; "))?;
+    let mut out = String::new();
+    let mut comments = String::new();
+    let mut group = None;
+    for line in text.lines() {
+        let entry = match parse_plain_line(line).unwrap() {
+            PlainLine::Blank => continue,
+            PlainLine::Comment => {
+                comments.push_str(line.trim());
+                comments.push('\n');
+                continue;
+            }
+            PlainLine::Group(g) => {
+                group = Some(g);
+                continue;
+            }
+            PlainLine::Entry(entry) => Entry {
+                group: group.clone(),
+                ..entry
+            },
+        };
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&comments);
+        comments.clear();
+        out.push_str(&format_entries(&[entry]));
+    }
+    if !comments.is_empty() {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&comments);
+    }
+    Ok(out)
+}
+
+// a plain line is a destination and a description, never a table header or "key = value"
+pub(crate) fn is_structured(text: &str) -> bool {
+    text.lines().map(str::trim).any(|line| {
+        let key = line.split('=').next().unwrap().trim_end();
+        line == "[[entry]]"
+            || (line.contains('=')
+                && !key.is_empty()
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+    })
+}
+
+/// Formats entries as `[[entry]]` tables of the structured format.
+pub fn format_entries(entries: &[Entry]) -> String {
+    let file = EntryFile {
+        entry: entries.to_vec(),
+        ..Default::default()
+    };
+    toml::to_string(&file).unwrap()
+}
+
+/// Finds the entry `target` refers to: its index, its alias (or host), a unique prefix of that, or
+/// a unique case-insensitive substring of the alias, host or description. Returns the index.
+pub fn resolve_target(entries: &[Entry], target: &str) -> Result<usize, String> {
+    if let Ok(i) = target.parse::<usize>() {
+        if i >= entries.len() {
+            return Err(format!("invalid target index {i}"));
+        }
+        return Ok(i);
+    }
+    let needle = target.to_lowercase();
+    let matchers: [&dyn Fn(&Entry) -> bool; 3] = [
+        &|e| e.name() == target,
+        &|e| e.name().starts_with(target),
+        &|e| {
+            [Some(e.name()), Some(&e.host), e.description.as_deref()]
+                .into_iter()
+                .flatten()
+                .any(|s| s.to_lowercase().contains(&needle))
+        },
+    ];
+    for matches in matchers {
+        let found: Vec<usize> = (0..entries.len())
+            .filter(|&i| matches(&entries[i]))
+            .collect();
+        match found[..] {
+            [] => continue,
+            [i] => return Ok(i),
+            _ => {
+                let candidates: Vec<String> = found
+                    .iter()
+                    .map(|&i| format!("[{i}] {}", entries[i].name()))
+                    .collect();
+                return Err(format!(
+                    "target '{target}' is ambiguous; candidates: {}",
+                    candidates.join(", ")
+                ));
+            }
+        }
+    }
+    Err(format!("no entry matches target '{target}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_entries, parse_entries, plain_to_structured, resolve_target, Entry};
+
+    #[test]
+    fn test_plain_format() {
+        let entries = parse_entries("root@10.0.0.1:2222 the web server\n\n  db.local\n").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].user.as_deref(), Some("root"));
+        assert_eq!(entries[0].host, "10.0.0.1");
+        assert_eq!(entries[0].port, Some(2222));
+        assert_eq!(entries[0].description.as_deref(), Some("the web server"));
+        assert_eq!(entries[0].uri("ssh"), "ssh://root@10.0.0.1:2222");
+        assert_eq!(entries[1].uri("scp"), "scp://db.local");
+        assert_eq!(entries[1].name(), "db.local");
+
+        let ipv6 = Entry::from_line("admin@[fe80::1]:22").unwrap();
+        assert_eq!(ipv6.host, "fe80::1");
+        assert_eq!(ipv6.destination(), "admin@[fe80::1]:22");
+
+        assert!(Entry::from_line("host:port").is_err());
+        assert!(Entry::from_line("@host").is_err());
+
+        let text = "# web servers\n[web]\nweb1 # not a comment\n[::1]:22\n\n[db]\n# db1\ndb2\n";
+        let entries = parse_entries(text).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].group.as_deref(), Some("web"));
+        assert_eq!(entries[0].description.as_deref(), Some("# not a comment"));
+        assert_eq!(entries[1].host, "::1");
+        assert_eq!(entries[1].group.as_deref(), Some("web"));
+        assert_eq!(entries[2].group.as_deref(), Some("db"));
+        let err = parse_entries("a\nb:x\n@c\n").unwrap_err();
+        assert!(
+            err.starts_with("line 2: ") && err.contains("; line 3: "),
+            "{err}"
+        );
+
+        let structured = plain_to_structured(text).unwrap();
+        assert!(
+            structured.starts_with("# web servers\n[[entry]]\n"),
+            "{structured}"
+        );
+        assert!(structured.contains("\n# db1\n[[entry]]\nhost = \"db2\""));
+        assert_eq!(parse_entries(&structured).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_structured_format() {
+        let text = r#"
+[[entry]]
+alias = "web1"
+host = "10.0.0.1"
+user = "root"
+port = 2222
+identity_file = "~/.ssh/id_web"
+jump_host = "bastion"
+tags = ["web", "prod"]
+description = "the web server"
+
+[[entry]]
+host = "db.local"
+"#;
+        let entries = parse_entries(text).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name(), "web1");
+        assert_eq!(entries[0].uri("ssh"), "ssh://root@10.0.0.1:2222");
+        assert_eq!(
+            entries[0].ssh_options(),
+            ["-i", "~/.ssh/id_web", "-J", "bastion"]
+        );
+        assert_eq!(
+            entries[0].to_string(),
+            "web1 root@10.0.0.1:2222 @web @prod the web server"
+        );
+        assert_eq!(entries[1].name(), "db.local");
+        assert!(entries[1].ssh_options().is_empty());
+
+        let duplicate =
+            "[[entry]]\nalias = \"a\"\nhost = \"x\"\n[[entry]]\nalias = \"a\"\nhost = \"y\"\n";
+        assert!(parse_entries(duplicate).is_err());
+        let numeric = "[[entry]]\nalias = \"42\"\nhost = \"x\"\n";
+        assert!(parse_entries(numeric).is_err());
+        let mixed = "[[entry]]\nalias = \"42a\"\nhost = \"x\"\n";
+        assert!(parse_entries(mixed).is_ok());
+        let unknown = "[[entry]]\nhost = \"x\"\nprot = 22\n";
+        assert!(parse_entries(unknown).is_err());
+
+        assert_eq!(parse_entries(&format_entries(&entries)).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_resolve_target() {
+        let text = r#"
+[[entry]]
+alias = "web1"
+host = "10.0.0.1"
+description = "Frontend"
+
+[[entry]]
+alias = "web10"
+host = "10.0.0.10"
+
+[[entry]]
+alias = "db"
+host = "db.internal"
+"#;
+        let entries = parse_entries(text).unwrap();
+        assert_eq!(resolve_target(&entries, "2"), Ok(2));
+        assert!(resolve_target(&entries, "3").is_err());
+        // an exact alias wins over being a prefix of another
+        assert_eq!(resolve_target(&entries, "web1"), Ok(0));
+        assert_eq!(resolve_target(&entries, "d"), Ok(2));
+        assert_eq!(resolve_target(&entries, "front"), Ok(0));
+        assert_eq!(resolve_target(&entries, "internal"), Ok(2));
+        let err = resolve_target(&entries, "web").unwrap_err();
+        assert!(err.contains("[0] web1, [1] web10"), "{err}");
+        assert!(resolve_target(&entries, "mail").is_err());
+    }
+}
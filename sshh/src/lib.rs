@@ -1,66 +1,301 @@
 use std::env;
-use std::fs::File;
-use std::io::{stdout, BufRead, BufReader, BufWriter, ErrorKind, Write};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, stdout, BufWriter, ErrorKind, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
+
+pub use check::{check_entry_file, probe_entries, probe_entry};
+pub use edit::{edit_entry_file, EntryEdit};
+pub use entry::{format_entries, parse_entries, resolve_target, Entry};
+pub use multi::{is_multi_target, run_on_entries, select_entries};
+pub use picker::{filter_entries, pick_entry};
+pub use ssh_config::{parse_known_hosts, parse_ssh_config};
+pub use transfer::{parse_path_arg, transfer_command, Backend, PathArg, PathSpec, TransferOptions};
+pub use tunnel::{tunnel_args, Tunnel};
+
+use entry::{is_structured, parse_entry_file, plain_to_structured};
+
+mod check;
+mod edit;
+mod entry;
+mod multi;
+mod picker;
+mod ssh_config;
+mod transfer;
+mod tunnel;
 
 const FILENAME: &str = ".sshh_entries";
 
 #[cfg(windows)]
-fn entry_file_path() -> PathBuf {
-    PathBuf::from(env::var("USERPROFILE").unwrap()).join(FILENAME)
+fn home_dir() -> PathBuf {
+    PathBuf::from(env::var("USERPROFILE").unwrap())
 }
 #[cfg(not(windows))]
+fn home_dir() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap())
+}
+
 fn entry_file_path() -> PathBuf {
-    PathBuf::from(env::var("HOME").unwrap()).join(FILENAME)
+    home_dir().join(FILENAME)
 }
 
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Opens the entry file with `$VISUAL`, `$EDITOR`, or else vi (notepad on Windows).
 pub fn edit_entries() -> ! {
     let path = entry_file_path();
-    let editor = match env::var("EDITOR") {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("error: environment variable 'EDITOR' not defined");
-            exit(1);
-        }
-    };
-    let mut cmd = win_exec::ExecCommand::new(&editor);
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|s| !s.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+    // e.g. "code --wait"
+    let mut words = editor.split_whitespace();
+    let mut cmd = win_exec::ExecCommand::new(words.next().unwrap());
+    cmd.args(words);
     cmd.arg(&path);
     cmd.exec(true);
 }
 
-pub fn load_entries() -> Result<Vec<String>, String> {
-    let file = match File::open(entry_file_path()) {
-        Ok(f) => f,
-        Err(e) if e.kind() == ErrorKind::NotFound => {
-            return Ok(vec![]);
+fn write_entry_file(text: &str) -> Result<(), String> {
+    replace_file(&entry_file_path(), text).map_err(|e| format!("failed to write entry file; {e}"))
+}
+
+// Through a temporary file renamed over it, so an interrupted write doesn't lose the entries. A
+// symlink (e.g. into a dotfiles repository) is followed, and stays one.
+fn replace_file(path: &Path, text: &str) -> io::Result<()> {
+    let path = match fs::canonicalize(path) {
+        Ok(p) => p,
+        Err(e) if e.kind() == ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let result = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(text.as_bytes())?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Applies `edit` to the entry file (see `edit_entry_file`).
+pub fn apply_edit(edit: &EntryEdit) -> Result<(), String> {
+    let text = read_entry_file()?;
+    let text =
+        edit_entry_file(&text, edit).map_err(|e| format!("failed to edit entry file; {e}"))?;
+    write_entry_file(&text)
+}
+
+fn read_entry_file() -> Result<String, String> {
+    match fs::read_to_string(entry_file_path()) {
+        Ok(s) => Ok(s),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("failed to read entry file; {e}")),
+    }
+}
+
+/// Loads the entries, in the structured format or one per line (see `parse_entries`). With
+/// `import_ssh_config = true` in the structured format, the hosts of `~/.ssh/config` and
+/// `~/.ssh/known_hosts` follow, except those named like an entry already.
+pub fn load_entries() -> Result<Vec<Entry>, String> {
+    let text = read_entry_file()?;
+    let mut file =
+        parse_entry_file(&text).map_err(|e| format!("failed to parse entry file; {e}"))?;
+    if file.import_ssh_config {
+        merge_entries(&mut file.entry, imported_entries()?);
+    }
+    Ok(file.entry)
+}
+
+// the hosts of ~/.ssh/config, then those of ~/.ssh/known_hosts it doesn't have already
+fn imported_entries() -> Result<Vec<Entry>, String> {
+    let ssh_dir = home_dir().join(".ssh");
+    let mut entries = vec![];
+    let path = ssh_dir.join("config");
+    if path.is_file() {
+        let (config_entries, warnings) = parse_ssh_config(&path, &ssh_dir)
+            .map_err(|e| format!("failed to parse ssh config; {e}"))?;
+        for warning in warnings {
+            eprintln!("warning: {warning}");
+        }
+        entries = config_entries;
+    }
+    let path = ssh_dir.join("known_hosts");
+    if path.is_file() {
+        let known_hosts =
+            parse_known_hosts(&path).map_err(|e| format!("failed to parse known hosts; {e}"))?;
+        // the entries of the ssh config have their HostName as the description
+        for entry in known_hosts {
+            if !entries.iter().any(|e| {
+                e.host == entry.host
+                    || e.name() == entry.host
+                    || e.description.as_deref() == Some(&entry.host)
+            }) {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// appends the entries whose names aren't taken; returns how many
+fn merge_entries(entries: &mut Vec<Entry>, other: Vec<Entry>) -> usize {
+    let len = entries.len();
+    for entry in other {
+        if !entries.iter().any(|e| e.name() == entry.name()) {
+            entries.push(entry);
+        }
+    }
+    entries.len() - len
+}
+
+/// Writes the hosts of `~/.ssh/config` and `~/.ssh/known_hosts` missing from the entry file into
+/// it. A file in the plain
+/// format is converted to the structured one.
+pub fn import_ssh_config() -> ! {
+    let result = read_entry_file().and_then(|text| {
+        let file =
+            parse_entry_file(&text).map_err(|e| format!("failed to parse entry file; {e}"))?;
+        let mut entries = file.entry;
+        let len = entries.len();
+        let count = merge_entries(&mut entries, imported_entries()?);
+        if count == 0 {
+            return Ok(0);
+        }
+        let text = match is_structured(&text) {
+            true => text,
+            false => plain_to_structured(&text)?,
+        };
+        let sep = if text.is_empty() || text.ends_with("\n\n") {
+            ""
+        } else if text.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        let text = format!("{text}{sep}{}", format_entries(&entries[len..]));
+        write_entry_file(&text).map(|_| count)
+    });
+    match result {
+        Ok(count) => {
+            println!("info: imported {count} entries from ssh config and known hosts");
+            exit(0);
         }
         Err(e) => {
-            return Err(format!("failed to open entry file; {e}"));
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    }
+}
+
+/// Reports the problems of the entry file, and with `probe_timeout`, the hosts which aren't
+/// reachable. Exits with 1 if there are any.
+pub fn check_entries(probe_timeout: Option<Duration>) -> ! {
+    let text = match read_entry_file() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
         }
     };
-    let mut lines = vec![];
-    let mut reader = BufReader::new(file);
-    loop {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(len) => {
-                if len == 0 {
-                    break;
-                }
-                let line = line.trim();
-                if line.is_empty() {
-                    continue;
-                }
-                let line = line.to_string();
-                lines.push(line);
+    let mut ok = true;
+    match check_entry_file(&text) {
+        Ok(warnings) => {
+            for warning in warnings {
+                ok = false;
+                eprintln!("warning: {warning}");
             }
+        }
+        Err(errors) => {
+            for error in errors {
+                eprintln!("error: {error}");
+            }
+            exit(1);
+        }
+    }
+
+    let timeout = match probe_timeout {
+        Some(t) => t,
+        None => exit(if ok { 0 } else { 1 }),
+    };
+    let entries = match load_entries() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    };
+    // the ones behind a jump host can only be reached through it
+    let (jumped, direct): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .enumerate()
+        .partition(|(_, e)| e.jump_host.is_some());
+    let direct_entries: Vec<&Entry> = direct.iter().map(|&(_, e)| e).collect();
+    let results = probe_entries(&direct_entries, timeout);
+    for ((i, entry), result) in direct.into_iter().zip(results) {
+        match result {
+            Ok(_) => println!("info: [{i}] {} is reachable", entry.name()),
             Err(e) => {
-                return Err(format!("failed to read line from entry file; {e}"));
+                ok = false;
+                eprintln!("warning: [{i}] {} is unreachable; {e}", entry.name());
             }
         }
     }
-    Ok(lines)
+    for (i, entry) in jumped {
+        let jump_host = entry.jump_host.as_deref().unwrap();
+        println!(
+            "info: [{i}] {} not probed, reached through '{jump_host}'",
+            entry.name()
+        );
+    }
+    exit(if ok { 0 } else { 1 });
+}
+
+/// Lists the tunnel profiles of all entries, or of the one `target` refers to, telling those
+/// which seem to be up.
+pub fn print_tunnels(target: Option<&str>) -> ! {
+    let entries = match load_entries() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    };
+    let indices = match target.map(|t| resolve_target(&entries, t)) {
+        None => (0..entries.len()).collect(),
+        Some(Ok(i)) => vec![i],
+        Some(Err(e)) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    };
+    let mut writer = BufWriter::new(stdout());
+    for i in indices {
+        let entry = &entries[i];
+        for (profile, tunnel) in &entry.tunnels {
+            let state = match tunnel.is_active(Duration::from_millis(500)) {
+                Some(true) => " (active)",
+                _ => "",
+            };
+            let args = tunnel.ssh_args().join(" ");
+            let name = entry.name();
+            writeln!(&mut writer, "[{i}] {name} {profile}: {args}{state}").unwrap();
+        }
+    }
+    writer.flush().unwrap();
+    exit(0);
 }
 
 pub fn print_entries() -> ! {
@@ -71,10 +306,71 @@ pub fn print_entries() -> ! {
             exit(1);
         }
     };
+    write_entries(&entries);
+    exit(0);
+}
+
+fn write_entries(entries: &[Entry]) {
     let mut writer = BufWriter::new(stdout());
+    let mut group = None;
     for (i, entry) in entries.iter().enumerate() {
+        if entry.group.is_some() && entry.group != group {
+            group = entry.group.clone();
+            writeln!(&mut writer, "{}:", group.as_deref().unwrap()).unwrap();
+        }
         writeln!(&mut writer, "[{i}] {entry}").unwrap();
     }
     writer.flush().unwrap();
-    exit(0);
+}
+
+/// Lets the user pick one of `entries` (see `pick_entry`), exiting if cancelled or on errors.
+/// Without a terminal to pick on, the entries are listed instead, and it exits with 1.
+pub fn pick_entry_or_exit(entries: &[Entry]) -> usize {
+    if !stdout().is_terminal() {
+        eprintln!("error: no terminal to pick an entry on; give a target of these");
+        write_entries(entries);
+        exit(1);
+    }
+    match pick_entry(entries) {
+        Ok(Some(i)) => i,
+        Ok(None) => exit(1),
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::replace_file;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_file() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("sshh-replace-{}", std::process::id()));
+        fs::create_dir_all(dir.join("dotfiles")).unwrap();
+        let target = dir.join("dotfiles/entries");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = dir.join(".sshh_entries");
+        symlink(&target, &link).unwrap();
+
+        replace_file(&link, "new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(fs::read_dir(dir.join("dotfiles")).unwrap().count(), 1);
+
+        let new_file = dir.join("new");
+        replace_file(&new_file, "text").unwrap();
+        assert_eq!(fs::read_to_string(&new_file).unwrap(), "text");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
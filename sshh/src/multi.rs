@@ -0,0 +1,200 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::{resolve_target, Entry};
+
+/// Whether `target` selects several entries: `@tag` (or group), or targets separated by commas.
+pub fn is_multi_target(target: &str) -> bool {
+    target.starts_with('@') || target.contains(',')
+}
+
+/// The indices of the entries `@tag`, `@group` or a comma separated list of targets select.
+pub fn select_entries(entries: &[Entry], target: &str) -> Result<Vec<usize>, String> {
+    if let Some(tag) = target.strip_prefix('@') {
+        let found: Vec<usize> = (0..entries.len())
+            .filter(|&i| {
+                entries[i].tags.iter().any(|t| t == tag) || entries[i].group.as_deref() == Some(tag)
+            })
+            .collect();
+        if found.is_empty() {
+            return Err(format!("no entry is tagged or grouped '{tag}'"));
+        }
+        return Ok(found);
+    }
+    let mut found = vec![];
+    for t in target.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let i = resolve_target(entries, t)?;
+        if !found.contains(&i) {
+            found.push(i);
+        }
+    }
+    Ok(found)
+}
+
+/// Runs `command` on every entry with `ssh`, at most `jobs` at a time. The lines they print are
+/// prefixed with the entry's name. Returns the name and exit code, or why there is none, of each.
+pub fn run_on_entries(
+    entries: &[&Entry],
+    command: &[String],
+    jobs: usize,
+) -> Vec<(String, Result<i32, String>)> {
+    let commands = entries
+        .iter()
+        .map(|entry| {
+            let mut cmd = Command::new("ssh");
+            // nothing to read from, and no prompts for passwords or host keys which nobody sees
+            // among the output of the other hosts
+            cmd.args(["-n", "-o", "BatchMode=yes"])
+                .args(entry.ssh_options())
+                .arg(entry.uri("ssh"))
+                .args(command);
+            (entry.name().to_string(), cmd)
+        })
+        .collect();
+    let (stdout, stderr) = (Mutex::new(std::io::stdout()), Mutex::new(std::io::stderr()));
+    run_all(commands, jobs, &stdout, &stderr)
+}
+
+fn run_all<O: Write + Send, E: Write + Send>(
+    commands: Vec<(String, Command)>,
+    jobs: usize,
+    stdout: &Mutex<O>,
+    stderr: &Mutex<E>,
+) -> Vec<(String, Result<i32, String>)> {
+    let width = commands.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    let names: Vec<String> = commands.iter().map(|(n, _)| n.clone()).collect();
+    let queue = Mutex::new(commands.into_iter().enumerate());
+    let results = Mutex::new(vec![None; names.len()]);
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            s.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let (i, (name, mut cmd)) = match next {
+                    Some(n) => n,
+                    None => break,
+                };
+                let prefix = format!("{name:width$} | ");
+                let result = run_prefixed(&mut cmd, &prefix, stdout, stderr);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    let results = results.into_inner().unwrap();
+    names
+        .into_iter()
+        .zip(results.into_iter().map(Option::unwrap))
+        .collect()
+}
+
+fn run_prefixed<O: Write + Send, E: Write + Send>(
+    cmd: &mut Command,
+    prefix: &str,
+    out: &Mutex<O>,
+    err: &Mutex<E>,
+) -> Result<i32, String> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to spawn sub-process; {e}"))?;
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    thread::scope(|s| {
+        s.spawn(|| copy_prefixed(stdout, out, prefix));
+        s.spawn(|| copy_prefixed(stderr, err, prefix));
+    });
+    let status = child
+        .wait()
+        .map_err(|e| format!("failed to wait sub-process; {e}"))?;
+    status
+        .code()
+        .ok_or_else(|| "sub-process ended without code".to_string())
+}
+
+// whole lines at a time, so those of the hosts don't mix
+fn copy_prefixed<R: Read, W: Write>(reader: R, writer: &Mutex<W>, prefix: &str) {
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        let mut buf = prefix.as_bytes().to_vec();
+        buf.extend_from_slice(&line);
+        let _ = writer.lock().unwrap().write_all(&buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_multi_target, select_entries};
+    use crate::parse_entries;
+
+    #[test]
+    fn test_select_entries() {
+        let text = r#"
+[[entry]]
+alias = "web1"
+host = "10.0.0.1"
+tags = ["web"]
+
+[[entry]]
+alias = "db"
+host = "10.0.0.2"
+
+[[entry]]
+alias = "web2"
+host = "10.0.0.3"
+tags = ["web", "prod"]
+"#;
+        let entries = parse_entries(text).unwrap();
+        assert!(is_multi_target("@web"));
+        assert!(is_multi_target("0,1"));
+        assert!(!is_multi_target("web1"));
+        assert_eq!(select_entries(&entries, "@web").unwrap(), [0, 2]);
+        assert_eq!(select_entries(&entries, "@prod").unwrap(), [2]);
+        assert!(select_entries(&entries, "@mail").is_err());
+        assert_eq!(select_entries(&entries, "2,db,web2,").unwrap(), [2, 1]);
+        assert!(select_entries(&entries, "0,9").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_all() {
+        use std::process::Command;
+        use std::sync::Mutex;
+
+        let commands = (0..4)
+            .map(|i| {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c")
+                    .arg(format!("echo {i}; echo error >&2; exit {i}"));
+                (format!("host{i}"), cmd)
+            })
+            .collect();
+        let (stdout, stderr) = (Mutex::new(vec![]), Mutex::new(vec![]));
+        let results = super::run_all(commands, 2, &stdout, &stderr);
+        let codes: Vec<(&str, i32)> = results
+            .iter()
+            .map(|(n, r)| (n.as_str(), *r.as_ref().unwrap()))
+            .collect();
+        assert_eq!(
+            codes,
+            [("host0", 0), ("host1", 1), ("host2", 2), ("host3", 3)]
+        );
+        let stdout = String::from_utf8(stdout.into_inner().unwrap()).unwrap();
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        lines.sort();
+        assert_eq!(lines, ["host0 | 0", "host1 | 1", "host2 | 2", "host3 | 3"]);
+        let stderr = String::from_utf8(stderr.into_inner().unwrap()).unwrap();
+        assert_eq!(stderr.matches(" | error\n").count(), 4);
+    }
+}
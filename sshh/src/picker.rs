@@ -0,0 +1,163 @@
+use std::io::{stdout, Write};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+
+use crate::Entry;
+
+/// Lets the user pick an entry on the terminal, filtering them by typing. Returns the index of
+/// the entry, or `None` if cancelled.
+pub fn pick_entry(entries: &[Entry]) -> Result<Option<usize>, String> {
+    if entries.is_empty() {
+        return Err("no entries; add some with --edit".to_string());
+    }
+    let _terminal = RawTerminal::enter().map_err(|e| format!("failed to set up terminal; {e}"))?;
+    run(entries).map_err(|e| format!("terminal error; {e}"))
+}
+
+/// The indices of the entries matching `query`: those containing it first, then those containing
+/// its characters in order, both case-insensitive and in the order of the entries.
+pub fn filter_entries(entries: &[Entry], query: &str) -> Vec<usize> {
+    let query = query.to_lowercase();
+    let texts: Vec<String> = entries
+        .iter()
+        .map(|e| e.to_string().to_lowercase())
+        .collect();
+    let mut matches: Vec<usize> = (0..entries.len())
+        .filter(|&i| texts[i].contains(&query))
+        .collect();
+    for (i, text) in texts.iter().enumerate() {
+        if !matches.contains(&i) && is_subsequence(&query, text) {
+            matches.push(i);
+        }
+    }
+    matches
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut chars = haystack.chars();
+    needle.chars().all(|c| chars.any(|h| h == c))
+}
+
+// raw mode on the alternate screen, until dropped
+struct RawTerminal;
+impl RawTerminal {
+    fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), terminal::EnterAlternateScreen)?;
+        Ok(Self)
+    }
+}
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn run(entries: &[Entry]) -> std::io::Result<Option<usize>> {
+    let mut query = String::new();
+    let mut matches = filter_entries(entries, &query);
+    let mut selected = 0;
+    let mut offset = 0; // the first match shown
+    loop {
+        let (width, height) = match terminal::size()? {
+            (0, _) | (_, 0) => (80, 24), // not told by some terminals
+            size => size,
+        };
+        let rows = (height as usize).saturating_sub(1).max(1);
+        if selected < offset {
+            offset = selected;
+        } else if selected >= offset + rows {
+            offset = selected + 1 - rows;
+        }
+        draw(
+            entries,
+            &query,
+            &matches,
+            selected,
+            offset,
+            width as usize,
+            rows,
+        )?;
+
+        let key = match event::read()? {
+            Event::Key(k) if k.kind != KeyEventKind::Release => k,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if ctrl => return Ok(None),
+            KeyCode::Enter => return Ok(matches.get(selected).copied()),
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') if ctrl => selected = selected.saturating_sub(1),
+            KeyCode::Down if selected + 1 < matches.len() => selected += 1,
+            KeyCode::Char('n') if ctrl && selected + 1 < matches.len() => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+                matches = filter_entries(entries, &query);
+                selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                query.push(c);
+                matches = filter_entries(entries, &query);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(
+    entries: &[Entry],
+    query: &str,
+    matches: &[usize],
+    selected: usize,
+    offset: usize,
+    width: usize,
+    rows: usize,
+) -> std::io::Result<()> {
+    let mut out = stdout();
+    queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+    queue!(out, Print(format!("> {query}")))?;
+    for (row, &i) in matches.iter().enumerate().skip(offset).take(rows) {
+        let line: String = format!("[{i}] {}", entries[i])
+            .chars()
+            .take(width)
+            .collect();
+        queue!(out, cursor::MoveTo(0, (row - offset + 1) as u16))?;
+        if row == selected {
+            queue!(
+                out,
+                SetAttribute(Attribute::Reverse),
+                Print(line),
+                SetAttribute(Attribute::Reset)
+            )?;
+        } else {
+            queue!(out, Print(line))?;
+        }
+    }
+    let column = 2 + query.chars().count();
+    queue!(out, cursor::MoveTo(column.min(width) as u16, 0))?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::filter_entries;
+    use crate::parse_entries;
+
+    #[test]
+    fn test_filter_entries() {
+        let entries = parse_entries("web-1 the frontend\nwb.local\ndb.internal backend\n").unwrap();
+        assert_eq!(filter_entries(&entries, ""), [0, 1, 2]);
+        assert_eq!(filter_entries(&entries, "END"), [0, 2]);
+        // substring matches come before the scattered ones
+        assert_eq!(filter_entries(&entries, "wb"), [1, 0]);
+        assert_eq!(filter_entries(&entries, "dbi"), [2]);
+        assert!(filter_entries(&entries, "mail").is_empty());
+    }
+}
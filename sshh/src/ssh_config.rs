@@ -0,0 +1,355 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Entry;
+
+// as ssh itself limits the nesting of Include
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// a Host block and its first HostName
+#[derive(Default)]
+struct Block {
+    patterns: Vec<String>,
+    host_name: Option<String>,
+}
+
+/// Parses an ssh client config file into an entry per host alias of its `Host` lines, following
+/// `Include` directives relative to `ssh_dir`. Patterns with wildcards or negation aren't hosts
+/// to connect to and give no entry. The entries connect by the alias, so ssh applies all of its
+/// config to them; the `HostName` which applies, if sshh can expand it, becomes the description.
+/// Returns the entries, and warnings about the all-digit aliases skipped.
+pub fn parse_ssh_config(path: &Path, ssh_dir: &Path) -> Result<(Vec<Entry>, Vec<String>), String> {
+    let mut blocks = vec![Block::default()]; // options before the first Host apply to all
+    blocks[0].patterns.push("*".to_string());
+    let mut warnings = vec![];
+    read_config(path, ssh_dir, &mut blocks, 0)?;
+
+    let mut entries: Vec<Entry> = vec![];
+    for block in &blocks {
+        for alias in &block.patterns {
+            if alias.contains(['*', '?', '!']) || entries.iter().any(|e| e.name() == alias) {
+                continue;
+            }
+            if alias.bytes().all(|b| b.is_ascii_digit()) {
+                warnings.push(format!(
+                    "skipped host '{alias}', which would be taken for an index"
+                ));
+                continue;
+            }
+            // the first value wins, as with ssh
+            let host_name = blocks
+                .iter()
+                .filter(|b| matches_host(&b.patterns, alias))
+                .find_map(|b| b.host_name.as_deref());
+            entries.push(Entry {
+                alias: Some(alias.clone()),
+                host: alias.clone(),
+                description: host_name.and_then(|h| expand_host_name(h, alias)),
+                ..Default::default()
+            });
+        }
+    }
+    Ok((entries, warnings))
+}
+
+// with the tokens expanded which need nothing but the alias; None for the others
+fn expand_host_name(host_name: &str, alias: &str) -> Option<String> {
+    let mut expanded = String::new();
+    let mut chars = host_name.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(alias),
+            Some('%') => expanded.push('%'),
+            _ => return None,
+        }
+    }
+    Some(expanded)
+}
+
+fn read_config(
+    path: &Path,
+    ssh_dir: &Path,
+    blocks: &mut Vec<Block>,
+    depth: usize,
+) -> Result<(), String> {
+    let name = path.to_string_lossy();
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("too deeply nested Include of '{name}'"));
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("failed to read '{name}'; {e}"))?;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // "Keyword value", "Keyword=value" or "Keyword = value"
+        let (keyword, value) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+            Some(i) => (&line[..i], line[i..].trim_start().trim_start_matches('=')),
+            None => (line, ""),
+        };
+        let args = split_args(value.trim());
+        let first = args.first().cloned();
+        let block = blocks.last_mut().unwrap();
+        match keyword.to_lowercase().as_str() {
+            "host" => blocks.push(Block {
+                patterns: args,
+                ..Default::default()
+            }),
+            "match" => blocks.push(Block::default()), // not evaluated, so matching nothing
+            "include" => {
+                for arg in &args {
+                    for included in expand_include(arg, ssh_dir)? {
+                        read_config(&included, ssh_dir, blocks, depth + 1)?;
+                    }
+                }
+            }
+            "hostname" => block.host_name = block.host_name.take().or(first),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parses an ssh known_hosts file into an entry per line, for the first host of it (the others
+/// are usually its addresses). Hashed hosts, patterns, and the lines of certificate authorities
+/// and revoked keys are skipped.
+pub fn parse_known_hosts(path: &Path) -> Result<Vec<Entry>, String> {
+    let name = path.to_string_lossy();
+    let text = fs::read_to_string(path).map_err(|e| format!("failed to read '{name}'; {e}"))?;
+    let mut entries: Vec<Entry> = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', '@']) {
+            continue;
+        }
+        // "host1,host2 keytype key", with "[host]:port" for other ports than 22
+        let host = line.split([',', ' ', '\t']).next().unwrap();
+        if host.starts_with('|') || host.contains(['*', '?', '!']) {
+            continue;
+        }
+        let entry = match host.strip_prefix('[').and_then(|h| h.split_once("]:")) {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => Entry {
+                    host: host.to_string(),
+                    port: Some(port),
+                    ..Default::default()
+                },
+                Err(_) => continue,
+            },
+            None => Entry {
+                host: host.to_string(),
+                ..Default::default()
+            },
+        };
+        if !entries
+            .iter()
+            .any(|e| e.destination() == entry.destination())
+        {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+// whitespace separated, with double quotes around arguments holding spaces
+fn split_args(value: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+// the files an Include argument names, in lexical order as ssh reads them
+fn expand_include(arg: &str, ssh_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let path = match arg.strip_prefix("~/") {
+        Some(rest) => ssh_dir.parent().unwrap_or(ssh_dir).join(rest),
+        None => ssh_dir.join(arg), // an absolute path replaces ssh_dir
+    };
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    if !file_name.contains(['*', '?']) {
+        return Ok(if path.is_file() { vec![path] } else { vec![] });
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let read_dir = match fs::read_dir(dir) {
+        Ok(r) => r,
+        Err(_) => return Ok(vec![]),
+    };
+    let mut paths: Vec<PathBuf> = read_dir
+        .filter_map(|e| e.ok())
+        .filter(|e| wildcard_match(&file_name, &e.file_name().to_string_lossy()))
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+// a host matches if any pattern does and no negated one
+fn matches_host(patterns: &[String], host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(p) if wildcard_match(p, host) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(pattern, host),
+        }
+    }
+    matched
+}
+
+// '*' matches any run of characters and '?' a single one
+fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    let mut star = None; // the last '*' and where its match ended
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((star_pi, star_si)) = star {
+            pi = star_pi + 1;
+            si = star_si + 1;
+            star = Some((star_pi, star_si + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{parse_known_hosts, parse_ssh_config, wildcard_match};
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("web*", "web1"));
+        assert!(wildcard_match("*.example.com", "a.example.com"));
+        assert!(wildcard_match("w?b*1", "web-01"));
+        assert!(!wildcard_match("web?", "web"));
+        assert!(!wildcard_match("*.com", "example.org"));
+    }
+
+    #[test]
+    fn test_parse_ssh_config() {
+        let dir = std::env::temp_dir().join(format!("sshh-test-{}", std::process::id()));
+        let ssh_dir = dir.join(".ssh");
+        fs::create_dir_all(ssh_dir.join("config.d")).unwrap();
+        let config = r#"
+# comment
+Host web1 web2
+    HostName 10.0.0.1
+    Port 2222
+
+Host *.internal !secret.internal
+    User admin
+    IdentityFile ~/.ssh/id_internal
+
+Include config.d/*.conf
+
+Host *.example.com
+    HostName %h.example.com
+
+Host proxied
+    HostName %n.lan
+
+Host broken
+    Port ssh
+
+Host www.example.com
+
+Host 42
+
+Host *
+    User nobody
+"#;
+        fs::write(ssh_dir.join("config"), config).unwrap();
+        let included = "Host db.internal secret.internal\n  ProxyJump=bastion\n";
+        fs::write(ssh_dir.join("config.d/db.conf"), included).unwrap();
+
+        let (entries, warnings) = parse_ssh_config(&ssh_dir.join("config"), &ssh_dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "web1",
+                "web2",
+                "db.internal",
+                "secret.internal",
+                "proxied",
+                "broken",
+                "www.example.com"
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("'42'"));
+        // ssh applies its config, the options sshh doesn't read too
+        for entry in &entries {
+            assert_eq!(entry.host, entry.name());
+            assert!(entry.ssh_options().is_empty() && entry.port.is_none());
+        }
+        assert_eq!(entries[1].description.as_deref(), Some("10.0.0.1"));
+        assert_eq!(entries[2].description, None);
+        assert_eq!(entries[4].description, None);
+        assert_eq!(
+            entries[6].description.as_deref(),
+            Some("www.example.com.example.com")
+        );
+    }
+
+    #[test]
+    fn test_parse_known_hosts() {
+        let path = std::env::temp_dir().join(format!("sshh-known-hosts-{}", std::process::id()));
+        let text = "\
+web1.example.com,10.0.0.1 ssh-ed25519 AAAAC3Nza
+10.0.0.1 ecdsa-sha2-nistp256 AAAAE2Vj
+# comment
+[git.example.com]:2222 ssh-ed25519 AAAAC3Nza
+[fd00::2]:22 ssh-ed25519 AAAAC3Nza
+|1|F1E1KeoE/eEWhi10WpGv4OdiO6Y=|3988QV0VE8wmZL7suNrYQLITLCg= ssh-rsa AAAAB3Nza
+*.example.org ssh-rsa AAAAB3Nza
+@cert-authority *.example.com ssh-rsa AAAAB3Nza
+@revoked web2.example.com ssh-rsa AAAAB3Nza
+web1.example.com ssh-rsa AAAAB3Nza
+";
+        fs::write(&path, text).unwrap();
+        let entries = parse_known_hosts(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let destinations: Vec<String> = entries.iter().map(|e| e.destination()).collect();
+        assert_eq!(
+            destinations,
+            [
+                "web1.example.com",
+                "10.0.0.1",
+                "git.example.com:2222",
+                "[fd00::2]:22"
+            ]
+        );
+    }
+}
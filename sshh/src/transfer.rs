@@ -0,0 +1,405 @@
+use crate::Entry;
+
+/// A path argument of `scph`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PathArg<'a> {
+    Local(String),
+    /// A path on the host of an entry.
+    Remote(&'a Entry, String),
+}
+
+/// A path argument of `scph` as written, before entry references are resolved.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PathSpec {
+    Local(String),
+    /// `TARGET:PATH`, the target as `resolve_target` takes it, or empty to pick one.
+    Target(String, String),
+    /// `user@host:PATH` or `[ipv6]:PATH`, a host not in the entry file.
+    Raw(Entry, String),
+}
+
+/// Parses a path argument the way `scp` does: local unless there's a colon before any slash,
+/// or if it starts with a drive letter like `C:\` (or `C:/` and `C:` on Windows).
+pub fn parse_path_arg(arg: &str) -> Result<PathSpec, String> {
+    let invalid = || format!("invalid path argument '{arg}'");
+    let bytes = arg.as_bytes();
+    let drive_letter = bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && match bytes.get(2) {
+            Some(b'\\') => true,
+            None | Some(b'/') => cfg!(windows),
+            _ => false,
+        };
+    if drive_letter {
+        return Ok(PathSpec::Local(arg.to_string()));
+    }
+
+    // the colon after the host, skipping one of an IPv6 address in brackets
+    let (user, rest) = match arg.split_once('@') {
+        Some((u, rest)) if !u.contains(['/', '\\', ':']) => (Some(u), rest),
+        _ => (None, arg),
+    };
+    let offset = arg.len() - rest.len();
+    let colon = if rest.starts_with('[') {
+        match rest.find(']') {
+            Some(i) if rest[i + 1..].starts_with(':') => Some(i + 1),
+            _ => None,
+        }
+    } else {
+        rest.find(':').filter(|&i| !rest[..i].contains(['/', '\\']))
+    };
+    let colon = match colon {
+        Some(i) => offset + i,
+        None => return Ok(PathSpec::Local(arg.to_string())),
+    };
+    let (prefix, path) = (&arg[..colon], arg[colon + 1..].to_string());
+
+    if user.is_none() && !prefix.starts_with('[') {
+        return Ok(PathSpec::Target(prefix.to_string(), path));
+    }
+    let host = &arg[offset..colon];
+    let host = match host.strip_prefix('[') {
+        Some(h) => h.strip_suffix(']').ok_or_else(invalid)?,
+        None => host,
+    };
+    if host.is_empty() || user == Some("") {
+        return Err(invalid());
+    }
+    let entry = Entry {
+        host: host.to_string(),
+        user: user.map(str::to_string),
+        ..Default::default()
+    };
+    Ok(PathSpec::Raw(entry, path))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Scp,
+    Rsync,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransferOptions {
+    pub recursive: bool,
+    /// Keep modification times and modes.
+    pub preserve: bool,
+    pub compress: bool,
+    /// In KiB/s.
+    pub bandwidth_limit: Option<u32>,
+}
+
+/// The program and arguments copying `paths`, the last being the destination.
+pub fn transfer_command(
+    backend: Backend,
+    paths: &[PathArg],
+    options: &TransferOptions,
+) -> Result<(String, Vec<String>), String> {
+    match backend {
+        Backend::Scp => Ok(("scp".to_string(), scp_args(paths, options)?)),
+        Backend::Rsync => Ok(("rsync".to_string(), rsync_args(paths, options)?)),
+    }
+}
+
+fn scp_args(paths: &[PathArg], options: &TransferOptions) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    if options.recursive {
+        args.push("-r".to_string());
+    }
+    if options.preserve {
+        args.push("-p".to_string());
+    }
+    if options.compress {
+        args.push("-C".to_string());
+    }
+    if let Some(limit) = options.bandwidth_limit {
+        args.push("-l".to_string());
+        args.push((limit as u64 * 8).to_string()); // Kbit/s
+    }
+    // scp takes a single identity file and jump host for all remotes
+    let mut remote: Option<&Entry> = None;
+    for path in paths {
+        if let PathArg::Remote(entry, _) = path {
+            match remote {
+                Some(r) if r.ssh_options() != entry.ssh_options() => {
+                    let msg = format!(
+                        "scp takes one identity file and jump host; '{}' and '{}' differ",
+                        r.name(),
+                        entry.name()
+                    );
+                    return Err(msg);
+                }
+                Some(_) => {}
+                None => {
+                    args.extend(entry.ssh_options());
+                    remote = Some(entry);
+                }
+            }
+        }
+    }
+    for path in paths {
+        args.push(match path {
+            PathArg::Local(p) => p.clone(),
+            PathArg::Remote(entry, p) => format!("{}/{}", entry.uri("scp"), percent_encode(p)),
+        });
+    }
+    Ok(args)
+}
+
+fn rsync_args(paths: &[PathArg], options: &TransferOptions) -> Result<Vec<String>, String> {
+    // an interrupted transfer is resumed by running it again
+    let mut args = vec!["--partial".to_string()];
+    if options.recursive {
+        args.push("-r".to_string());
+    }
+    if options.preserve {
+        args.push("-tp".to_string());
+    }
+    if options.compress {
+        args.push("-z".to_string());
+    }
+    if let Some(limit) = options.bandwidth_limit {
+        args.push(format!("--bwlimit={limit}"));
+    }
+
+    let mut remote: Option<&Entry> = None;
+    for path in paths {
+        if let PathArg::Remote(entry, _) = path {
+            if remote.is_some_and(|r| r != *entry) {
+                return Err("rsync copies from or to a single remote host".to_string());
+            }
+            remote = Some(entry);
+        }
+    }
+    if let Some(entry) = remote {
+        let mut ssh = vec!["ssh".to_string()];
+        if let Some(port) = entry.port {
+            ssh.push("-p".to_string());
+            ssh.push(port.to_string());
+        }
+        ssh.extend(entry.ssh_options());
+        if ssh.len() > 1 {
+            args.push("-e".to_string());
+            args.push(ssh.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" "));
+        }
+    }
+
+    for path in paths {
+        args.push(match path {
+            PathArg::Local(p) => p.clone(),
+            PathArg::Remote(entry, p) => {
+                let host = Entry {
+                    port: None, // given to ssh
+                    ..(*entry).clone()
+                };
+                format!("{}:{p}", host.destination())
+            }
+        });
+    }
+    Ok(args)
+}
+
+// scp decodes the path of a URI, '+' as a space too
+fn percent_encode(path: &str) -> String {
+    let mut s = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                s.push(b as char)
+            }
+            _ => s.push_str(&format!("%{b:02X}")),
+        }
+    }
+    s
+}
+
+// rsync splits the -e command at spaces, except within quotes
+fn quote(arg: &str) -> String {
+    match arg.contains(' ') {
+        true => format!("\"{arg}\""),
+        false => arg.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_path_arg, transfer_command, Backend, PathArg, PathSpec, TransferOptions};
+    use crate::{parse_entries, Entry};
+
+    #[test]
+    fn test_parse_path_arg() {
+        let local = |p: &str| PathSpec::Local(p.to_string());
+        let target = |t: &str, p: &str| PathSpec::Target(t.to_string(), p.to_string());
+        let raw = |user: Option<&str>, host: &str, p: &str| {
+            let entry = Entry {
+                host: host.to_string(),
+                user: user.map(str::to_string),
+                ..Default::default()
+            };
+            PathSpec::Raw(entry, p.to_string())
+        };
+        let cases = [
+            ("file.txt", local("file.txt")),
+            ("./x:y", local("./x:y")),
+            ("dir/a@b:c", local("dir/a@b:c")),
+            ("C:\\Users\\me\\file", local("C:\\Users\\me\\file")),
+            ("[fd00::1]", local("[fd00::1]")),
+            ("user@host", local("user@host")),
+            ("web1:/var/log", target("web1", "/var/log")),
+            ("0:", target("0", "")),
+            (":notes.txt", target("", "notes.txt")),
+            ("web1:a:b", target("web1", "a:b")),
+            ("root@10.0.0.1:/tmp", raw(Some("root"), "10.0.0.1", "/tmp")),
+            ("root@[fd00::1]:/tmp", raw(Some("root"), "fd00::1", "/tmp")),
+            ("[fd00::1]:x@y", raw(None, "fd00::1", "x@y")),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(parse_path_arg(arg), Ok(expected), "{arg}");
+        }
+        let drive = parse_path_arg("C:/file").unwrap();
+        assert_eq!(drive == local("C:/file"), cfg!(windows));
+
+        for invalid in ["@host:x", "root@:x", "root@[]:x"] {
+            assert!(parse_path_arg(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_transfer_command() {
+        let text = r#"
+[[entry]]
+alias = "web1"
+host = "10.0.0.1"
+user = "root"
+port = 2222
+identity_file = "~/my keys/id_web"
+
+[[entry]]
+alias = "db"
+host = "fd00::2"
+
+[[entry]]
+alias = "backup"
+host = "backup.local"
+jump_host = "bastion"
+"#;
+        let entries = parse_entries(text).unwrap();
+        let web1 = || PathArg::Remote(&entries[0], "/var/log".to_string());
+        let db = || PathArg::Remote(&entries[1], "dump.sql".to_string());
+        let local = || PathArg::Local("logs".to_string());
+        let options = TransferOptions {
+            recursive: true,
+            preserve: true,
+            compress: true,
+            bandwidth_limit: Some(1000),
+        };
+
+        let cases: [(Backend, Vec<PathArg>, &TransferOptions, &[&str]); 7] = [
+            (
+                Backend::Scp,
+                vec![
+                    local(),
+                    PathArg::Remote(&entries[1], "my file+1%.txt".to_string()),
+                ],
+                &TransferOptions::default(),
+                &["logs", "scp://[fd00::2]/my%20file%2B1%25.txt"],
+            ),
+            (
+                Backend::Scp,
+                vec![web1(), local()],
+                &TransferOptions::default(),
+                &[
+                    "-i",
+                    "~/my keys/id_web",
+                    "scp://root@10.0.0.1:2222//var/log",
+                    "logs",
+                ],
+            ),
+            (
+                Backend::Scp,
+                vec![db(), PathArg::Remote(&entries[1], "x".to_string()), local()],
+                &options,
+                &[
+                    "-r",
+                    "-p",
+                    "-C",
+                    "-l",
+                    "8000",
+                    "scp://[fd00::2]/dump.sql",
+                    "scp://[fd00::2]/x",
+                    "logs",
+                ],
+            ),
+            (
+                Backend::Rsync,
+                vec![web1(), local()],
+                &TransferOptions::default(),
+                &[
+                    "--partial",
+                    "-e",
+                    "ssh -p 2222 -i \"~/my keys/id_web\"",
+                    "root@10.0.0.1:/var/log",
+                    "logs",
+                ],
+            ),
+            (
+                Backend::Rsync,
+                vec![local(), db()],
+                &options,
+                &[
+                    "--partial",
+                    "-r",
+                    "-tp",
+                    "-z",
+                    "--bwlimit=1000",
+                    "logs",
+                    "[fd00::2]:dump.sql",
+                ],
+            ),
+            (
+                Backend::Rsync,
+                vec![local(), PathArg::Local("copy".to_string())],
+                &TransferOptions::default(),
+                &["--partial", "logs", "copy"],
+            ),
+            (
+                Backend::Rsync,
+                vec![PathArg::Remote(&entries[2], "x".to_string()), local()],
+                &TransferOptions::default(),
+                &[
+                    "--partial",
+                    "-e",
+                    "ssh -J bastion",
+                    "backup.local:x",
+                    "logs",
+                ],
+            ),
+        ];
+        for (backend, paths, options, expected) in cases {
+            let (program, args) = transfer_command(backend, &paths, options).unwrap();
+            assert_eq!(
+                program,
+                if backend == Backend::Scp {
+                    "scp"
+                } else {
+                    "rsync"
+                }
+            );
+            assert_eq!(args, expected);
+        }
+
+        let two_hosts = transfer_command(Backend::Rsync, &[web1(), db()], &options);
+        assert!(two_hosts.is_err());
+        // which identity file or jump host would be used for all of them
+        let backup = || PathArg::Remote(&entries[2], "x".to_string());
+        for paths in [
+            vec![web1(), db()],
+            vec![web1(), backup(), db()],
+            vec![backup(), db()],
+        ] {
+            assert!(transfer_command(Backend::Scp, &paths, &options).is_err());
+        }
+    }
+}
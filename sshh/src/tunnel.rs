@@ -0,0 +1,213 @@
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Entry;
+
+/// A named set of port forwardings of an entry, as `ssh -L`, `-R` and `-D` take them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tunnel {
+    /// `[bind_address:]port:host:hostport`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local: Vec<String>,
+    /// `[bind_address:]port:host:hostport`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remote: Vec<String>,
+    /// `[bind_address:]port`, a SOCKS proxy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dynamic: Vec<String>,
+}
+
+impl Tunnel {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.local.is_empty() && self.remote.is_empty() && self.dynamic.is_empty() {
+            return Err("no forwarding".to_string());
+        }
+        for (kind, specs) in [("local", &self.local), ("remote", &self.remote)] {
+            for spec in specs {
+                let parts = split_spec(spec);
+                let ports = match parts.len() {
+                    3 => [parts[0], parts[2]],
+                    4 => [parts[1], parts[3]],
+                    _ => [""; 2],
+                };
+                if ports.iter().any(|p| p.parse::<u16>().is_err())
+                    || parts[parts.len() - 2].is_empty()
+                {
+                    return Err(format!("invalid {kind} forwarding '{spec}'"));
+                }
+            }
+        }
+        for spec in &self.dynamic {
+            let parts = split_spec(spec);
+            if parts.len() > 2 || parts[parts.len() - 1].parse::<u16>().is_err() {
+                return Err(format!("invalid dynamic forwarding '{spec}'"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The `-L`, `-R` and `-D` options of the forwardings.
+    pub fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        for (option, specs) in [
+            ("-L", &self.local),
+            ("-R", &self.remote),
+            ("-D", &self.dynamic),
+        ] {
+            for spec in specs {
+                args.push(option.to_string());
+                args.push(spec.clone());
+            }
+        }
+        args
+    }
+
+    /// Whether something listens on all the local ports of the forwardings, as with the tunnel up;
+    /// `None` if it has remote forwardings only.
+    pub fn is_active(&self, timeout: Duration) -> Option<bool> {
+        let listen_addrs: Vec<(String, u16)> = self
+            .local
+            .iter()
+            .chain(&self.dynamic)
+            .filter_map(|spec| listen_addr(spec))
+            .collect();
+        if listen_addrs.is_empty() {
+            return None;
+        }
+        Some(listen_addrs.iter().all(|(host, port)| {
+            let addrs = (host.as_str(), *port).to_socket_addrs();
+            addrs
+                .into_iter()
+                .flatten()
+                .any(|addr| TcpStream::connect_timeout(&addr, timeout).is_ok())
+        }))
+    }
+}
+
+// split at the colons outside brackets, as in "[::1]:8080:localhost:80"
+fn split_spec(spec: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ':' if depth == 0 => {
+                parts.push(&spec[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&spec[start..]);
+    parts
+}
+
+// where the forwarding of a local or dynamic spec listens
+fn listen_addr(spec: &str) -> Option<(String, u16)> {
+    let parts = split_spec(spec);
+    let (bind, port) = match parts.len() {
+        1 | 3 => ("", parts[0]),
+        _ => (parts[0], parts[1]),
+    };
+    let host = match bind.trim_start_matches('[').trim_end_matches(']') {
+        // all addresses, or localhost by default
+        "" | "*" | "localhost" => "127.0.0.1",
+        b => b,
+    };
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+/// The arguments of `ssh` to bring up the tunnel `profile` of `entry`, without a remote command.
+pub fn tunnel_args(entry: &Entry, profile: &str) -> Result<Vec<String>, String> {
+    let tunnel = match entry.tunnels.get(profile) {
+        Some(t) => t,
+        None => {
+            let name = entry.name();
+            return Err(format!("entry '{name}' has no tunnel profile '{profile}'"));
+        }
+    };
+    let mut args = vec!["-N".to_string(), "-o".to_string()];
+    args.push("ExitOnForwardFailure=yes".to_string());
+    args.extend(entry.ssh_options());
+    args.extend(tunnel.ssh_args());
+    args.push(entry.uri("ssh"));
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use super::{tunnel_args, Tunnel};
+    use crate::parse_entries;
+
+    #[test]
+    fn test_tunnel() {
+        let text = r#"
+[[entry]]
+alias = "db"
+host = "10.0.0.2"
+jump_host = "bastion"
+
+[entry.tunnels.pg]
+local = ["5432:localhost:5432", "127.0.0.1:6543:[fd00::1]:5432"]
+
+[entry.tunnels.socks]
+dynamic = ["1080"]
+remote = ["8080:localhost:80"]
+"#;
+        let entries = parse_entries(text).unwrap();
+        let args = tunnel_args(&entries[0], "pg").unwrap();
+        let expected = [
+            "-N",
+            "-o",
+            "ExitOnForwardFailure=yes",
+            "-J",
+            "bastion",
+            "-L",
+            "5432:localhost:5432",
+            "-L",
+            "127.0.0.1:6543:[fd00::1]:5432",
+            "ssh://10.0.0.2",
+        ];
+        assert_eq!(args, expected);
+        let args = tunnel_args(&entries[0], "socks").unwrap();
+        assert_eq!(args[5..9], ["-R", "8080:localhost:80", "-D", "1080"]);
+        assert!(tunnel_args(&entries[0], "web").is_err());
+
+        for invalid in [
+            "local = [\"5432\"]",
+            "local = [\"x:h:1\"]",
+            "dynamic = [\"a:b:1\"]",
+            "",
+        ] {
+            let text = format!("[[entry]]\nhost = \"h\"\n[entry.tunnels.t]\n{invalid}\n");
+            assert!(parse_entries(&text).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_is_active() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_secs(3);
+        let tunnel = Tunnel {
+            dynamic: vec![port.to_string()],
+            ..Default::default()
+        };
+        assert_eq!(tunnel.is_active(timeout), Some(true));
+        drop(listener);
+        assert_eq!(tunnel.is_active(timeout), Some(false));
+        let tunnel = Tunnel {
+            remote: vec!["8080:localhost:80".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(tunnel.is_active(timeout), None);
+    }
+}